@@ -0,0 +1,494 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    instruction_parsing::instruction::Instruction,
+    lua_file::LuaFile,
+};
+
+use super::{block_leaders, decode, is_env_upvalue, writes_register, ENV_UPVALUE_NAME};
+
+/// How dangerous a finding is
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Severity::Info => "INFO",
+            Severity::Low => "LOW",
+            Severity::Medium => "MEDIUM",
+            Severity::High => "HIGH",
+            Severity::Critical => "CRITICAL",
+        })
+    }
+}
+
+/// A risky pattern found in a function block
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Short identifier of the pattern, e.g. `os.execute` or `deep-nesting`
+    pub rule: String,
+    pub message: String,
+    pub path: ProtoPath,
+    pub pc: Option<usize>,
+    pub line: Option<u64>,
+}
+
+/// Thresholds for the suspicious shapes the audit looks for
+#[derive(Debug, PartialEq, Clone)]
+pub struct AuditOptions {
+    /// Largest table constructor size that is not reported
+    pub max_table_size: usize,
+    /// Largest constant pool of a single function block that is not reported
+    pub max_constants: usize,
+    /// Deepest function block nesting that is not reported
+    pub max_nesting_depth: usize,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        AuditOptions {
+            max_table_size: 4096,
+            max_constants: 4096,
+            max_nesting_depth: 8,
+        }
+    }
+}
+
+/// Globals and library functions that are risky to give untrusted code
+const RISKY_NAMES: &[(&str, Severity, &str)] = &[
+    ("os.execute", Severity::Critical, "runs shell commands"),
+    ("io.popen", Severity::Critical, "runs shell commands"),
+    ("load", Severity::High, "compiles and runs code at runtime"),
+    (
+        "loadstring",
+        Severity::High,
+        "compiles and runs code at runtime",
+    ),
+    ("dofile", Severity::High, "runs code from a file"),
+    ("loadfile", Severity::High, "loads code from a file"),
+    ("package.loadlib", Severity::High, "loads native libraries"),
+    (
+        "debug",
+        Severity::High,
+        "the debug library can break out of sandboxes",
+    ),
+    (
+        "string.dump",
+        Severity::Medium,
+        "dumps the bytecode of functions",
+    ),
+    ("os.remove", Severity::Medium, "deletes files"),
+    ("os.rename", Severity::Medium, "renames files"),
+    ("os.exit", Severity::Medium, "exits the host process"),
+    ("io.open", Severity::Medium, "opens files"),
+    ("require", Severity::Low, "loads modules"),
+    (
+        "collectgarbage",
+        Severity::Low,
+        "controls the garbage collector",
+    ),
+];
+
+const GLOBAL_TABLE_NAME: &str = "_G";
+
+/// A register holding the value of a global for a whole range of pcs
+struct StableName {
+    register: u8,
+    start_pc: usize,
+    end_pc: usize,
+    name: String,
+}
+
+/// Security audit of a compiled file
+#[derive(Debug, PartialEq, Default)]
+pub struct AuditReport {
+    pub findings: Vec<Finding>,
+}
+
+impl AuditReport {
+    /// Audits a file with the default thresholds
+    pub fn build(file: &LuaFile) -> Self {
+        Self::build_with_options(file, &AuditOptions::default())
+    }
+
+    /// Audits a file, flagging risky library accesses, `_ENV` tampering and
+    /// suspiciously shaped code
+    pub fn build_with_options(file: &LuaFile, options: &AuditOptions) -> Self {
+        let mut auditor = Auditor {
+            file,
+            options,
+            stable_names: HashMap::new(),
+            closure_sites: HashMap::new(),
+            findings: Vec::new(),
+        };
+        for (path, function) in file.functions() {
+            auditor.audit_function(&path, function);
+        }
+        AuditReport {
+            findings: auditor.findings,
+        }
+    }
+
+    /// Severity of the worst finding
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|finding| finding.severity).max()
+    }
+}
+
+impl Display for AuditReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for finding in &self.findings {
+            write!(f, "[{}] {}", finding.severity, finding.path)?;
+            if let Some(pc) = finding.pc {
+                write!(f, " pc {}", pc)?;
+            }
+            if let Some(line) = finding.line {
+                write!(f, " line {}", line)?;
+            }
+            writeln!(f, ": {}: {}", finding.rule, finding.message)?;
+        }
+        Ok(())
+    }
+}
+
+struct Auditor<'a> {
+    file: &'a LuaFile,
+    options: &'a AuditOptions,
+    stable_names: HashMap<ProtoPath, Vec<StableName>>,
+    closure_sites: HashMap<ProtoPath, usize>,
+    findings: Vec<Finding>,
+}
+
+impl Auditor<'_> {
+    fn report(
+        &mut self,
+        path: &ProtoPath,
+        function: &FunctionBlockChunk,
+        pc: Option<usize>,
+        severity: Severity,
+        rule: &str,
+        message: String,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            rule: rule.to_string(),
+            message,
+            path: path.clone(),
+            pc,
+            line: pc.and_then(|pc| function.line_at(pc)),
+        });
+    }
+
+    /// Name of the global held by a register at `pc`, outside of the current block
+    fn stable_name(&self, path: &ProtoPath, register: u8, pc: usize) -> Option<String> {
+        self.stable_names.get(path)?.iter().find_map(|stable| {
+            (stable.register == register && stable.start_pc <= pc && pc < stable.end_pc)
+                .then(|| stable.name.clone())
+        })
+    }
+
+    /// Name of the global held by an upvalue of the function block at `path`
+    fn upvalue_name(&self, path: &ProtoPath, index: u8) -> Option<String> {
+        if is_env_upvalue(self.file, path, index) {
+            return Some(ENV_UPVALUE_NAME.to_string());
+        }
+        let function = self.file.function_at(path)?;
+        let upvalue = function.upvalues.get(index as usize)?;
+        let parent = path.parent()?;
+        if upvalue.in_stack {
+            let closure_pc = *self.closure_sites.get(path)?;
+            self.stable_name(&parent, upvalue.index, closure_pc)
+        } else {
+            self.upvalue_name(&parent, upvalue.index)
+        }
+    }
+
+    fn audit_function(&mut self, path: &ProtoPath, function: &FunctionBlockChunk) {
+        if path.depth() > self.options.max_nesting_depth {
+            self.report(
+                path,
+                function,
+                None,
+                Severity::Low,
+                "deep-nesting",
+                format!("function is nested {} levels deep", path.depth()),
+            );
+        }
+        if function.constants.len() > self.options.max_constants {
+            self.report(
+                path,
+                function,
+                None,
+                Severity::Low,
+                "large-constant-pool",
+                format!("function has {} constants", function.constants.len()),
+            );
+        }
+
+        let instructions: Vec<_> = decode(function).collect();
+        let leaders = block_leaders(function);
+        let local_registers = function.debug_info.local_registers();
+        let mut known: HashMap<u8, String> = HashMap::new();
+        self.stable_names.insert(path.clone(), Vec::new());
+        for (pc, instruction) in &instructions {
+            let pc = *pc;
+            if leaders.contains(&pc) {
+                known.clear();
+            }
+            // Locals starting here keep the value computed by the previous instructions
+            let mut new_names = Vec::new();
+            for (local_var, register) in function.debug_info.local_vars.iter().zip(&local_registers)
+            {
+                if local_var.start_pc as usize != pc {
+                    continue;
+                }
+                let Some(name) = known.get(register) else {
+                    continue;
+                };
+                let end_pc = local_var.end_pc as usize;
+                let reassigned =
+                    instructions[pc..end_pc.min(instructions.len())]
+                        .iter()
+                        .any(|(_, other)| {
+                            other
+                                .as_ref()
+                                .is_some_and(|other| writes_register(other, *register))
+                        });
+                if !reassigned {
+                    new_names.push(StableName {
+                        register: *register,
+                        start_pc: pc,
+                        end_pc,
+                        name: name.clone(),
+                    });
+                }
+            }
+            if let Some(stable_names) = self.stable_names.get_mut(path) {
+                stable_names.extend(new_names);
+            }
+
+            let Some(instruction) = instruction else {
+                known.clear();
+                continue;
+            };
+            let name_of = |register: u8| {
+                known
+                    .get(&register)
+                    .cloned()
+                    .or_else(|| self.stable_name(path, register, pc))
+            };
+            let constant = |index: u8| {
+                function
+                    .constants
+                    .get(index as usize)
+                    .and_then(|constant| constant.as_str())
+            };
+            let field_of = |table: Option<String>, key: Option<&str>| {
+                let key = key?;
+                match table?.as_str() {
+                    ENV_UPVALUE_NAME | GLOBAL_TABLE_NAME => Some(key.to_string()),
+                    table => Some(format!("{}.{}", table, key)),
+                }
+            };
+            let loaded = match *instruction {
+                Instruction::GetTabup(_, b, c) => field_of(self.upvalue_name(path, b), constant(c)),
+                Instruction::GetField(_, b, c) => field_of(name_of(b), constant(c)),
+                Instruction::Self_(_, b, c, 1) => field_of(name_of(b), constant(c)),
+                Instruction::GetUpval(_, b) => self.upvalue_name(path, b),
+                Instruction::Move(_, b) => name_of(b),
+                _ => None,
+            };
+            let mut findings = Vec::new();
+            if let Some(name) = &loaded {
+                if let Some((rule, severity, reason)) =
+                    RISKY_NAMES.iter().find(|(rule, _, _)| rule == name)
+                {
+                    if !matches!(
+                        instruction,
+                        Instruction::Move(..) | Instruction::GetUpval(..)
+                    ) {
+                        findings.push((
+                            *severity,
+                            *rule,
+                            format!("access to {} ({})", rule, reason),
+                        ));
+                    }
+                }
+            }
+            match *instruction {
+                Instruction::Call(a, b, _) if b != 1 => {
+                    let target = name_of(a + 1);
+                    if name_of(a).as_deref() == Some("setmetatable")
+                        && matches!(
+                            target.as_deref(),
+                            Some(GLOBAL_TABLE_NAME | ENV_UPVALUE_NAME)
+                        )
+                    {
+                        findings.push((
+                            Severity::High,
+                            "setmetatable-global",
+                            "setmetatable on the global table".to_string(),
+                        ));
+                    }
+                }
+                Instruction::SetUpval(_, b) if is_env_upvalue(self.file, path, b) => {
+                    findings.push((
+                        Severity::High,
+                        "env-reassignment",
+                        "_ENV is reassigned through SetUpval".to_string(),
+                    ));
+                }
+                Instruction::NewTable(_, b, c, k) => {
+                    let extra = match instructions.get(pc + 1) {
+                        Some((_, Some(Instruction::Extraarg(ax)))) if k != 0 => *ax as usize,
+                        _ => 0,
+                    };
+                    let array_size = c as usize + extra * (u8::MAX as usize + 1);
+                    let hash_size = if b > 0 { 1usize << (b - 1) } else { 0 };
+                    if array_size + hash_size > self.options.max_table_size {
+                        findings.push((
+                            Severity::Medium,
+                            "large-table",
+                            format!(
+                                "table constructor with {} array and {} hash slots",
+                                array_size, hash_size
+                            ),
+                        ));
+                    }
+                }
+                Instruction::Closure(_, bx) => {
+                    self.closure_sites.insert(path.child(bx as usize), pc);
+                }
+                _ => {}
+            }
+            for (severity, rule, message) in findings {
+                self.report(path, function, Some(pc), severity, rule, message);
+            }
+
+            if let Some((first, count)) = instruction.written_registers() {
+                known.retain(|register, _| {
+                    *register < first
+                        || count.is_some_and(|count| {
+                            *register as usize >= first as usize + count as usize
+                        })
+                });
+                if let Some(name) = loaded {
+                    known.insert(first, name);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{binary_chunks::proto_path::ProtoPath, lua_file::LuaFile};
+
+    use super::{AuditOptions, AuditReport, Finding, Severity};
+
+    #[test]
+    fn test_audit_of_risky_plugin() {
+        let file = LuaFile::parse(include_bytes!("../../tests/audit.luac"))
+            .unwrap()
+            .1;
+        let report = AuditReport::build(&file);
+        let findings: Vec<_> = report
+            .findings
+            .iter()
+            .map(|finding| {
+                (
+                    finding.severity,
+                    finding.rule.as_str(),
+                    finding.path.to_string(),
+                    finding.line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            findings,
+            vec![
+                (
+                    Severity::Critical,
+                    "os.execute",
+                    "main".to_string(),
+                    Some(2)
+                ),
+                (Severity::Medium, "string.dump", "main".to_string(), Some(3)),
+                (
+                    Severity::High,
+                    "setmetatable-global",
+                    "main".to_string(),
+                    Some(4)
+                ),
+                (Severity::High, "debug", "main".to_string(), Some(5)),
+                (Severity::Low, "collectgarbage", "main".to_string(), Some(6)),
+                (
+                    Severity::Critical,
+                    "io.popen",
+                    "main/0".to_string(),
+                    Some(9)
+                ),
+                (Severity::High, "load", "main/0".to_string(), Some(10)),
+                (
+                    Severity::High,
+                    "env-reassignment",
+                    "main/0".to_string(),
+                    Some(11)
+                ),
+            ]
+        );
+        assert_eq!(report.max_severity(), Some(Severity::Critical));
+        assert!(report.to_string().contains(
+            "[CRITICAL] main pc 2 line 2: os.execute: access to os.execute (runs shell commands)"
+        ));
+    }
+
+    #[test]
+    fn test_audit_of_suspicious_shapes() {
+        let file = LuaFile::parse(include_bytes!("../../tests/all_opcodes.luac"))
+            .unwrap()
+            .1;
+        let report = AuditReport::build(&file);
+        assert_eq!(
+            report.findings,
+            vec![Finding {
+                severity: Severity::High,
+                rule: "env-reassignment".to_string(),
+                message: "_ENV is reassigned through SetUpval".to_string(),
+                path: ProtoPath::main(),
+                pc: Some(15),
+                line: Some(11),
+            }]
+        );
+        let options = AuditOptions {
+            max_table_size: 0,
+            max_constants: 8,
+            max_nesting_depth: 0,
+        };
+        let report = AuditReport::build_with_options(&file, &options);
+        let rules: Vec<_> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.rule.as_str(), finding.path.clone()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("large-constant-pool", ProtoPath::main()),
+                ("env-reassignment", ProtoPath::main()),
+                ("deep-nesting", ProtoPath(vec![0])),
+                ("deep-nesting", ProtoPath(vec![1])),
+                ("deep-nesting", ProtoPath(vec![2])),
+            ]
+        );
+    }
+}
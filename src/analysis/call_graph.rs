@@ -0,0 +1,621 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    common_structs::debug_info::LocalScopes,
+    instruction_parsing::instruction::Instruction,
+    lua_file::LuaFile,
+};
+
+use super::{block_leaders, decode, is_env_upvalue, writes_register};
+
+/// Where a closure takes one of its upvalues from
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CaptureSource {
+    /// A register of the function creating the closure
+    Register(u8),
+    /// An upvalue of the function creating the closure
+    Upvalue(u8),
+}
+
+/// A function block of the file
+#[derive(Debug, PartialEq)]
+pub struct FunctionNode {
+    pub path: ProtoPath,
+    /// Name the closure is stored under, when the code creating it tells
+    pub name: Option<String>,
+    pub source_line_start: u64,
+    pub source_line_end: u64,
+}
+
+/// A `Closure` instruction creating a nested function block
+#[derive(Debug, PartialEq)]
+pub struct ClosureEdge {
+    pub parent: ProtoPath,
+    pub child: ProtoPath,
+    pub pc: usize,
+    pub line: Option<u64>,
+}
+
+/// An upvalue of a closure captured from the function creating it
+#[derive(Debug, PartialEq)]
+pub struct CaptureEdge {
+    pub parent: ProtoPath,
+    pub child: ProtoPath,
+    /// Index of the upvalue in the closure
+    pub upvalue: usize,
+    pub name: Option<String>,
+    pub source: CaptureSource,
+}
+
+/// A call whose callee is known statically
+#[derive(Debug, PartialEq)]
+pub struct CallEdge {
+    pub caller: ProtoPath,
+    pub callee: ProtoPath,
+    pub pc: usize,
+    pub line: Option<u64>,
+}
+
+/// Whole-file graph of closure creation, upvalue captures and statically
+/// resolvable calls
+#[derive(Debug, PartialEq, Default)]
+pub struct CallGraph {
+    pub functions: Vec<FunctionNode>,
+    pub closures: Vec<ClosureEdge>,
+    pub captures: Vec<CaptureEdge>,
+    pub calls: Vec<CallEdge>,
+}
+
+/// A register holding the same closure for a whole range of pcs
+struct StableBinding {
+    register: u8,
+    start_pc: usize,
+    end_pc: usize,
+    closure: ProtoPath,
+}
+
+/// Resolves which registers, upvalues and globals statically hold a closure
+struct Resolver<'a> {
+    file: &'a LuaFile,
+    /// Pc of the `Closure` instruction creating each nested function block
+    closure_sites: HashMap<ProtoPath, usize>,
+    stable_bindings: HashMap<ProtoPath, Vec<StableBinding>>,
+    globals: HashMap<String, ProtoPath>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(file: &'a LuaFile) -> Self {
+        let mut resolver = Resolver {
+            file,
+            closure_sites: HashMap::new(),
+            stable_bindings: HashMap::new(),
+            globals: HashMap::new(),
+        };
+        for (path, function) in file.functions() {
+            for (pc, instruction) in decode(function) {
+                if let Some(Instruction::Closure(_, bx)) = instruction {
+                    resolver.closure_sites.insert(path.child(bx as usize), pc);
+                }
+            }
+        }
+        for (path, function) in file.functions() {
+            let bindings = resolver.find_stable_bindings(&path, function);
+            resolver.stable_bindings.insert(path, bindings);
+        }
+        resolver.globals = resolver.find_global_functions();
+        resolver
+    }
+
+    /// Finds registers holding a closure for the rest of their scope: locals
+    /// initialized with a closure, or registers written only by a `Closure`
+    fn find_stable_bindings(
+        &self,
+        path: &ProtoPath,
+        function: &FunctionBlockChunk,
+    ) -> Vec<StableBinding> {
+        let instructions: Vec<_> = decode(function)
+            .map(|(_, instruction)| instruction)
+            .collect();
+        let closure_at = |pc: usize, register: u8| match instructions.get(pc) {
+            Some(Some(Instruction::Closure(a, bx))) if *a == register => {
+                Some(path.child(*bx as usize))
+            }
+            _ => None,
+        };
+        let mut candidates = Vec::new();
+        let registers = function.debug_info.local_registers();
+        for (local_var, register) in function.debug_info.local_vars.iter().zip(registers) {
+            let start_pc = local_var.start_pc as usize;
+            let closure = start_pc.checked_sub(1).and_then(|pc| {
+                closure_at(pc, register).or_else(|| match instructions.get(pc) {
+                    Some(Some(Instruction::Move(a, b))) if *a == register => {
+                        pc.checked_sub(1).and_then(|pc| closure_at(pc, *b))
+                    }
+                    _ => None,
+                })
+            });
+            if let Some(closure) = closure {
+                candidates.push(StableBinding {
+                    register,
+                    start_pc,
+                    end_pc: local_var.end_pc as usize,
+                    closure,
+                });
+            }
+        }
+        for (pc, instruction) in instructions.iter().enumerate() {
+            if let Some(Instruction::Closure(a, bx)) = instruction {
+                let written_elsewhere = instructions.iter().enumerate().any(|(other_pc, other)| {
+                    other_pc != pc
+                        && other
+                            .as_ref()
+                            .is_some_and(|other| writes_register(other, *a))
+                });
+                if !written_elsewhere {
+                    candidates.push(StableBinding {
+                        register: *a,
+                        start_pc: pc + 1,
+                        end_pc: instructions.len(),
+                        closure: path.child(*bx as usize),
+                    });
+                }
+            }
+        }
+        candidates
+            .into_iter()
+            .filter(|binding| {
+                let written_in_scope = instructions[binding.start_pc.min(instructions.len())
+                    ..binding.end_pc.min(instructions.len())]
+                    .iter()
+                    .flatten()
+                    .any(|instruction| writes_register(instruction, binding.register));
+                let written_by_closure =
+                    (binding.start_pc..binding.end_pc).any(|pc| match instructions.get(pc) {
+                        Some(Some(Instruction::Closure(_, bx))) => {
+                            function.protos.get(*bx as usize).is_some_and(|child| {
+                                child.upvalues.iter().enumerate().any(|(index, upvalue)| {
+                                    upvalue.in_stack
+                                        && upvalue.index == binding.register
+                                        && writes_upvalue(child, index as u8)
+                                })
+                            })
+                        }
+                        _ => false,
+                    });
+                !written_in_scope && !written_by_closure
+            })
+            .collect()
+    }
+
+    /// Finds globals assigned exactly once in the whole file, with a closure
+    fn find_global_functions(&self) -> HashMap<String, ProtoPath> {
+        let mut assignments: HashMap<String, Vec<Option<ProtoPath>>> = HashMap::new();
+        for (path, function) in self.file.functions() {
+            self.scan(&path, function, |pc, instruction, known| {
+                if let Instruction::SetTabup(a, b, c, k) = *instruction {
+                    if let Some(name) = self.global_name(&path, function, a, b) {
+                        let value = if k == 0 {
+                            self.value_of(&path, known, c, pc)
+                        } else {
+                            None
+                        };
+                        assignments.entry(name).or_default().push(value);
+                    }
+                }
+            });
+        }
+        assignments
+            .into_iter()
+            .filter_map(|(name, values)| match values.as_slice() {
+                [Some(closure)] => Some((name, closure.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Name of the global accessed through upvalue `upvalue` with key `K[key]`
+    fn global_name(
+        &self,
+        path: &ProtoPath,
+        function: &FunctionBlockChunk,
+        upvalue: u8,
+        key: u8,
+    ) -> Option<String> {
+        if !is_env_upvalue(self.file, path, upvalue) {
+            return None;
+        }
+        function
+            .constants
+            .get(key as usize)
+            .and_then(|constant| constant.as_str())
+            .map(str::to_string)
+    }
+
+    /// Closure held by `register` at `pc`
+    fn value_of(
+        &self,
+        path: &ProtoPath,
+        known: &HashMap<u8, ProtoPath>,
+        register: u8,
+        pc: usize,
+    ) -> Option<ProtoPath> {
+        known.get(&register).cloned().or_else(|| {
+            self.stable_bindings.get(path).and_then(|bindings| {
+                bindings
+                    .iter()
+                    .find(|binding| {
+                        binding.register == register
+                            && binding.start_pc <= pc
+                            && pc < binding.end_pc
+                    })
+                    .map(|binding| binding.closure.clone())
+            })
+        })
+    }
+
+    /// Closure held by upvalue `index` of the function block at `path`
+    fn upvalue_value(&self, path: &ProtoPath, index: u8) -> Option<ProtoPath> {
+        let function = self.file.function_at(path)?;
+        let upvalue = function.upvalues.get(index as usize)?;
+        if writes_upvalue(function, index) {
+            return None;
+        }
+        let parent = path.parent()?;
+        if upvalue.in_stack {
+            let closure_pc = *self.closure_sites.get(path)?;
+            self.value_of(&parent, &HashMap::new(), upvalue.index, closure_pc)
+        } else {
+            self.upvalue_value(&parent, upvalue.index)
+        }
+    }
+
+    /// Walks the instructions of a function block, tracking which registers
+    /// hold a known closure inside each basic block
+    fn scan(
+        &self,
+        path: &ProtoPath,
+        function: &FunctionBlockChunk,
+        mut visit: impl FnMut(usize, &Instruction, &HashMap<u8, ProtoPath>),
+    ) {
+        let leaders = block_leaders(function);
+        let mut known: HashMap<u8, ProtoPath> = HashMap::new();
+        for (pc, instruction) in decode(function) {
+            if leaders.contains(&pc) {
+                known.clear();
+            }
+            let Some(instruction) = instruction else {
+                known.clear();
+                continue;
+            };
+            visit(pc, &instruction, &known);
+            let value = match instruction {
+                Instruction::Closure(_, bx) => Some(path.child(bx as usize)),
+                Instruction::Move(_, b) => self.value_of(path, &known, b, pc),
+                Instruction::GetUpval(_, b) => self.upvalue_value(path, b),
+                Instruction::GetTabup(_, b, c) => self
+                    .global_name(path, function, b, c)
+                    .and_then(|name| self.globals.get(&name).cloned()),
+                _ => None,
+            };
+            if let Some((first, count)) = instruction.written_registers() {
+                let last = count.map(|count| first as usize + count as usize);
+                known.retain(|register, _| {
+                    (*register as usize) < first as usize
+                        || last.is_some_and(|last| *register as usize >= last)
+                });
+                if let Some(value) = value {
+                    known.insert(first, value);
+                }
+            }
+        }
+    }
+}
+
+/// Checks whether a function block, or a closure nested in it sharing the
+/// upvalue, assigns upvalue `index`
+fn writes_upvalue(function: &FunctionBlockChunk, index: u8) -> bool {
+    decode(function).any(
+        |(_, instruction)| matches!(instruction, Some(Instruction::SetUpval(_, b)) if b == index),
+    ) || function.protos.iter().any(|proto| {
+        proto
+            .upvalues
+            .iter()
+            .enumerate()
+            .any(|(proto_index, upvalue)| {
+                !upvalue.in_stack
+                    && upvalue.index == index
+                    && writes_upvalue(proto, proto_index as u8)
+            })
+    })
+}
+
+/// Describes the value held by `register` at `pc` by looking at the instructions
+/// loading it, for naming closures stored into tables
+fn describe_register(
+    file: &LuaFile,
+    path: &ProtoPath,
+    function: &FunctionBlockChunk,
+    locals: &LocalScopes,
+    register: u8,
+    pc: usize,
+) -> Option<String> {
+    if let Some(name) = locals.local_name(register, pc) {
+        return Some(name.to_string());
+    }
+    let (load_pc, load) = (0..pc)
+        .rev()
+        .filter_map(|load_pc| {
+            Instruction::parse_u32(function.instructions[load_pc]).map(|load| (load_pc, load))
+        })
+        .find(|(_, load)| writes_register(load, register))?;
+    let constant = |index: u8| {
+        function
+            .constants
+            .get(index as usize)
+            .and_then(|constant| constant.as_str())
+    };
+    match load {
+        Instruction::GetTabup(_, b, c) if is_env_upvalue(file, path, b) => {
+            constant(c).map(str::to_string)
+        }
+        Instruction::GetField(_, b, c) => Some(format!(
+            "{}.{}",
+            describe_register(file, path, function, locals, b, load_pc)
+                .unwrap_or_else(|| "?".to_string()),
+            constant(c)?
+        )),
+        Instruction::GetUpval(_, b) => function
+            .debug_info
+            .upvalue_name(b as usize)
+            .map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Tells the name a closure created at `pc` gets stored under
+fn closure_name(
+    file: &LuaFile,
+    path: &ProtoPath,
+    function: &FunctionBlockChunk,
+    locals: &LocalScopes,
+    register: u8,
+    pc: usize,
+) -> Option<String> {
+    if let Some(name) = locals.local_name(register, pc + 1) {
+        return Some(name.to_string());
+    }
+    let constant = |index: u8| {
+        function
+            .constants
+            .get(index as usize)
+            .and_then(|constant| constant.as_str())
+    };
+    let next = function
+        .instructions
+        .get(pc + 1)
+        .and_then(|instruction| Instruction::parse_u32(*instruction))?;
+    match next {
+        Instruction::SetTabup(a, b, c, 0) if c == register && is_env_upvalue(file, path, a) => {
+            constant(b).map(str::to_string)
+        }
+        Instruction::SetField(a, b, c, 0) if c == register => Some(format!(
+            "{}.{}",
+            describe_register(file, path, function, locals, a, pc + 1)
+                .unwrap_or_else(|| "?".to_string()),
+            constant(b)?
+        )),
+        Instruction::Move(a, b) if b == register => {
+            locals.local_name(a, pc + 2).map(str::to_string)
+        }
+        _ => None,
+    }
+}
+
+impl CallGraph {
+    /// Builds the graph of every function block in a file
+    pub fn build(file: &LuaFile) -> Self {
+        let resolver = Resolver::new(file);
+        let mut graph = CallGraph::default();
+        let mut names = HashMap::new();
+        for (path, function) in file.functions() {
+            let locals = function.debug_info.local_scopes();
+            for (pc, instruction) in decode(function) {
+                let Some(Instruction::Closure(a, bx)) = instruction else {
+                    continue;
+                };
+                let child_path = path.child(bx as usize);
+                let Some(child) = function.protos.get(bx as usize) else {
+                    continue;
+                };
+                names.insert(
+                    child_path.clone(),
+                    closure_name(file, &path, function, &locals, a, pc),
+                );
+                graph.closures.push(ClosureEdge {
+                    parent: path.clone(),
+                    child: child_path.clone(),
+                    pc,
+                    line: function.line_at(pc),
+                });
+                for (index, upvalue) in child.upvalues.iter().enumerate() {
+                    let (source, name) = if upvalue.in_stack {
+                        (
+                            CaptureSource::Register(upvalue.index),
+                            locals.local_name(upvalue.index, pc).map(str::to_string),
+                        )
+                    } else {
+                        (
+                            CaptureSource::Upvalue(upvalue.index),
+                            function
+                                .debug_info
+                                .upvalue_name(upvalue.index as usize)
+                                .map(str::to_string),
+                        )
+                    };
+                    graph.captures.push(CaptureEdge {
+                        parent: path.clone(),
+                        child: child_path.clone(),
+                        upvalue: index,
+                        name: name
+                            .or_else(|| child.debug_info.upvalue_name(index).map(str::to_string)),
+                        source,
+                    });
+                }
+            }
+            resolver.scan(&path, function, |pc, instruction, known| {
+                if let Instruction::Call(a, _, _) | Instruction::TailCall(a, _, _, _) = *instruction
+                {
+                    if let Some(callee) = resolver.value_of(&path, known, a, pc) {
+                        graph.calls.push(CallEdge {
+                            caller: path.clone(),
+                            callee,
+                            pc,
+                            line: function.line_at(pc),
+                        });
+                    }
+                }
+            });
+        }
+        graph.functions = file
+            .functions()
+            .into_iter()
+            .map(|(path, function)| FunctionNode {
+                name: names.remove(&path).flatten(),
+                path,
+                source_line_start: function.source_line_start,
+                source_line_end: function.source_line_end,
+            })
+            .collect();
+        graph
+    }
+
+    /// Renders the graph in the Graphviz DOT language
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph call_graph {\n    node [shape=box];\n");
+        for function in &self.functions {
+            let mut label = function.path.to_string();
+            if let Some(name) = &function.name {
+                write!(label, "\\n{}", escape_dot(name)).unwrap();
+            }
+            if !function.path.0.is_empty() {
+                write!(
+                    label,
+                    "\\nlines {}-{}",
+                    function.source_line_start, function.source_line_end
+                )
+                .unwrap();
+            }
+            writeln!(dot, "    \"{}\" [label=\"{}\"];", function.path, label).unwrap();
+        }
+        for closure in &self.closures {
+            writeln!(
+                dot,
+                "    \"{}\" -> \"{}\" [label=\"closure @{}\"];",
+                closure.parent, closure.child, closure.pc
+            )
+            .unwrap();
+        }
+        for capture in &self.captures {
+            let source = match capture.source {
+                CaptureSource::Register(register) => format!("R{}", register),
+                CaptureSource::Upvalue(upvalue) => format!("U{}", upvalue),
+            };
+            let label = match &capture.name {
+                Some(name) => format!("{} ({})", escape_dot(name), source),
+                None => source,
+            };
+            writeln!(
+                dot,
+                "    \"{}\" -> \"{}\" [label=\"captures {}\", style=dashed];",
+                capture.parent, capture.child, label
+            )
+            .unwrap();
+        }
+        for call in &self.calls {
+            writeln!(
+                dot,
+                "    \"{}\" -> \"{}\" [label=\"call @{}\", color=blue];",
+                call.caller, call.callee, call.pc
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{binary_chunks::proto_path::ProtoPath, lua_file::LuaFile};
+
+    use super::{CallEdge, CallGraph, CaptureEdge, CaptureSource};
+
+    #[test]
+    fn test_call_graph_of_closures() {
+        let file = LuaFile::parse(include_bytes!("../../tests/closures.luac"))
+            .unwrap()
+            .1;
+        let graph = CallGraph::build(&file);
+        let names: Vec<_> = graph
+            .functions
+            .iter()
+            .map(|function| function.name.as_deref())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                None,
+                Some("increment"),
+                Some("twice"),
+                Some("report"),
+                Some("helpers.reset")
+            ]
+        );
+        assert_eq!(graph.closures.len(), 4);
+        assert!(graph.captures.contains(&CaptureEdge {
+            parent: ProtoPath::main(),
+            child: ProtoPath(vec![2]),
+            upvalue: 1,
+            name: Some("twice".to_string()),
+            source: CaptureSource::Register(2),
+        }));
+        let calls: Vec<_> = graph
+            .calls
+            .iter()
+            .map(
+                |CallEdge {
+                     caller, callee, pc, ..
+                 }| (caller.to_string(), callee.to_string(), *pc),
+            )
+            .collect();
+        assert_eq!(
+            calls,
+            vec![
+                ("main".to_string(), "main/2".to_string(), 11),
+                ("main/1".to_string(), "main/0".to_string(), 2),
+                ("main/1".to_string(), "main/0".to_string(), 5),
+                ("main/2".to_string(), "main/1".to_string(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call_graph_dot_output() {
+        let file = LuaFile::parse(include_bytes!("../../tests/all_opcodes.luac"))
+            .unwrap()
+            .1;
+        let dot = CallGraph::build(&file).to_dot();
+        assert!(dot.starts_with("digraph call_graph {"));
+        assert!(dot.contains("\"main/2\" [label=\"main/2\\nr\\nlines 84-86\"];"));
+        assert!(dot.contains("\"main\" -> \"main/0\" [label=\"closure @173\"];"));
+        assert!(dot.contains("\"main\" -> \"main/0\" [label=\"captures x (R6)\", style=dashed];"));
+        assert!(
+            dot.contains("\"main\" -> \"main/2\" [label=\"captures _ENV (U0)\", style=dashed];")
+        );
+    }
+}
@@ -0,0 +1,498 @@
+use std::fmt::Display;
+
+use crate::{
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    disassembler::{display_constant, quote_bytes},
+    lua_file::LuaFile,
+};
+
+use super::decode;
+
+/// Unchanged instructions shown around the changed ones
+const CONTEXT: usize = 3;
+/// Largest table aligning two lists, beyond which they are not aligned
+const MAX_ALIGNMENT_CELLS: usize = 1 << 24;
+
+/// A line of two listings aligned side by side, with the index of the item in
+/// each of them. Items only in the old listing were removed, items only in the
+/// new one added.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AlignedLine {
+    pub old: Option<usize>,
+    pub new: Option<usize>,
+    pub text: String,
+}
+
+impl AlignedLine {
+    pub fn is_same(&self) -> bool {
+        self.old.is_some() && self.new.is_some()
+    }
+}
+
+impl Display for AlignedLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let index =
+            |index: Option<usize>| index.map_or_else(String::new, |index| index.to_string());
+        let marker = match (self.old, self.new) {
+            (Some(_), None) => "-",
+            (None, Some(_)) => "+",
+            _ => " ",
+        };
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            marker,
+            index(self.old),
+            index(self.new),
+            self.text
+        )
+    }
+}
+
+/// A property of a function block that differs between the two files
+#[derive(Debug, PartialEq, Clone)]
+pub struct AttributeChange {
+    pub name: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// The differences between two function blocks matched with each other
+#[derive(Debug, PartialEq)]
+pub struct FunctionDiff {
+    pub old_path: ProtoPath,
+    pub new_path: ProtoPath,
+    pub old_lines: (u64, u64),
+    pub new_lines: (u64, u64),
+    pub attributes: Vec<AttributeChange>,
+    pub constants: Vec<AlignedLine>,
+    pub upvalues: Vec<AlignedLine>,
+    pub instructions: Vec<AlignedLine>,
+}
+
+impl FunctionDiff {
+    pub fn build(
+        (old_path, old): (&ProtoPath, &FunctionBlockChunk),
+        (new_path, new): (&ProtoPath, &FunctionBlockChunk),
+    ) -> Self {
+        let mut attributes = vec![];
+        let mut compare = |name, old: String, new: String| {
+            if old != new {
+                attributes.push(AttributeChange { name, old, new });
+            }
+        };
+        let source = |function: &FunctionBlockChunk| {
+            function
+                .source_name
+                .as_deref()
+                .map_or_else(|| "-".to_string(), quote_bytes)
+        };
+        compare("source", source(old), source(new));
+        compare(
+            "params",
+            old.number_of_parameters.to_string(),
+            new.number_of_parameters.to_string(),
+        );
+        compare(
+            "vararg",
+            (old.is_vararg.has_arg() != 0).to_string(),
+            (new.is_vararg.has_arg() != 0).to_string(),
+        );
+        compare(
+            "slots",
+            old.maximum_stack_size.to_string(),
+            new.maximum_stack_size.to_string(),
+        );
+        FunctionDiff {
+            old_path: old_path.clone(),
+            new_path: new_path.clone(),
+            old_lines: lines(old),
+            new_lines: lines(new),
+            attributes,
+            constants: align_listings(&constant_listing(old), &constant_listing(new)),
+            upvalues: align_listings(&upvalue_listing(old), &upvalue_listing(new)),
+            instructions: align_listings(&instruction_listing(old), &instruction_listing(new)),
+        }
+    }
+
+    /// Whether the function block moved or anything in it changed
+    pub fn is_changed(&self) -> bool {
+        self.old_path != self.new_path
+            || !self.attributes.is_empty()
+            || [&self.constants, &self.upvalues, &self.instructions]
+                .iter()
+                .any(|lines| lines.iter().any(|line| !line.is_same()))
+    }
+}
+
+impl Display for FunctionDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "changed {} <{},{}>",
+            self.old_path, self.old_lines.0, self.old_lines.1
+        )?;
+        if self.old_path != self.new_path || self.old_lines != self.new_lines {
+            write!(
+                f,
+                " -> {} <{},{}>",
+                self.new_path, self.new_lines.0, self.new_lines.1
+            )?;
+        }
+        writeln!(f)?;
+        for attribute in &self.attributes {
+            writeln!(
+                f,
+                "  {}: {} -> {}",
+                attribute.name, attribute.old, attribute.new
+            )?;
+        }
+        for (name, lines) in [("constants", &self.constants), ("upvalues", &self.upvalues)] {
+            if lines.iter().all(AlignedLine::is_same) {
+                continue;
+            }
+            writeln!(f, "  {}:", name)?;
+            for line in lines.iter().filter(|line| !line.is_same()) {
+                writeln!(f, "  {}", line)?;
+            }
+        }
+        if self.instructions.iter().all(AlignedLine::is_same) {
+            return Ok(());
+        }
+        writeln!(f, "  instructions:")?;
+        // Changed instructions and their context, with `...` for the rest
+        let shown: Vec<_> = (0..self.instructions.len())
+            .map(|index| {
+                let start = index.saturating_sub(CONTEXT);
+                let end = (index + CONTEXT + 1).min(self.instructions.len());
+                self.instructions[start..end]
+                    .iter()
+                    .any(|line| !line.is_same())
+            })
+            .collect();
+        for (index, line) in self.instructions.iter().enumerate() {
+            if shown[index] {
+                writeln!(f, "  {}", line)?;
+            } else if index == 0 || shown[index - 1] {
+                writeln!(f, "  \t...")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a function block differs between two files
+#[derive(Debug, PartialEq)]
+pub enum FunctionChange {
+    Added { path: ProtoPath, lines: (u64, u64) },
+    Removed { path: ProtoPath, lines: (u64, u64) },
+    Changed(FunctionDiff),
+}
+
+impl Display for FunctionChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FunctionChange::Added { path, lines } => {
+                writeln!(f, "added {} <{},{}>", path, lines.0, lines.1)
+            }
+            FunctionChange::Removed { path, lines } => {
+                writeln!(f, "removed {} <{},{}>", path, lines.0, lines.1)
+            }
+            FunctionChange::Changed(diff) => write!(f, "{}", diff),
+        }
+    }
+}
+
+/// The function blocks added, removed and changed from one compiled file to
+/// another. Line information and local variables are not compared.
+#[derive(Debug, PartialEq, Default)]
+pub struct FileDiff {
+    pub functions: Vec<FunctionChange>,
+}
+
+impl FileDiff {
+    /// Matches the function blocks of the two files, first by both path and
+    /// line range, then by line range alone and last by path alone
+    pub fn build(old: &LuaFile, new: &LuaFile) -> Self {
+        let old_functions = old.functions();
+        let new_functions = new.functions();
+        let mut matches: Vec<Option<usize>> = vec![None; old_functions.len()];
+        let mut new_matched = vec![false; new_functions.len()];
+        let passes: [fn(&Function, &Function) -> bool; 3] = [
+            |old, new| old.0 == new.0 && lines(old.1) == lines(new.1),
+            |old, new| lines(old.1) == lines(new.1),
+            |old, new| old.0 == new.0,
+        ];
+        for matching in passes {
+            for (old_index, old_function) in old_functions.iter().enumerate() {
+                if matches[old_index].is_some() {
+                    continue;
+                }
+                let found = new_functions
+                    .iter()
+                    .enumerate()
+                    .find(|(new_index, new_function)| {
+                        !new_matched[*new_index] && matching(old_function, new_function)
+                    });
+                if let Some((new_index, _)) = found {
+                    matches[old_index] = Some(new_index);
+                    new_matched[new_index] = true;
+                }
+            }
+        }
+        let mut functions = vec![];
+        for ((old_path, old_function), matched) in old_functions.iter().zip(matches) {
+            match matched {
+                Some(new_index) => {
+                    let (new_path, new_function) = &new_functions[new_index];
+                    let diff =
+                        FunctionDiff::build((old_path, old_function), (new_path, new_function));
+                    if diff.is_changed() {
+                        functions.push(FunctionChange::Changed(diff));
+                    }
+                }
+                None => functions.push(FunctionChange::Removed {
+                    path: old_path.clone(),
+                    lines: lines(old_function),
+                }),
+            }
+        }
+        for ((path, function), matched) in new_functions.iter().zip(new_matched) {
+            if !matched {
+                functions.push(FunctionChange::Added {
+                    path: path.clone(),
+                    lines: lines(function),
+                });
+            }
+        }
+        FileDiff { functions }
+    }
+
+    /// Whether the two files have the same function blocks
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+}
+
+impl Display for FileDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.functions {
+            write!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// A function block with its path, as `LuaFile::functions` lists them
+type Function<'a> = (ProtoPath, &'a FunctionBlockChunk);
+
+fn lines(function: &FunctionBlockChunk) -> (u64, u64) {
+    (function.source_line_start, function.source_line_end)
+}
+
+fn constant_listing(function: &FunctionBlockChunk) -> Vec<String> {
+    function.constants.iter().map(display_constant).collect()
+}
+
+fn upvalue_listing(function: &FunctionBlockChunk) -> Vec<String> {
+    function
+        .upvalues
+        .iter()
+        .enumerate()
+        .map(|(index, upvalue)| {
+            let name = function
+                .debug_info
+                .upvalue_names
+                .get(index)
+                .and_then(|name| name.as_deref())
+                .map_or("-".into(), String::from_utf8_lossy);
+            format!(
+                "{}\t{}\t{}\t{:?}",
+                name, upvalue.in_stack as u8, upvalue.index, upvalue.kind
+            )
+        })
+        .collect()
+}
+
+/// The instructions with the constants they read, so that instructions
+/// reading moved constants still differ
+fn instruction_listing(function: &FunctionBlockChunk) -> Vec<String> {
+    decode(function)
+        .map(|(pc, instruction)| {
+            let Some(instruction) = instruction else {
+                return format!("{:#010x}", function.instructions[pc]);
+            };
+            let constants: Vec<_> = instruction
+                .constant_indices()
+                .iter()
+                .map(|index| {
+                    function
+                        .constants
+                        .get(*index as usize)
+                        .map_or_else(|| "?".to_string(), display_constant)
+                })
+                .collect();
+            if constants.is_empty() {
+                format!("{:?}", instruction)
+            } else {
+                format!("{:?}\t; {}", instruction, constants.join(" "))
+            }
+        })
+        .collect()
+}
+
+fn align_listings(old: &[String], new: &[String]) -> Vec<AlignedLine> {
+    align(old, new)
+        .into_iter()
+        .map(|(old_index, new_index)| AlignedLine {
+            old: old_index,
+            new: new_index,
+            text: match (old_index, new_index) {
+                (Some(index), _) => old[index].clone(),
+                (None, Some(index)) => new[index].clone(),
+                (None, None) => unreachable!("aligned lines come from either side"),
+            },
+        })
+        .collect()
+}
+
+/// Pairs the equal items of `old` and `new` along a longest common
+/// subsequence, the others being paired with `None`, removals first
+fn align<T: PartialEq>(old: &[T], new: &[T]) -> Vec<(Option<usize>, Option<usize>)> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    let mut aligned: Vec<_> = (0..prefix)
+        .map(|index| (Some(index), Some(index)))
+        .collect();
+    let (rows, columns) = (old_middle.len() + 1, new_middle.len() + 1);
+    if rows * columns > MAX_ALIGNMENT_CELLS {
+        aligned.extend((0..old_middle.len()).map(|index| (Some(prefix + index), None)));
+        aligned.extend((0..new_middle.len()).map(|index| (None, Some(prefix + index))));
+    } else {
+        // Length of the longest common subsequence of the rests of both lists
+        let mut common = vec![0u32; rows * columns];
+        for i in (0..old_middle.len()).rev() {
+            for j in (0..new_middle.len()).rev() {
+                common[i * columns + j] = if old_middle[i] == new_middle[j] {
+                    common[(i + 1) * columns + j + 1] + 1
+                } else {
+                    common[(i + 1) * columns + j].max(common[i * columns + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old_middle.len() || j < new_middle.len() {
+            if i < old_middle.len() && j < new_middle.len() && old_middle[i] == new_middle[j] {
+                aligned.push((Some(prefix + i), Some(prefix + j)));
+                i += 1;
+                j += 1;
+            } else if j == new_middle.len()
+                || (i < old_middle.len()
+                    && common[(i + 1) * columns + j] >= common[i * columns + j + 1])
+            {
+                aligned.push((Some(prefix + i), None));
+                i += 1;
+            } else {
+                aligned.push((None, Some(prefix + j)));
+                j += 1;
+            }
+        }
+    }
+    aligned.extend((0..suffix).map(|index| {
+        (
+            Some(old.len() - suffix + index),
+            Some(new.len() - suffix + index),
+        )
+    }));
+    aligned
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        binary_chunks::proto_path::ProtoPath, common_structs::constant::LuaConstant,
+        lua_file::LuaFile,
+    };
+
+    use super::{align, FileDiff, FunctionChange};
+
+    #[test]
+    fn test_align() {
+        assert_eq!(
+            align(&["a", "b", "c", "d"], &["a", "x", "c", "d", "e"]),
+            vec![
+                (Some(0), Some(0)),
+                (Some(1), None),
+                (None, Some(1)),
+                (Some(2), Some(2)),
+                (Some(3), Some(3)),
+                (None, Some(4)),
+            ]
+        );
+        assert_eq!(align::<u8>(&[], &[]), vec![]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = LuaFile::parse(include_bytes!("../../tests/closures.luac"))
+            .unwrap()
+            .1;
+        assert!(FileDiff::build(&old, &old).is_empty());
+        let mut new = LuaFile::parse(include_bytes!("../../tests/closures.luac"))
+            .unwrap()
+            .1;
+        let main = &mut new.main_function_block;
+        main.constants[0] = LuaConstant::String(b"summary".to_vec());
+        main.protos.remove(1);
+        // `increment` adds its step twice
+        let increment = &mut main.protos[0];
+        let added: Vec<_> = increment.instructions[1..3].to_vec();
+        increment.instructions.splice(3..3, added);
+        let diff = FileDiff::build(&old, &new);
+        let text = diff.to_string();
+        assert_eq!(diff.functions.len(), 5, "{}", text);
+        assert!(matches!(
+            &diff.functions[2],
+            FunctionChange::Removed { path, lines: (9, 12) } if *path == ProtoPath(vec![1])
+        ));
+        assert!(
+            text.starts_with(
+                "changed main <0,0>\n  constants:\n  -\t0\t\t\"report\"\n  +\t\t0\t\"summary\"\n"
+            ),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains(indoc!(
+                "
+            changed main/0 <4,7>
+              instructions:
+               \t0\t0\tGetUpval(1, 0)
+               \t1\t1\tAdd(1, 1, 0)
+               \t2\t2\tMmBin(1, 0, 6)
+              +\t\t3\tAdd(1, 1, 0)
+              +\t\t4\tMmBin(1, 0, 6)
+               \t3\t5\tSetUpval(1, 0)
+            "
+            )),
+            "{}",
+            text
+        );
+        assert!(
+            text.ends_with("changed main/2 <14,16> -> main/1 <14,16>\nchanged main/3 <19,21> -> main/2 <19,21>\n"),
+            "{}",
+            text
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+};
+
+use crate::{
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    instruction_parsing::instruction::Instruction,
+    lua_file::LuaFile,
+};
+
+use super::{block_leaders, decode, is_env_upvalue, ENV_UPVALUE_NAME};
+
+/// Whether a global is read or written
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GlobalAccessKind {
+    Read,
+    Write,
+}
+
+impl Display for GlobalAccessKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GlobalAccessKind::Read => f.pad("read"),
+            GlobalAccessKind::Write => f.pad("write"),
+        }
+    }
+}
+
+/// A single instruction reading or writing a global variable
+#[derive(Debug, PartialEq)]
+pub struct GlobalAccess {
+    pub name: String,
+    pub kind: GlobalAccessKind,
+    pub path: ProtoPath,
+    pub pc: usize,
+    pub line: Option<u64>,
+}
+
+/// Number of reads and writes of a global variable
+#[derive(Debug, PartialEq, Default)]
+pub struct GlobalUsage {
+    pub reads: usize,
+    pub writes: usize,
+}
+
+/// Every global variable access in a compiled file
+#[derive(Debug, PartialEq, Default)]
+pub struct GlobalsReport {
+    pub accesses: Vec<GlobalAccess>,
+}
+
+impl GlobalsReport {
+    /// Finds the global accesses of every function block in a file.
+    /// Globals are accessed through `GetTabup`/`SetTabup` on the `_ENV`
+    /// upvalue, or through `GetField`/`SetField`/`GetTable`/`SetTable` on a
+    /// register holding `_ENV`.
+    pub fn build(file: &LuaFile) -> Self {
+        let mut report = GlobalsReport::default();
+        for (path, function) in file.functions() {
+            report.add_function(file, &path, function);
+        }
+        report
+    }
+
+    fn add_function(&mut self, file: &LuaFile, path: &ProtoPath, function: &FunctionBlockChunk) {
+        let leaders = block_leaders(function);
+        let constant = |index: usize| {
+            function
+                .constants
+                .get(index)
+                .and_then(|constant| constant.as_str())
+        };
+        let locals = function.debug_info.local_scopes();
+        let mut env_registers: HashSet<u8> = HashSet::new();
+        let mut string_registers: HashMap<u8, &str> = HashMap::new();
+        for (pc, instruction) in decode(function) {
+            if leaders.contains(&pc) {
+                env_registers.clear();
+                string_registers.clear();
+            }
+            let Some(instruction) = instruction else {
+                env_registers.clear();
+                string_registers.clear();
+                continue;
+            };
+            let holds_env = |register: u8| {
+                env_registers.contains(&register)
+                    || locals.local_name(register, pc) == Some(ENV_UPVALUE_NAME)
+            };
+            let access = match instruction {
+                Instruction::GetTabup(_, b, c) if is_env_upvalue(file, path, b) => {
+                    constant(c.into()).map(|name| (name, GlobalAccessKind::Read))
+                }
+                Instruction::SetTabup(a, b, _, _) if is_env_upvalue(file, path, a) => {
+                    constant(b.into()).map(|name| (name, GlobalAccessKind::Write))
+                }
+                Instruction::GetField(_, b, c) if holds_env(b) => {
+                    constant(c.into()).map(|name| (name, GlobalAccessKind::Read))
+                }
+                Instruction::SetField(a, b, _, _) if holds_env(a) => {
+                    constant(b.into()).map(|name| (name, GlobalAccessKind::Write))
+                }
+                Instruction::GetTable(_, b, c) if holds_env(b) => string_registers
+                    .get(&c)
+                    .map(|name| (*name, GlobalAccessKind::Read)),
+                Instruction::SetTable(a, b, _, _) if holds_env(a) => string_registers
+                    .get(&b)
+                    .map(|name| (*name, GlobalAccessKind::Write)),
+                _ => None,
+            };
+            if let Some((name, kind)) = access {
+                self.accesses.push(GlobalAccess {
+                    name: name.to_string(),
+                    kind,
+                    path: path.clone(),
+                    pc,
+                    line: function.line_at(pc),
+                });
+            }
+
+            let loads_env = match instruction {
+                Instruction::GetUpval(_, b) => is_env_upvalue(file, path, b),
+                Instruction::Move(_, b) => holds_env(b),
+                _ => false,
+            };
+            let loaded_string = match instruction {
+                Instruction::LoadK(_, bx) => constant(bx as usize),
+                Instruction::Move(_, b) => string_registers.get(&b).copied(),
+                _ => None,
+            };
+            if let Some((first, count)) = instruction.written_registers() {
+                let written = |register: &u8| {
+                    *register >= first
+                        && count.is_none_or(|count| {
+                            (*register as usize) < first as usize + count as usize
+                        })
+                };
+                env_registers.retain(|register| !written(register));
+                string_registers.retain(|register, _| !written(register));
+                if loads_env {
+                    env_registers.insert(first);
+                }
+                if let Some(string) = loaded_string {
+                    string_registers.insert(first, string);
+                }
+            }
+        }
+    }
+
+    /// Number of reads and writes of each global, by name
+    pub fn usage(&self) -> BTreeMap<&str, GlobalUsage> {
+        let mut usage: BTreeMap<&str, GlobalUsage> = BTreeMap::new();
+        for access in &self.accesses {
+            let entry = usage.entry(&access.name).or_default();
+            match access.kind {
+                GlobalAccessKind::Read => entry.reads += 1,
+                GlobalAccessKind::Write => entry.writes += 1,
+            }
+        }
+        usage
+    }
+}
+
+impl Display for GlobalsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Globals:")?;
+        for (name, usage) in self.usage() {
+            writeln!(
+                f,
+                "  {:<24} reads: {:<4} writes: {}",
+                name, usage.reads, usage.writes
+            )?;
+        }
+        writeln!(f, "Accesses:")?;
+        for access in &self.accesses {
+            let line = access
+                .line
+                .map_or_else(|| "?".to_string(), |line| line.to_string());
+            writeln!(
+                f,
+                "  {:<12} pc {:<5} line {:<5} {:<5} {}",
+                access.path.to_string(),
+                access.pc,
+                line,
+                access.kind,
+                access.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{binary_chunks::proto_path::ProtoPath, lua_file::LuaFile};
+
+    use super::{GlobalAccess, GlobalAccessKind, GlobalsReport};
+
+    fn accesses(report: &GlobalsReport) -> Vec<(String, GlobalAccessKind, String, usize)> {
+        report
+            .accesses
+            .iter()
+            .map(|access| {
+                (
+                    access.name.clone(),
+                    access.kind,
+                    access.path.to_string(),
+                    access.pc,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_globals_through_env_registers() {
+        let file = LuaFile::parse(include_bytes!("../../tests/globals.luac"))
+            .unwrap()
+            .1;
+        let report = GlobalsReport::build(&file);
+        use GlobalAccessKind::{Read, Write};
+        assert_eq!(
+            accesses(&report),
+            vec![
+                ("print".to_string(), Read, "main".to_string(), 2),
+                ("os".to_string(), Read, "main".to_string(), 3),
+                ("answer".to_string(), Write, "main".to_string(), 7),
+                ("loaded".to_string(), Read, "main".to_string(), 9),
+                ("debug".to_string(), Write, "main".to_string(), 10),
+                ("print".to_string(), Read, "main/0".to_string(), 2),
+                ("print".to_string(), Read, "main/0".to_string(), 4),
+                ("load".to_string(), Read, "main/0".to_string(), 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_globals_report_lines_and_usage() {
+        let file = LuaFile::parse(include_bytes!("../../tests/all_opcodes.luac"))
+            .unwrap()
+            .1;
+        let report = GlobalsReport::build(&file);
+        assert_eq!(
+            report.accesses[0],
+            GlobalAccess {
+                name: "hello".to_string(),
+                kind: GlobalAccessKind::Read,
+                path: ProtoPath::main(),
+                pc: 16,
+                line: Some(12),
+            }
+        );
+        let usage = report.usage();
+        assert_eq!(usage["hello"].writes, 1);
+        assert_eq!(usage["ipairs"].reads, 1);
+        assert_eq!(usage["a"].writes, 30);
+        assert!(report
+            .to_string()
+            .contains("  main/2       pc 1     line 85    read  ipairs"));
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+
+use crate::{
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    instruction_parsing::instruction::Instruction,
+    lua_file::LuaFile,
+};
+
+pub mod audit;
+pub mod call_graph;
+pub mod diff;
+pub mod globals;
+pub mod similarity;
+pub mod stats;
+pub mod strings;
+
+pub(crate) const ENV_UPVALUE_NAME: &str = "_ENV";
+
+/// Checks whether upvalue `index` of the function block at `path` is the
+/// `_ENV` upvalue that global variables are resolved through.
+/// Uses the upvalue names when they were kept, and otherwise follows the
+/// upvalue back to the single upvalue of the main function block.
+pub fn is_env_upvalue(file: &LuaFile, path: &ProtoPath, index: u8) -> bool {
+    let Some(function) = file.function_at(path) else {
+        return false;
+    };
+    if let Some(Some(name)) = function.debug_info.upvalue_names.get(index as usize) {
+        return name == ENV_UPVALUE_NAME.as_bytes();
+    }
+    match (path.parent(), function.upvalues.get(index as usize)) {
+        (None, _) => index == 0,
+        (Some(parent), Some(upvalue)) if !upvalue.in_stack => {
+            is_env_upvalue(file, &parent, upvalue.index)
+        }
+        _ => false,
+    }
+}
+
+/// Decodes the instructions of a function block along with their pcs
+pub(crate) fn decode(
+    function: &FunctionBlockChunk,
+) -> impl Iterator<Item = (usize, Option<Instruction>)> + '_ {
+    function
+        .instructions
+        .iter()
+        .map(|instruction| Instruction::parse_u32(*instruction))
+        .enumerate()
+}
+
+/// Checks whether an instruction writes `register`
+pub(crate) fn writes_register(instruction: &Instruction, register: u8) -> bool {
+    match instruction.written_registers() {
+        Some((first, Some(count))) => {
+            first <= register && (register as usize) < first as usize + count as usize
+        }
+        Some((first, None)) => first <= register,
+        None => false,
+    }
+}
+
+/// Pcs control flow can reach from somewhere other than the previous instruction
+pub(crate) fn block_leaders(function: &FunctionBlockChunk) -> HashSet<usize> {
+    let mut leaders = HashSet::new();
+    for (pc, instruction) in decode(function) {
+        let Some(instruction) = instruction else {
+            continue;
+        };
+        if let Some(target) = instruction.jump_target(pc) {
+            leaders.insert(target);
+        }
+        if instruction.is_test() || matches!(instruction, Instruction::LFalseSkip(_)) {
+            leaders.insert(pc + 2);
+        }
+    }
+    leaders
+}
+
+/// A run of instructions entered only at its first and left only after its
+/// last
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// The pc after the last instruction
+    pub end: usize,
+    /// Indices of the blocks control flow can go to next
+    pub successors: Vec<usize>,
+}
+
+/// Pcs control flow can go to after the instruction at `pc`, which may be
+/// past the last instruction
+fn successors(pc: usize, instruction: Option<&Instruction>) -> Vec<usize> {
+    let Some(instruction) = instruction else {
+        return vec![pc + 1];
+    };
+    match instruction {
+        Instruction::Return(..) | Instruction::Return0() | Instruction::Return1(_) => vec![],
+        Instruction::Jmp(_) | Instruction::TForPrep(..) => {
+            instruction.jump_target(pc).into_iter().collect()
+        }
+        Instruction::ForPrep(..) | Instruction::ForLoop(..) | Instruction::TForLoop(..) => [pc + 1]
+            .into_iter()
+            .chain(instruction.jump_target(pc))
+            .collect(),
+        _ if instruction.is_test() || matches!(instruction, Instruction::LFalseSkip(_)) => {
+            vec![pc + 1, pc + 2]
+        }
+        _ => vec![pc + 1],
+    }
+}
+
+/// Splits the instructions of a function block into basic blocks, in pc order
+pub fn basic_blocks(function: &FunctionBlockChunk) -> Vec<BasicBlock> {
+    let length = function.instructions.len();
+    let instructions: Vec<_> = decode(function).collect();
+    let mut starts = block_leaders(function);
+    starts.insert(0);
+    for (pc, instruction) in &instructions {
+        if successors(*pc, instruction.as_ref()) != [pc + 1] {
+            starts.insert(pc + 1);
+        }
+    }
+    let mut starts: Vec<_> = starts.into_iter().filter(|pc| *pc < length).collect();
+    starts.sort_unstable();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, start)| {
+            let end = starts.get(index + 1).copied().unwrap_or(length);
+            let successors = successors(end - 1, instructions[end - 1].1.as_ref())
+                .into_iter()
+                .filter_map(|pc| starts.binary_search(&pc).ok())
+                .collect();
+            BasicBlock {
+                start: *start,
+                end,
+                successors,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lua_file::LuaFile;
+
+    use super::{basic_blocks, BasicBlock};
+
+    #[test]
+    fn test_basic_blocks() {
+        let file = LuaFile::parse(include_bytes!("../../tests/control_flow.luac"))
+            .unwrap()
+            .1;
+        let blocks = basic_blocks(&file.main_function_block);
+        assert_eq!(blocks[0].start, 0);
+        assert_eq!(
+            blocks.last().map(|block| block.end),
+            Some(file.main_function_block.instructions.len())
+        );
+        for (index, block) in blocks.iter().enumerate().skip(1) {
+            assert_eq!(block.start, blocks[index - 1].end);
+        }
+        assert!(blocks.iter().any(|block| block.successors.len() == 2));
+        assert!(matches!(
+            blocks.last(),
+            Some(BasicBlock { successors, .. }) if successors.is_empty()
+        ));
+    }
+}
@@ -0,0 +1,291 @@
+use crate::{
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    common_structs::constant::LuaConstant,
+    lua_file::LuaFile,
+};
+
+use super::basic_blocks;
+
+/// Number of consecutive opcodes hashed together
+const NGRAM_LENGTH: usize = 3;
+/// How much each part of the fingerprint counts in the similarity score
+const NGRAM_WEIGHT: f64 = 0.5;
+const CONSTANT_WEIGHT: f64 = 0.3;
+const CFG_WEIGHT: f64 = 0.2;
+/// Lowest score for two function blocks to be paired
+pub const MIN_SCORE: f64 = 0.5;
+
+/// 64-bit FNV-1a, so that fingerprints do not change between builds of this
+/// crate
+fn hash_bytes(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn hash_values(values: impl IntoIterator<Item = u64>) -> u64 {
+    values.into_iter().fold(0xcbf29ce484222325, |hash, value| {
+        hash_bytes(hash, &value.to_le_bytes())
+    })
+}
+
+fn hash_constant(constant: &LuaConstant) -> u64 {
+    let (tag, bytes) = match constant {
+        LuaConstant::Nil => (0, vec![]),
+        LuaConstant::Boolean(value) => (1, vec![*value as u8]),
+        LuaConstant::Number(value) => (3, value.to_le_bytes().to_vec()),
+        LuaConstant::Integer(value) => (0x13, value.to_le_bytes().to_vec()),
+        LuaConstant::String(value) => (4, value.clone()),
+    };
+    hash_bytes(hash_values([tag]), &bytes)
+}
+
+/// Size of the intersection of two sorted multisets over that of their union,
+/// 1 when both are empty
+fn jaccard(first: &[u64], second: &[u64]) -> f64 {
+    if first.is_empty() && second.is_empty() {
+        return 1.0;
+    }
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < first.len() && j < second.len() {
+        match first[i].cmp(&second[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    common as f64 / (first.len() + second.len() - common) as f64
+}
+
+/// What a function block does, leaving out its position in the file and its
+/// debug information, so that it survives moving the function and stripping
+#[derive(Debug, PartialEq, Clone)]
+pub struct Fingerprint {
+    /// Hashes of every run of `NGRAM_LENGTH` opcodes, sorted
+    pub opcode_ngrams: Vec<u64>,
+    pub opcode_hash: u64,
+    /// Hashes of the distinct constants, sorted
+    pub constants: Vec<u64>,
+    pub constant_hash: u64,
+    /// Number of basic blocks and of edges between them
+    pub blocks: usize,
+    pub edges: usize,
+    /// Hash of the successors of every basic block, relative to the block
+    pub cfg_hash: u64,
+}
+
+impl Fingerprint {
+    pub fn build(function: &FunctionBlockChunk) -> Self {
+        let opcodes: Vec<_> = function
+            .instructions
+            .iter()
+            .map(|instruction| (instruction & 0x7f) as u64)
+            .collect();
+        let mut opcode_ngrams: Vec<_> = opcodes
+            .windows(NGRAM_LENGTH.min(opcodes.len()).max(1))
+            .map(|ngram| hash_values(ngram.iter().copied()))
+            .collect();
+        opcode_ngrams.sort_unstable();
+        let mut constants: Vec<_> = function.constants.iter().map(hash_constant).collect();
+        constants.sort_unstable();
+        constants.dedup();
+        let blocks = basic_blocks(function);
+        let cfg_hash = hash_values(blocks.iter().enumerate().flat_map(|(index, block)| {
+            // The count first, so that the offsets of two blocks cannot merge
+            [block.successors.len() as u64].into_iter().chain(
+                block
+                    .successors
+                    .iter()
+                    .map(move |successor| (*successor as i64 - index as i64) as u64),
+            )
+        }));
+        Fingerprint {
+            opcode_hash: hash_values(opcode_ngrams.iter().copied()),
+            opcode_ngrams,
+            constant_hash: hash_values(constants.iter().copied()),
+            constants,
+            blocks: blocks.len(),
+            edges: blocks.iter().map(|block| block.successors.len()).sum(),
+            cfg_hash,
+        }
+    }
+
+    /// Whether the two fingerprints are the same in every hash
+    pub fn is_identical(&self, other: &Self) -> bool {
+        self.opcode_hash == other.opcode_hash
+            && self.constant_hash == other.constant_hash
+            && self.cfg_hash == other.cfg_hash
+    }
+
+    /// How alike the two function blocks are, from 0 to 1
+    pub fn similarity(&self, other: &Self) -> f64 {
+        let cfg = if self.cfg_hash == other.cfg_hash {
+            1.0
+        } else {
+            let ratio = |first: usize, second: usize| {
+                first.min(second) as f64 / first.max(second).max(1) as f64
+            };
+            // Differently shaped graphs of the same size still count for half
+            (ratio(self.blocks, other.blocks) + ratio(self.edges, other.edges)) / 4.0
+        };
+        NGRAM_WEIGHT * jaccard(&self.opcode_ngrams, &other.opcode_ngrams)
+            + CONSTANT_WEIGHT * jaccard(&self.constants, &other.constants)
+            + CFG_WEIGHT * cfg
+    }
+}
+
+/// A function block of one file paired with a function block of another
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionMatch {
+    pub old: ProtoPath,
+    pub new: ProtoPath,
+    pub score: f64,
+}
+
+/// Pairs the function blocks of two builds of the same source by their
+/// fingerprints, whatever their paths and lines. Fingerprints found once in
+/// each file are paired first, then the most similar pairs scoring at least
+/// `MIN_SCORE`. The matches are in the order of the old function blocks.
+pub fn match_functions(old: &LuaFile, new: &LuaFile) -> Vec<FunctionMatch> {
+    let fingerprints = |file: &LuaFile| -> Vec<(ProtoPath, Fingerprint)> {
+        file.functions()
+            .into_iter()
+            .map(|(path, function)| (path, Fingerprint::build(function)))
+            .collect()
+    };
+    let old_functions = fingerprints(old);
+    let new_functions = fingerprints(new);
+    let mut matches: Vec<(usize, usize, f64)> = vec![];
+    let mut old_matched = vec![false; old_functions.len()];
+    let mut new_matched = vec![false; new_functions.len()];
+    let unique = |functions: &[(ProtoPath, Fingerprint)], fingerprint: &Fingerprint| {
+        let mut identical = functions
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, other))| other.is_identical(fingerprint));
+        match (identical.next(), identical.next()) {
+            (Some((index, _)), None) => Some(index),
+            _ => None,
+        }
+    };
+    for (old_index, (_, fingerprint)) in old_functions.iter().enumerate() {
+        if unique(&old_functions, fingerprint).is_none() {
+            continue;
+        }
+        if let Some(new_index) = unique(&new_functions, fingerprint) {
+            matches.push((old_index, new_index, 1.0));
+            old_matched[old_index] = true;
+            new_matched[new_index] = true;
+        }
+    }
+    let mut candidates = vec![];
+    for (old_index, (_, old_fingerprint)) in old_functions.iter().enumerate() {
+        for (new_index, (_, new_fingerprint)) in new_functions.iter().enumerate() {
+            if old_matched[old_index] || new_matched[new_index] {
+                continue;
+            }
+            let score = old_fingerprint.similarity(new_fingerprint);
+            if score >= MIN_SCORE {
+                candidates.push((old_index, new_index, score));
+            }
+        }
+    }
+    // Best scores first, ties going to the closest positions in the files
+    candidates.sort_by(|first, second| {
+        second
+            .2
+            .total_cmp(&first.2)
+            .then_with(|| first.0.abs_diff(first.1).cmp(&second.0.abs_diff(second.1)))
+    });
+    for (old_index, new_index, score) in candidates {
+        if !old_matched[old_index] && !new_matched[new_index] {
+            matches.push((old_index, new_index, score));
+            old_matched[old_index] = true;
+            new_matched[new_index] = true;
+        }
+    }
+    matches.sort_by_key(|(old_index, _, _)| *old_index);
+    matches
+        .into_iter()
+        .map(|(old_index, new_index, score)| FunctionMatch {
+            old: old_functions[old_index].0.clone(),
+            new: new_functions[new_index].0.clone(),
+            score,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+        common_structs::{constant::LuaConstant, debug_info::DebugInfo},
+        lua_file::LuaFile,
+    };
+
+    use super::{match_functions, Fingerprint};
+
+    #[test]
+    fn test_fingerprint() {
+        let file = LuaFile::parse(include_bytes!("../../tests/closures.luac"))
+            .unwrap()
+            .1;
+        let main = Fingerprint::build(&file.main_function_block);
+        let increment = Fingerprint::build(&file.main_function_block.protos[0]);
+        assert_eq!(main.similarity(&main), 1.0);
+        assert!(main.similarity(&increment) < 0.5);
+        assert_eq!(increment.opcode_ngrams.len(), 5);
+        // The `Return0` after `Return1` is a block of its own
+        assert_eq!((increment.blocks, increment.edges), (2, 0));
+    }
+
+    #[test]
+    fn test_match_functions() {
+        let debug = LuaFile::parse(include_bytes!("../../tests/closures.luac"))
+            .unwrap()
+            .1;
+        // A release build: stripped, with the functions in another order and
+        // the report going elsewhere
+        let mut release = LuaFile::parse(include_bytes!("../../tests/closures.luac"))
+            .unwrap()
+            .1;
+        let main = &mut release.main_function_block;
+        main.protos.reverse();
+        main.constants[0] = LuaConstant::String(b"log".to_vec());
+        let strip = |function: &mut FunctionBlockChunk| {
+            function.debug_info = DebugInfo {
+                line_info: vec![],
+                abs_line_info: vec![],
+                local_vars: vec![],
+                upvalue_names: vec![],
+            };
+            function.source_line_start += 100;
+            function.source_line_end += 100;
+        };
+        main.protos.iter_mut().for_each(strip);
+        strip(main);
+        let matches = match_functions(&debug, &release);
+        let pairs: Vec<_> = matches
+            .iter()
+            .map(|found| (found.old.to_string(), found.new.to_string()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                ("main", "main"),
+                ("main/0", "main/3"),
+                ("main/1", "main/2"),
+                ("main/2", "main/1"),
+                ("main/3", "main/0"),
+            ]
+            .map(|(old, new)| (old.to_string(), new.to_string()))
+        );
+        assert!(matches[0].score < 1.0 && matches[0].score >= 0.5);
+        assert_eq!(matches[1].score, 1.0);
+        assert_eq!(matches[4].new, ProtoPath(vec![0]));
+    }
+}
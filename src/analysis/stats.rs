@@ -0,0 +1,397 @@
+use std::{collections::BTreeMap, fmt::Display, ops::AddAssign};
+
+use num::FromPrimitive;
+
+use crate::{
+    binary_chunks::{
+        function_block::FunctionBlockChunk, header::HeaderChunk, proto_path::ProtoPath,
+    },
+    common_structs::{constant::LuaConstant, size_t::lua_size_t_size, string::lua_string_size},
+    instruction_parsing::opcodes::Opcode,
+    lua_file::LuaFile,
+};
+
+/// Number of constants of each type
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+pub struct ConstantCounts {
+    pub nil: usize,
+    pub boolean: usize,
+    pub number: usize,
+    pub integer: usize,
+    pub string: usize,
+}
+
+impl ConstantCounts {
+    pub fn total(&self) -> usize {
+        self.nil + self.boolean + self.number + self.integer + self.string
+    }
+}
+
+impl AddAssign for ConstantCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.nil += other.nil;
+        self.boolean += other.boolean;
+        self.number += other.number;
+        self.integer += other.integer;
+        self.string += other.string;
+    }
+}
+
+/// Number of bytes each section of a function block takes in the binary chunk.
+/// Nested function blocks are not included, only the length of their list.
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+pub struct SectionSizes {
+    pub header: usize,
+    pub code: usize,
+    pub constants: usize,
+    pub upvalues: usize,
+    pub protos: usize,
+    pub debug: usize,
+}
+
+impl SectionSizes {
+    pub fn total(&self) -> usize {
+        self.header + self.code + self.constants + self.upvalues + self.protos + self.debug
+    }
+}
+
+impl AddAssign for SectionSizes {
+    fn add_assign(&mut self, other: Self) {
+        self.header += other.header;
+        self.code += other.code;
+        self.constants += other.constants;
+        self.upvalues += other.upvalues;
+        self.protos += other.protos;
+        self.debug += other.debug;
+    }
+}
+
+/// Statistics of a single function block
+#[derive(Debug, PartialEq)]
+pub struct FunctionStats {
+    pub path: ProtoPath,
+    pub instructions: usize,
+    pub opcodes: BTreeMap<Opcode, usize>,
+    /// Instructions with an opcode unknown to Lua 5.4
+    pub unknown_opcodes: usize,
+    pub constants: ConstantCounts,
+    pub upvalues: usize,
+    pub protos: usize,
+    pub maximum_stack_size: u8,
+    pub has_line_info: bool,
+    pub has_local_names: bool,
+    pub has_upvalue_names: bool,
+    pub sizes: SectionSizes,
+}
+
+impl FunctionStats {
+    pub fn build(path: ProtoPath, function: &FunctionBlockChunk) -> Self {
+        let mut opcodes = BTreeMap::new();
+        let mut unknown_opcodes = 0;
+        for instruction in &function.instructions {
+            match Opcode::from_u32(instruction & 0x7f) {
+                Some(opcode) => *opcodes.entry(opcode).or_default() += 1,
+                None => unknown_opcodes += 1,
+            }
+        }
+        let mut constants = ConstantCounts::default();
+        for constant in &function.constants {
+            match constant {
+                LuaConstant::Nil => constants.nil += 1,
+                LuaConstant::Boolean(_) => constants.boolean += 1,
+                LuaConstant::Number(_) => constants.number += 1,
+                LuaConstant::Integer(_) => constants.integer += 1,
+                LuaConstant::String(_) => constants.string += 1,
+            }
+        }
+        let debug_info = &function.debug_info;
+        FunctionStats {
+            path,
+            instructions: function.instructions.len(),
+            opcodes,
+            unknown_opcodes,
+            constants,
+            upvalues: function.upvalues.len(),
+            protos: function.protos.len(),
+            maximum_stack_size: function.maximum_stack_size,
+            has_line_info: !debug_info.line_info.is_empty(),
+            has_local_names: debug_info
+                .local_vars
+                .iter()
+                .any(|local_var| local_var.name.is_some()),
+            has_upvalue_names: debug_info.upvalue_names.iter().any(Option::is_some),
+            sizes: section_sizes(function),
+        }
+    }
+
+    /// How deep the function block is nested, the main function block being 0
+    pub fn depth(&self) -> usize {
+        self.path.depth()
+    }
+
+    pub fn has_debug_info(&self) -> bool {
+        self.has_line_info || self.has_local_names || self.has_upvalue_names
+    }
+}
+
+fn vector_size<T>(items: &[T], item_size: impl Fn(&T) -> usize) -> usize {
+    lua_size_t_size(items.len() as u64) + items.iter().map(item_size).sum::<usize>()
+}
+
+fn section_sizes(function: &FunctionBlockChunk) -> SectionSizes {
+    let debug_info = &function.debug_info;
+    SectionSizes {
+        header: lua_string_size(function.source_name.as_deref())
+            + lua_size_t_size(function.source_line_start)
+            + lua_size_t_size(function.source_line_end)
+            + 3,
+        code: vector_size(&function.instructions, |_| 4),
+        constants: vector_size(&function.constants, |constant| {
+            1 + match constant {
+                LuaConstant::Nil | LuaConstant::Boolean(_) => 0,
+                LuaConstant::Number(_) | LuaConstant::Integer(_) => 8,
+                LuaConstant::String(string) => lua_string_size(Some(string)),
+            }
+        }),
+        upvalues: vector_size(&function.upvalues, |_| 3),
+        protos: lua_size_t_size(function.protos.len() as u64),
+        debug: vector_size(&debug_info.line_info, |_| 1)
+            + vector_size(&debug_info.abs_line_info, |abs_line_info| {
+                lua_size_t_size(abs_line_info.pc) + lua_size_t_size(abs_line_info.line)
+            })
+            + vector_size(&debug_info.local_vars, |local_var| {
+                lua_string_size(local_var.name.as_deref())
+                    + lua_size_t_size(local_var.start_pc)
+                    + lua_size_t_size(local_var.end_pc)
+            })
+            + vector_size(&debug_info.upvalue_names, |name| {
+                lua_string_size(name.as_deref())
+            }),
+    }
+}
+
+/// Statistics of a compiled file and each of its function blocks
+#[derive(Debug, PartialEq)]
+pub struct FileStats {
+    /// Size of the header and of the main function block upvalue count
+    pub header_size: usize,
+    pub functions: Vec<FunctionStats>,
+}
+
+impl FileStats {
+    pub fn build(file: &LuaFile) -> Self {
+        FileStats {
+            header_size: HeaderChunk::SIZE + 1,
+            functions: file
+                .functions()
+                .into_iter()
+                .map(|(path, function)| FunctionStats::build(path, function))
+                .collect(),
+        }
+    }
+
+    pub fn instructions(&self) -> usize {
+        self.functions
+            .iter()
+            .map(|function| function.instructions)
+            .sum()
+    }
+
+    /// Number of instructions of each opcode across every function block
+    pub fn opcodes(&self) -> BTreeMap<Opcode, usize> {
+        let mut opcodes = BTreeMap::new();
+        for function in &self.functions {
+            for (opcode, count) in &function.opcodes {
+                *opcodes.entry(*opcode).or_default() += count;
+            }
+        }
+        opcodes
+    }
+
+    pub fn unknown_opcodes(&self) -> usize {
+        self.functions
+            .iter()
+            .map(|function| function.unknown_opcodes)
+            .sum()
+    }
+
+    pub fn constants(&self) -> ConstantCounts {
+        let mut constants = ConstantCounts::default();
+        for function in &self.functions {
+            constants += function.constants;
+        }
+        constants
+    }
+
+    pub fn upvalues(&self) -> usize {
+        self.functions
+            .iter()
+            .map(|function| function.upvalues)
+            .sum()
+    }
+
+    pub fn max_nesting_depth(&self) -> usize {
+        self.functions
+            .iter()
+            .map(FunctionStats::depth)
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub fn max_stack_size(&self) -> u8 {
+        self.functions
+            .iter()
+            .map(|function| function.maximum_stack_size)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Section sizes summed over every function block
+    pub fn sizes(&self) -> SectionSizes {
+        let mut sizes = SectionSizes::default();
+        for function in &self.functions {
+            sizes += function.sizes;
+        }
+        sizes
+    }
+
+    /// Number of bytes of the whole binary chunk
+    pub fn total_size(&self) -> usize {
+        self.header_size + self.sizes().total()
+    }
+}
+
+impl Display for FileStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<16} {:>5} {:>6} {:>6} {:>6} {:>6} {:>5} {:>5} {:>7}",
+            "function", "depth", "instrs", "consts", "upvals", "protos", "stack", "debug", "bytes"
+        )?;
+        for function in &self.functions {
+            writeln!(
+                f,
+                "{:<16} {:>5} {:>6} {:>6} {:>6} {:>6} {:>5} {:>5} {:>7}",
+                function.path.to_string(),
+                function.depth(),
+                function.instructions,
+                function.constants.total(),
+                function.upvalues,
+                function.protos,
+                function.maximum_stack_size,
+                if function.has_debug_info() {
+                    "yes"
+                } else {
+                    "no"
+                },
+                function.sizes.total()
+            )?;
+        }
+        writeln!(
+            f,
+            "{:<16} {:>5} {:>6} {:>6} {:>6} {:>6} {:>5} {:>5} {:>7}",
+            "total",
+            self.max_nesting_depth(),
+            self.instructions(),
+            self.constants().total(),
+            self.upvalues(),
+            self.functions.len(),
+            self.max_stack_size(),
+            "",
+            self.total_size()
+        )?;
+
+        let constants = self.constants();
+        writeln!(f)?;
+        writeln!(f, "Constants:")?;
+        for (name, count) in [
+            ("nil", constants.nil),
+            ("boolean", constants.boolean),
+            ("number", constants.number),
+            ("integer", constants.integer),
+            ("string", constants.string),
+        ] {
+            writeln!(f, "  {:<12} {:>6}", name, count)?;
+        }
+
+        let sizes = self.sizes();
+        writeln!(f)?;
+        writeln!(f, "Sections (bytes):")?;
+        for (name, size) in [
+            ("header", self.header_size + sizes.header),
+            ("code", sizes.code),
+            ("constants", sizes.constants),
+            ("upvalues", sizes.upvalues),
+            ("protos", sizes.protos),
+            ("debug", sizes.debug),
+        ] {
+            writeln!(f, "  {:<12} {:>6}", name, size)?;
+        }
+
+        let instructions = self.instructions().max(1);
+        writeln!(f)?;
+        writeln!(f, "Opcodes:")?;
+        let mut opcodes: Vec<_> = self.opcodes().into_iter().collect();
+        opcodes.sort_by(|(_, first), (_, second)| second.cmp(first));
+        for (opcode, count) in opcodes {
+            writeln!(
+                f,
+                "  {:<12} {:>6} {:>6.1}%",
+                format!("{:?}", opcode).to_uppercase(),
+                count,
+                count as f64 * 100.0 / instructions as f64
+            )?;
+        }
+        if self.unknown_opcodes() > 0 {
+            writeln!(f, "  {:<12} {:>6}", "unknown", self.unknown_opcodes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{instruction_parsing::opcodes::Opcode, lua_file::LuaFile};
+
+    use super::{ConstantCounts, FileStats};
+
+    #[test]
+    fn test_stats_of_all_opcodes() {
+        let data = include_bytes!("../../tests/all_opcodes.luac");
+        let file = LuaFile::parse(data).unwrap().1;
+        let stats = FileStats::build(&file);
+        assert_eq!(stats.total_size(), data.len());
+        assert_eq!(stats.functions.len(), 4);
+        assert_eq!(stats.max_nesting_depth(), 1);
+        assert_eq!(
+            stats.instructions(),
+            file.functions()
+                .iter()
+                .map(|(_, function)| function.instructions.len())
+                .sum::<usize>()
+        );
+        assert_eq!(stats.opcodes()[&Opcode::Closure], 3);
+        assert_eq!(stats.unknown_opcodes(), 0);
+        assert_eq!(stats.functions[0].constants.string, 5);
+        assert!(stats
+            .functions
+            .iter()
+            .all(|function| function.has_debug_info()));
+        let table = stats.to_string();
+        assert!(table.starts_with("function         depth instrs"));
+        assert!(table.contains("  CLOSURE           3"));
+        assert_eq!(ConstantCounts::default().total(), 0);
+    }
+
+    #[test]
+    fn test_stats_sizes_of_every_fixture() {
+        for data in [
+            &include_bytes!("../../tests/closures.luac")[..],
+            &include_bytes!("../../tests/globals.luac")[..],
+            &include_bytes!("../../tests/audit.luac")[..],
+        ] {
+            let file = LuaFile::parse(data).unwrap().1;
+            assert_eq!(FileStats::build(&file).total_size(), data.len());
+        }
+    }
+}
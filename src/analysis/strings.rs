@@ -0,0 +1,225 @@
+use std::fmt::Display;
+
+use crate::{
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    common_structs::constant::LuaConstant,
+    disassembler::quote_bytes,
+    instruction_parsing::instruction::Instruction,
+    lua_file::LuaFile,
+};
+
+use super::decode;
+
+/// Where an extracted string comes from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StringKind {
+    /// A string in the constant pool
+    Constant,
+    /// The name of a local variable, from the debug info
+    LocalName,
+    /// The name of an upvalue, from the debug info
+    UpvalueName,
+}
+
+impl Display for StringKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            StringKind::Constant => "constant",
+            StringKind::LocalName => "local",
+            StringKind::UpvalueName => "upvalue",
+        })
+    }
+}
+
+/// An instruction referencing an extracted string
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct StringReference {
+    pub pc: usize,
+    pub line: Option<u64>,
+}
+
+/// A string found in a function block
+#[derive(Debug, PartialEq)]
+pub struct ExtractedString {
+    /// The bytes of the string, which need not be UTF-8
+    pub value: Vec<u8>,
+    pub kind: StringKind,
+    pub path: ProtoPath,
+    /// Index in the constant pool, the local variables or the upvalues
+    pub index: usize,
+    /// Instructions using a constant, or the instruction a local starts at
+    pub references: Vec<StringReference>,
+}
+
+/// Every string of a compiled file, like `strings` for binaries
+#[derive(Debug, PartialEq, Default)]
+pub struct StringsReport {
+    pub strings: Vec<ExtractedString>,
+}
+
+impl StringsReport {
+    /// Extracts the string constants and debug names of every function block
+    pub fn build(file: &LuaFile) -> Self {
+        let mut report = StringsReport::default();
+        for (path, function) in file.functions() {
+            report.add_function(&path, function);
+        }
+        report
+    }
+
+    fn add_function(&mut self, path: &ProtoPath, function: &FunctionBlockChunk) {
+        let mut references = vec![Vec::new(); function.constants.len()];
+        let mut previous = None;
+        for (pc, instruction) in decode(function) {
+            let indices = match (&previous, &instruction) {
+                (Some(Instruction::LoadKx(_)), Some(Instruction::Extraarg(ax))) => vec![*ax],
+                (_, Some(instruction)) => instruction.constant_indices(),
+                _ => vec![],
+            };
+            for index in indices {
+                if let Some(constant_references) = references.get_mut(index as usize) {
+                    constant_references.push(StringReference {
+                        pc: pc - usize::from(matches!(previous, Some(Instruction::LoadKx(_)))),
+                        line: function.line_at(pc),
+                    });
+                }
+            }
+            previous = instruction;
+        }
+        for (index, (constant, references)) in function.constants.iter().zip(references).enumerate()
+        {
+            if let LuaConstant::String(value) = constant {
+                self.strings.push(ExtractedString {
+                    value: value.clone(),
+                    kind: StringKind::Constant,
+                    path: path.clone(),
+                    index,
+                    references,
+                });
+            }
+        }
+        for (index, local_var) in function.debug_info.local_vars.iter().enumerate() {
+            if let Some(name) = &local_var.name {
+                let pc = local_var.start_pc as usize;
+                self.strings.push(ExtractedString {
+                    value: name.clone(),
+                    kind: StringKind::LocalName,
+                    path: path.clone(),
+                    index,
+                    references: vec![StringReference {
+                        pc,
+                        line: function.line_at(pc),
+                    }],
+                });
+            }
+        }
+        for (index, name) in function.debug_info.upvalue_names.iter().enumerate() {
+            if let Some(name) = name {
+                self.strings.push(ExtractedString {
+                    value: name.clone(),
+                    kind: StringKind::UpvalueName,
+                    path: path.clone(),
+                    index,
+                    references: vec![],
+                });
+            }
+        }
+    }
+
+    /// The extracted strings of one kind
+    pub fn of_kind(&self, kind: StringKind) -> impl Iterator<Item = &ExtractedString> {
+        self.strings
+            .iter()
+            .filter(move |string| string.kind == kind)
+    }
+}
+
+impl Display for StringsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for string in &self.strings {
+            write!(
+                f,
+                "{:<12} {:<8} {:<5} {}",
+                string.path.to_string(),
+                string.kind,
+                string.index,
+                quote_bytes(&string.value)
+            )?;
+            for (position, reference) in string.references.iter().enumerate() {
+                let separator = if position == 0 { " @ " } else { ", " };
+                write!(f, "{}pc {}", separator, reference.pc)?;
+                if let Some(line) = reference.line {
+                    write!(f, " line {}", line)?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{binary_chunks::proto_path::ProtoPath, lua_file::LuaFile};
+
+    use super::{ExtractedString, StringKind, StringReference, StringsReport};
+
+    #[test]
+    fn test_strings_of_all_opcodes() {
+        let file = LuaFile::parse(include_bytes!("../../tests/all_opcodes.luac"))
+            .unwrap()
+            .1;
+        let report = StringsReport::build(&file);
+        let hello = report
+            .of_kind(StringKind::Constant)
+            .find(|string| string.value == b"hello")
+            .unwrap();
+        let pcs: Vec<_> = hello
+            .references
+            .iter()
+            .map(|reference| (reference.pc, reference.line))
+            .collect();
+        assert_eq!(hello.index, 1);
+        assert_eq!(
+            pcs,
+            vec![
+                (16, Some(12)),
+                (17, Some(13)),
+                (24, Some(19)),
+                (25, Some(20))
+            ]
+        );
+        let empty: Vec<_> = report
+            .of_kind(StringKind::Constant)
+            .find(|string| string.value.is_empty())
+            .unwrap()
+            .references
+            .iter()
+            .map(|reference| reference.pc)
+            .collect();
+        assert_eq!(empty, vec![17, 21, 23]);
+        assert_eq!(
+            report
+                .of_kind(StringKind::UpvalueName)
+                .map(|string| string.value.as_slice())
+                .collect::<Vec<_>>(),
+            vec![b"_ENV".as_slice(), b"x", b"x", b"_ENV"]
+        );
+        assert_eq!(
+            report.of_kind(StringKind::LocalName).nth(1),
+            Some(&ExtractedString {
+                value: b"f".to_vec(),
+                kind: StringKind::LocalName,
+                path: ProtoPath::main(),
+                index: 1,
+                references: vec![StringReference {
+                    pc: 3,
+                    line: Some(4)
+                }],
+            })
+        );
+        assert!(report
+            .to_string()
+            .contains("main/2       constant 0     \"ipairs\" @ pc 1 line 85\n"));
+    }
+}
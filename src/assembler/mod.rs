@@ -0,0 +1,924 @@
+//! Assembles Lua 5.4 bytecode from text.
+//!
+//! A file holds the main function, which may nest further functions:
+//!
+//! ```text
+//! ; Comments run to the end of the line
+//! .function main
+//! .vararg
+//! .upvalue _ENV 1 0
+//! .const "print"
+//!         VarargPrep 0
+//!         Closure 0 greet
+//!         Call 0 1 1
+//!         Return 0 1 1 1
+//!     .function greet
+//!     .lines 2 4
+//!     .upvalue _ENV 0 0
+//!     .const "print"
+//!     .const "hello"
+//!             GetTabup 0 0 0
+//!             LoadK 1 1
+//!             Call 0 2 1
+//!             Return0
+//!     .end
+//! .end
+//! ```
+//!
+//! Instructions are written with the `Opcode` mnemonics, in any case, and
+//! their operands in the order of `Opcode::operand_names`, trailing operands
+//! defaulting to 0. Numbers may be written in hexadecimal, as `0x1f`. Jumps
+//! take a label instead of their offset, and `Closure` the name of a function
+//! nested in the current one instead of its index.
+//!
+//! Directives, all optional:
+//! - `.source "@name.lua"`, `.lines <first> <last>`: where the function was
+//!   defined
+//! - `.params <count>`, `.vararg [flag]`: the parameters
+//! - `.stack <size>`: registers needed, by default the highest register the
+//!   instructions use plus one, and at least 2
+//! - `.const <value>`: the next constant, `nil`, `true`, `false`, an integer,
+//!   a float such as `1.0`, `inf` or `nan`, or a string
+//! - `.upvalue <name> <in stack: 0 or 1> <index> [kind]`: the next upvalue,
+//!   `-` standing for no name and the kind a `VariableKind`
+//! - `.local <name> <start> <end>`: a local variable, live between two pcs
+//!   or labels
+//! - `.line <line>`: the source line of the following instructions
+//! - `.lineinfo <delta>...`, `.abslineinfo <pc> <line>`: the line information
+//!   as stored, in place of the one computed from `.line`
+//! - `.word <instruction>`: an instruction given as a number
+//!
+//! Names that are not identifiers, such as `(for state)`, are quoted. Quoted
+//! strings take the escapes of Rust strings, and `\xNN` for any byte. Before
+//! the main function, `.header <version> <format> <instruction size>
+//! <integer size> <number size> [upvalues]` replaces the Lua 5.4 header.
+//!
+//! `LoadK` with a constant index too large for Bx is written as `LoadKx`
+//! followed by an `Extraarg`. `NewTable` and `SetList` with a C operand above
+//! 255 are written with the k flag and the rest of C in an `Extraarg`, which
+//! `NewTable` always needs. An `Extraarg` written right after them is kept as
+//! is.
+
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{
+    binary_chunks::{
+        function_block::{FunctionBlockChunk, IsVarargFlag},
+        header::HeaderChunk,
+    },
+    common_structs::{
+        constant::LuaConstant,
+        debug_info::{AbsLineInfo, DebugInfo, LocalVar},
+        upvalue::Upvalue,
+        variable_kind::VariableKind,
+    },
+    disassembler::quote_bytes,
+    instruction_parsing::{
+        instruction::Instruction, instruction_encodings::InstructionEncoding, opcodes::Opcode,
+    },
+    lua_file::LuaFile,
+};
+
+/// Largest value of the C operand
+const MAX_C: i64 = 0xff;
+/// Largest value of the Bx operand
+const MAX_BX: i64 = 0x1ffff;
+/// Line deltas this large are stored as absolute lines
+const LINE_DELTA_LIMIT: i64 = 0x80;
+/// Most instructions between two absolute lines
+const MAX_WITHOUT_ABSOLUTE_LINE: usize = 128;
+/// The line delta marking an absolute line
+const ABSOLUTE_LINE: i8 = -0x80;
+
+/// Why a source could not be assembled, with the line at fault
+#[derive(Debug, PartialEq, Clone)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// Assembles `source` into a file with a Lua 5.4 header, unless `.header`
+/// gives another
+pub fn assemble(source: &str) -> Result<LuaFile, AssembleError> {
+    let mut functions: Vec<FunctionBuilder> = vec![];
+    let mut main = None;
+    let mut header = None;
+    let mut last_line = 0;
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        last_line = line;
+        let error = |message: String| AssembleError { line, message };
+        let mut tokens = tokenize(text).map_err(error)?.into_iter().peekable();
+        // A label may precede an instruction on the same line
+        if let Some(Token::Word(word)) = tokens.peek() {
+            if let Some(label) = word.strip_suffix(':') {
+                let label = label.to_string();
+                tokens.next();
+                let function = functions
+                    .last_mut()
+                    .ok_or_else(|| error("label outside of a function".to_string()))?;
+                function.add_label(label).map_err(error)?;
+            }
+        }
+        let Some(first) = tokens.next() else {
+            continue;
+        };
+        let Token::Word(word) = first else {
+            return Err(error("expected a directive or an instruction".to_string()));
+        };
+        let arguments: Vec<_> = tokens.collect();
+        match word.as_str() {
+            ".header" if functions.is_empty() && main.is_none() => {
+                header = Some(parse_header(&arguments).map_err(error)?);
+            }
+            ".function" => {
+                if main.is_some() {
+                    return Err(error("only one main function is allowed".to_string()));
+                }
+                let [Token::Word(name)] = arguments.as_slice() else {
+                    return Err(error(".function takes a name".to_string()));
+                };
+                functions.push(FunctionBuilder::new(name.clone(), line));
+            }
+            ".end" => {
+                let function = functions
+                    .pop()
+                    .ok_or_else(|| error(".end outside of a function".to_string()))?;
+                let name = function.name.clone();
+                let chunk = function.finish()?;
+                match functions.last_mut() {
+                    Some(parent) => parent.add_proto(name, chunk).map_err(error)?,
+                    None => main = Some(chunk),
+                }
+            }
+            _ => {
+                let function = functions
+                    .last_mut()
+                    .ok_or_else(|| error(format!("{} outside of a function", word)))?;
+                if word.starts_with('.') {
+                    function.directive(&word, &arguments, line).map_err(error)?;
+                } else {
+                    function
+                        .instruction(&word, &arguments, line)
+                        .map_err(error)?;
+                }
+            }
+        }
+    }
+    if let Some(function) = functions.last() {
+        return Err(AssembleError {
+            line: function.line,
+            message: format!("function {} has no .end", function.name),
+        });
+    }
+    let main_function_block = main.ok_or_else(|| AssembleError {
+        line: last_line,
+        message: "no function".to_string(),
+    })?;
+    let (header, number_of_upvalues) = header.unwrap_or_default();
+    Ok(LuaFile {
+        header,
+        number_of_upvalues: number_of_upvalues.unwrap_or(main_function_block.upvalues.len() as u8),
+        main_function_block,
+    })
+}
+
+/// The arguments of `.header`, with the number of upvalues if given
+fn parse_header(arguments: &[Token]) -> Result<(HeaderChunk, Option<u8>), String> {
+    let (version, sizes) = match arguments {
+        [Token::Word(version), sizes @ ..] if (4..=5).contains(&sizes.len()) => (version, sizes),
+        _ => return Err("invalid arguments for .header".to_string()),
+    };
+    let version_number = version
+        .split_once('.')
+        .and_then(|(major, minor)| Some((major.parse::<u8>().ok()?, minor.parse::<u8>().ok()?)))
+        .filter(|(major, minor)| *major < 16 && *minor < 16)
+        .map(|(major, minor)| (major << 4 | minor).into())
+        .ok_or_else(|| format!("invalid version {}", version))?;
+    let sizes = sizes.iter().map(byte).collect::<Result<Vec<_>, _>>()?;
+    Ok((
+        HeaderChunk {
+            version_number,
+            format_version: sizes[0],
+            size_of_int: sizes[1],
+            size_of_size_t: sizes[2],
+            size_of_lua_number: sizes[3],
+        },
+        sizes.get(4).copied(),
+    ))
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Word(String),
+    /// The bytes of a quoted string, which need not be UTF-8
+    String(Vec<u8>),
+}
+
+/// Splits a line into words and strings, dropping commas and the comment
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            ';' => break,
+            ',' => {}
+            char if char.is_whitespace() => {}
+            '"' => {
+                let mut string = vec![];
+                loop {
+                    match chars.next() {
+                        None => return Err("unfinished string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => unescape(&mut chars, &mut string)?,
+                        Some(char) => {
+                            string.extend_from_slice(char.encode_utf8(&mut [0; 4]).as_bytes())
+                        }
+                    }
+                }
+                tokens.push(Token::String(string));
+            }
+            char => {
+                let mut word = char.to_string();
+                while let Some(char) =
+                    chars.next_if(|char| !char.is_whitespace() && !matches!(char, ',' | ';' | '"'))
+                {
+                    word.push(char);
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Adds to `string` what is escaped after a backslash, as `Debug` escapes
+/// strings, or the byte given by `\xNN`
+fn unescape(chars: &mut impl Iterator<Item = char>, string: &mut Vec<u8>) -> Result<(), String> {
+    let char = match chars.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('0') => '\0',
+        Some(char @ ('\\' | '"' | '\'')) => char,
+        Some('u') => {
+            let escape: String = chars.take_while(|char| *char != '}').collect();
+            escape
+                .strip_prefix('{')
+                .and_then(|code| u32::from_str_radix(code, 16).ok())
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("invalid escape \\u{}}}", escape))?
+        }
+        Some('x') => {
+            let escape: String = chars.take(2).collect();
+            let byte = u8::from_str_radix(&escape, 16)
+                .ok()
+                .filter(|_| escape.len() == 2)
+                .ok_or_else(|| format!("invalid escape \\x{}", escape))?;
+            string.push(byte);
+            return Ok(());
+        }
+        Some(char) => return Err(format!("invalid escape \\{}", char)),
+        None => return Err("unfinished string".to_string()),
+    };
+    string.extend_from_slice(char.encode_utf8(&mut [0; 4]).as_bytes());
+    Ok(())
+}
+
+/// An operand as written, resolved once the function is complete
+#[derive(Debug, PartialEq, Clone)]
+enum Operand {
+    Number(i64),
+    Name(String),
+}
+
+impl Operand {
+    fn parse(token: &Token) -> Result<Self, String> {
+        match token {
+            Token::Word(word) => Ok(match parse_integer(word) {
+                Some(number) => Operand::Number(number),
+                None => Operand::Name(word.clone()),
+            }),
+            Token::String(string) => Err(format!("unexpected string {}", quote_bytes(string))),
+        }
+    }
+}
+
+/// Parses a decimal or `0x` hexadecimal integer
+fn parse_integer(word: &str) -> Option<i64> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, word),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn number(token: &Token) -> Result<i64, String> {
+    match Operand::parse(token)? {
+        Operand::Number(number) => Ok(number),
+        Operand::Name(name) => Err(format!("expected a number, found {}", name)),
+    }
+}
+
+fn byte(token: &Token) -> Result<u8, String> {
+    let value = number(token)?;
+    u8::try_from(value).map_err(|_| format!("{} does not fit in a byte", value))
+}
+
+fn unsigned(token: &Token) -> Result<u64, String> {
+    let value = number(token)?;
+    u64::try_from(value).map_err(|_| format!("{} is negative", value))
+}
+
+/// A variable name, `-` standing for none unless quoted
+fn name(token: &Token) -> Option<Vec<u8>> {
+    match token {
+        Token::Word(word) if word == "-" => None,
+        Token::Word(name) => Some(name.as_bytes().to_vec()),
+        Token::String(name) => Some(name.clone()),
+    }
+}
+
+/// An instruction as written
+struct PendingInstruction {
+    line: usize,
+    code: Code,
+    source_line: Option<u64>,
+}
+
+enum Code {
+    Instruction(Opcode, Vec<Operand>),
+    /// An instruction given by `.word`
+    Word(u32),
+}
+
+/// A function being assembled
+struct FunctionBuilder {
+    name: String,
+    /// Where the function starts
+    line: usize,
+    source_name: Option<Vec<u8>>,
+    source_lines: (u64, u64),
+    number_of_parameters: u8,
+    is_vararg: u8,
+    maximum_stack_size: Option<u8>,
+    constants: Vec<LuaConstant>,
+    upvalues: Vec<Upvalue>,
+    upvalue_names: Vec<Option<Vec<u8>>>,
+    line_info: Option<Vec<i8>>,
+    abs_line_info: Vec<AbsLineInfo>,
+    /// Locals with the line declaring them and their start and end
+    locals: Vec<(usize, Option<Vec<u8>>, Operand, Operand)>,
+    instructions: Vec<PendingInstruction>,
+    /// The index in `instructions` of the instruction after each label
+    labels: HashMap<String, usize>,
+    protos: Vec<FunctionBlockChunk>,
+    proto_names: HashMap<String, usize>,
+    source_line: Option<u64>,
+}
+
+impl FunctionBuilder {
+    fn new(name: String, line: usize) -> Self {
+        FunctionBuilder {
+            name,
+            line,
+            source_name: None,
+            source_lines: (0, 0),
+            number_of_parameters: 0,
+            is_vararg: 0,
+            maximum_stack_size: None,
+            constants: vec![],
+            upvalues: vec![],
+            upvalue_names: vec![],
+            line_info: None,
+            abs_line_info: vec![],
+            locals: vec![],
+            instructions: vec![],
+            labels: HashMap::new(),
+            protos: vec![],
+            proto_names: HashMap::new(),
+            source_line: None,
+        }
+    }
+
+    fn add_label(&mut self, label: String) -> Result<(), String> {
+        if self.labels.contains_key(&label) {
+            return Err(format!("label {} is already defined", label));
+        }
+        self.labels.insert(label, self.instructions.len());
+        Ok(())
+    }
+
+    fn add_proto(&mut self, name: String, proto: FunctionBlockChunk) -> Result<(), String> {
+        if self.proto_names.contains_key(&name) {
+            return Err(format!("function {} is already defined", name));
+        }
+        self.proto_names.insert(name, self.protos.len());
+        self.protos.push(proto);
+        Ok(())
+    }
+
+    fn directive(
+        &mut self,
+        directive: &str,
+        arguments: &[Token],
+        line: usize,
+    ) -> Result<(), String> {
+        match (directive, arguments) {
+            (".source", [Token::String(name)]) => self.source_name = Some(name.clone()),
+            (".lines", [first, last]) => self.source_lines = (unsigned(first)?, unsigned(last)?),
+            (".params", [count]) => self.number_of_parameters = byte(count)?,
+            (".vararg", []) => self.is_vararg = 1,
+            (".vararg", [flag]) => self.is_vararg = byte(flag)?,
+            (".stack", [size]) => self.maximum_stack_size = Some(byte(size)?),
+            (".const", [value]) => self.constants.push(parse_constant(value)?),
+            (".upvalue", [variable, in_stack, index, kind @ ..]) if kind.len() <= 1 => {
+                let kind = match kind {
+                    [Token::Word(kind)] => VariableKind::from_name(kind)
+                        .ok_or_else(|| format!("unknown variable kind {}", kind))?,
+                    [_] => return Err("expected a variable kind".to_string()),
+                    _ => VariableKind::Regular,
+                };
+                let in_stack = match byte(in_stack)? {
+                    0 => false,
+                    1 => true,
+                    _ => return Err("in stack must be 0 or 1".to_string()),
+                };
+                self.upvalues.push(Upvalue {
+                    in_stack,
+                    index: byte(index)?,
+                    kind,
+                });
+                self.upvalue_names.push(name(variable));
+            }
+            (".local", [variable, start, end]) => self.locals.push((
+                line,
+                name(variable),
+                Operand::parse(start)?,
+                Operand::parse(end)?,
+            )),
+            (".line", [line]) => self.source_line = Some(unsigned(line)?),
+            (".lineinfo", deltas) => {
+                for delta in deltas {
+                    let delta = number(delta)?;
+                    let delta = i8::try_from(delta)
+                        .map_err(|_| format!("line delta {} is out of range", delta))?;
+                    self.line_info.get_or_insert_with(Vec::new).push(delta);
+                }
+            }
+            (".abslineinfo", [pc, line]) => self.abs_line_info.push(AbsLineInfo {
+                pc: unsigned(pc)?,
+                line: unsigned(line)?,
+            }),
+            (".word", [instruction]) => {
+                let instruction = number(instruction)?;
+                let instruction = u32::try_from(instruction)
+                    .map_err(|_| format!("{} is not a 32-bit instruction", instruction))?;
+                self.instructions.push(PendingInstruction {
+                    line,
+                    code: Code::Word(instruction),
+                    source_line: self.source_line,
+                });
+            }
+            (
+                ".source" | ".lines" | ".params" | ".vararg" | ".stack" | ".const" | ".upvalue"
+                | ".local" | ".line" | ".abslineinfo" | ".word",
+                _,
+            ) => return Err(format!("invalid arguments for {}", directive)),
+            _ => return Err(format!("unknown directive {}", directive)),
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        mnemonic: &str,
+        arguments: &[Token],
+        line: usize,
+    ) -> Result<(), String> {
+        let opcode = Opcode::from_mnemonic(mnemonic)
+            .ok_or_else(|| format!("unknown instruction {}", mnemonic))?;
+        let operands = arguments
+            .iter()
+            .map(Operand::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if operands.len() > opcode.operand_names().len() {
+            return Err(format!(
+                "{} takes at most {} operands",
+                opcode.mnemonic(),
+                opcode.operand_names().len()
+            ));
+        }
+        self.instructions.push(PendingInstruction {
+            line,
+            code: Code::Instruction(opcode, operands),
+            source_line: self.source_line,
+        });
+        Ok(())
+    }
+
+    /// Whether the instruction at `index` is written with an `Extraarg` the
+    /// source does not have
+    fn needs_extraarg(&self, index: usize) -> bool {
+        let Code::Instruction(opcode, operands) = &self.instructions[index].code else {
+            return false;
+        };
+        let operand = |position: usize| match operands.get(position) {
+            Some(Operand::Number(number)) => *number,
+            _ => 0,
+        };
+        let followed_by_extraarg = self
+            .instructions
+            .get(index + 1)
+            .is_some_and(|next| matches!(next.code, Code::Instruction(Opcode::Extraarg, _)));
+        match opcode {
+            Opcode::LoadK => operand(1) > MAX_BX,
+            Opcode::NewTable => !followed_by_extraarg,
+            Opcode::SetList => operand(2) > MAX_C && !followed_by_extraarg,
+            _ => false,
+        }
+    }
+
+    fn finish(self) -> Result<FunctionBlockChunk, AssembleError> {
+        let error = |line: usize| move |message: String| AssembleError { line, message };
+        // The pc of every instruction and of the end of the function
+        let mut pcs = Vec::with_capacity(self.instructions.len() + 1);
+        let mut pc = 0;
+        for index in 0..self.instructions.len() {
+            pcs.push(pc);
+            pc += if self.needs_extraarg(index) { 2 } else { 1 };
+        }
+        pcs.push(pc);
+        let label_pc = |label: &str| {
+            self.labels
+                .get(label)
+                .map(|index| pcs[*index] as i64)
+                .ok_or_else(|| format!("unknown label {}", label))
+        };
+
+        let mut instructions = vec![];
+        let mut lines = vec![];
+        for (index, pending) in self.instructions.iter().enumerate() {
+            let (opcode, written_operands) = match &pending.code {
+                Code::Instruction(opcode, operands) => (*opcode, operands),
+                Code::Word(instruction) => {
+                    instructions.push(*instruction);
+                    lines.push(pending.source_line);
+                    continue;
+                }
+            };
+            let pc = pcs[index] as i64;
+            let jump = match opcode {
+                Opcode::Jmp => Some((0, -pc - 1)),
+                Opcode::ForPrep => Some((1, -pc - 2)),
+                Opcode::TForPrep => Some((1, -pc - 1)),
+                Opcode::ForLoop | Opcode::TForLoop => Some((1, pc + 1)),
+                _ => None,
+            };
+            let mut operands = vec![0; opcode.operand_names().len()];
+            for (position, operand) in written_operands.iter().enumerate() {
+                operands[position] = match operand {
+                    Operand::Number(number) => *number,
+                    Operand::Name(name) => match (opcode, jump) {
+                        (Opcode::ForLoop | Opcode::TForLoop, Some((jump_position, offset)))
+                            if jump_position == position =>
+                        {
+                            offset - label_pc(name).map_err(error(pending.line))?
+                        }
+                        (_, Some((jump_position, offset))) if jump_position == position => {
+                            label_pc(name).map_err(error(pending.line))? + offset
+                        }
+                        (Opcode::Closure, _) if position == 1 => {
+                            *self.proto_names.get(name).ok_or_else(|| AssembleError {
+                                line: pending.line,
+                                message: format!("unknown function {}", name),
+                            })? as i64
+                        }
+                        _ => {
+                            return Err(AssembleError {
+                                line: pending.line,
+                                message: format!("unexpected name {}", name),
+                            })
+                        }
+                    },
+                }
+            }
+            let mut opcode = opcode;
+            let mut extra = None;
+            if self.needs_extraarg(index) {
+                match opcode {
+                    Opcode::LoadK => {
+                        opcode = Opcode::LoadKx;
+                        extra = Some(operands[1]);
+                        operands[1] = 0;
+                    }
+                    _ => {
+                        // NewTable and SetList
+                        extra = Some(operands[2] / (MAX_C + 1));
+                        operands[3] = (operands[2] > MAX_C) as i64;
+                        operands[2] %= MAX_C + 1;
+                    }
+                }
+            }
+            let encoding = InstructionEncoding::from_operands(opcode, &operands)
+                .map_err(error(pending.line))?;
+            instructions.push(encoding.encode());
+            lines.push(pending.source_line);
+            if let Some(extra) = extra {
+                let encoding = InstructionEncoding::from_operands(Opcode::Extraarg, &[extra])
+                    .map_err(error(pending.line))?;
+                instructions.push(encoding.encode());
+                lines.push(pending.source_line);
+            }
+        }
+
+        let mut local_vars = vec![];
+        for (line, name, start, end) in &self.locals {
+            let pc = |operand: &Operand| match operand {
+                Operand::Number(number) => {
+                    u64::try_from(*number).map_err(|_| format!("{} is negative", number))
+                }
+                Operand::Name(label) => label_pc(label).map(|pc| pc as u64),
+            };
+            local_vars.push(LocalVar {
+                name: name.clone(),
+                start_pc: pc(start).map_err(error(*line))?,
+                end_pc: pc(end).map_err(error(*line))?,
+            });
+        }
+        let (line_info, abs_line_info) = match self.line_info {
+            Some(line_info) => (line_info, self.abs_line_info),
+            None if !self.abs_line_info.is_empty() => (vec![], self.abs_line_info),
+            None if lines.iter().any(Option::is_some) => line_info(self.source_lines.0, &lines),
+            None => (vec![], vec![]),
+        };
+        let maximum_stack_size = self.maximum_stack_size.unwrap_or_else(|| {
+            registers_used(&instructions)
+                .max(self.number_of_parameters)
+                .max(2)
+        });
+        // Stripped functions have no upvalue names
+        let upvalue_names = if self.upvalue_names.iter().all(Option::is_none) {
+            vec![]
+        } else {
+            self.upvalue_names
+        };
+        Ok(FunctionBlockChunk {
+            source_name: self.source_name,
+            source_line_start: self.source_lines.0,
+            source_line_end: self.source_lines.1,
+            number_of_parameters: self.number_of_parameters,
+            is_vararg: IsVarargFlag::from_bytes([self.is_vararg]).map_err(|_| AssembleError {
+                line: self.line,
+                message: format!("invalid vararg flag {}", self.is_vararg),
+            })?,
+            maximum_stack_size,
+            instructions,
+            constants: self.constants,
+            upvalues: self.upvalues,
+            protos: self.protos,
+            debug_info: DebugInfo {
+                line_info,
+                abs_line_info,
+                local_vars,
+                upvalue_names,
+            },
+        })
+    }
+}
+
+fn parse_constant(token: &Token) -> Result<LuaConstant, String> {
+    match token {
+        Token::String(string) => Ok(LuaConstant::String(string.clone())),
+        Token::Word(word) => match word.as_str() {
+            "nil" => Ok(LuaConstant::Nil),
+            "true" => Ok(LuaConstant::Boolean(true)),
+            "false" => Ok(LuaConstant::Boolean(false)),
+            word => word
+                .parse()
+                .map(LuaConstant::Integer)
+                .or_else(|_| word.parse().map(LuaConstant::Number))
+                .map_err(|_| format!("invalid constant {}", word)),
+        },
+    }
+}
+
+/// The line deltas and absolute lines of instructions on `lines`, as `luac`
+/// saves them. Instructions without a line keep the previous one.
+pub(crate) fn line_info(first_line: u64, lines: &[Option<u64>]) -> (Vec<i8>, Vec<AbsLineInfo>) {
+    let mut line_info = vec![];
+    let mut abs_line_info = vec![];
+    let mut previous = first_line as i64;
+    let mut without_absolute_line = 0;
+    for (pc, line) in lines.iter().enumerate() {
+        let line = line.map_or(previous, |line| line as i64);
+        let delta = line - previous;
+        if delta.abs() >= LINE_DELTA_LIMIT || without_absolute_line >= MAX_WITHOUT_ABSOLUTE_LINE {
+            abs_line_info.push(AbsLineInfo {
+                pc: pc as u64,
+                line: line as u64,
+            });
+            line_info.push(ABSOLUTE_LINE);
+            without_absolute_line = 1;
+        } else {
+            line_info.push(delta as i8);
+            without_absolute_line += 1;
+        }
+        previous = line;
+    }
+    (line_info, abs_line_info)
+}
+
+/// The highest register the instructions use plus one
+fn registers_used(instructions: &[u32]) -> u8 {
+    instructions
+        .iter()
+        .map(|instruction| {
+            let a = (instruction >> 7) as u8;
+            let Some(instruction) = Instruction::parse_u32(*instruction) else {
+                return 0;
+            };
+            // Ranges starting this close to the top overflow
+            if a > u8::MAX - 4 || matches!(instruction, Instruction::LoadNil(_, u8::MAX)) {
+                return u8::MAX as u16;
+            }
+            instruction
+                .read_registers()
+                .into_iter()
+                .chain(instruction.written_registers())
+                .map(|(first, count)| first as u16 + count.unwrap_or(1) as u16)
+                .max()
+                .unwrap_or(0)
+        })
+        .max()
+        .unwrap_or(0)
+        .min(u8::MAX as u16) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::{
+        common_structs::{constant::LuaConstant, debug_info::LocalVar},
+        instruction_parsing::instruction::Instruction,
+        lua_file::LuaFile,
+    };
+
+    use super::{assemble, AssembleError};
+
+    /// Runs a chunk with the Lua 5.4 interpreter linked into the tests
+    fn run(file: &LuaFile) -> mlua::Result<String> {
+        // Binary chunks are only loaded by unsafe states
+        let lua = unsafe { mlua::Lua::unsafe_new() };
+        let function = lua.load(file.to_bytes()).into_function()?;
+        function.call(())
+    }
+
+    #[test]
+    fn test_assemble() {
+        let file = assemble(indoc! {r#"
+            .function main
+            .vararg
+            .upvalue _ENV 1 0
+            .const "hello"
+            .const 3
+            .local total 2 done
+                    VarargPrep 0
+                    LoadI 0 0         ; total
+                    LoadI 1 1
+                    LoadK 2 1
+                    LoadI 3 1
+                    ForPrep 1 done
+            loop:   Add 0 0 4
+                    MmBin 0 4 6
+                    ForLoop 1 loop
+            done:   Closure 1 greet
+                    LoadK 2 0
+                    Move 3 0
+                    TailCall 1 3 1 0
+                    Return 1 0 1 0
+                .function greet
+                .params 2
+                .lines 20 23
+                .const " "
+                .line 21
+                        Move 2 0
+                        LoadK 3 0
+                        Move 4 1
+                        Concat 2 3
+                .line 22
+                        Return1 2
+                .end
+            .end
+        "#})
+        .unwrap();
+        let main = &file.main_function_block;
+        assert_eq!(file.number_of_upvalues, 1);
+        assert_eq!(main.maximum_stack_size, 5);
+        assert_eq!(
+            main.debug_info.local_vars,
+            vec![LocalVar {
+                name: Some(b"total".to_vec()),
+                start_pc: 2,
+                end_pc: 9,
+            }]
+        );
+        assert_eq!(main.debug_info.upvalue_names, vec![Some(b"_ENV".to_vec())]);
+        assert!(main.debug_info.line_info.is_empty());
+        assert_eq!(main.protos[0].number_of_parameters, 2);
+        assert_eq!(main.protos[0].debug_info.line_info, vec![1, 0, 0, 0, 1]);
+        assert_eq!(main.protos[0].line_at(4), Some(22));
+        assert_eq!(
+            main.protos[0].constants,
+            vec![LuaConstant::String(b" ".to_vec())]
+        );
+        assert_eq!(LuaFile::parse(&file.to_bytes()).unwrap().1, file);
+        // The loop sums 1, 2 and 3
+        assert_eq!(run(&file).unwrap(), "hello 6");
+    }
+
+    #[test]
+    fn test_extraarg() {
+        let file = assemble(indoc! {"
+            .function main
+                    LoadK 0 131072
+                    NewTable 1 0 300
+                    SetList 1 0 300
+                    NewTable 1 0 4
+                    SetList 1 4 0
+                    Return0
+            .end
+        "})
+        .unwrap();
+        let listing: Vec<_> = file
+            .main_function_block
+            .instructions
+            .iter()
+            .map(|instruction| format!("{:?}", Instruction::parse_u32(*instruction).unwrap()))
+            .collect();
+        assert_eq!(
+            listing,
+            vec![
+                "LoadKx(0)",
+                "Extraarg(131072)",
+                "NewTable(1, 0, 44, 1)",
+                "Extraarg(1)",
+                "SetList(1, 0, 44, 1)",
+                "Extraarg(1)",
+                "NewTable(1, 0, 4, 0)",
+                "Extraarg(0)",
+                "SetList(1, 4, 0, 0)",
+                "Return0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| assemble(source).unwrap_err();
+        assert_eq!(
+            error(".function main\nJmp nowhere\n.end"),
+            AssembleError {
+                line: 2,
+                message: "unknown label nowhere".to_string(),
+            }
+        );
+        assert_eq!(
+            error(".function main\n  Move 0 256\n.end").to_string(),
+            "line 2: b of Move is out of range: 256"
+        );
+        assert_eq!(
+            error(".function main\nReturn0\n").to_string(),
+            "line 1: function main has no .end"
+        );
+        assert_eq!(
+            error(".function main\n.const \"unfinished\n.end").to_string(),
+            "line 2: unfinished string"
+        );
+        assert_eq!(
+            error(".function main\n.const \"\\xf\"\n.end").to_string(),
+            "line 2: invalid escape \\xf\""
+        );
+        assert_eq!(
+            error(".function main\n.local x 0 later\nReturn0\n.end").to_string(),
+            "line 2: unknown label later"
+        );
+        assert_eq!(
+            error("Nop\n").to_string(),
+            "line 1: Nop outside of a function"
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    binary_chunks::header::HeaderChunk,
+    decompiler::{printer::Printer, Decompiler},
+    disassembler::Disassembler,
+    lua_file::{describe_parse_error, LuaFile},
+};
+
+/// What is written for each chunk of a tree
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BatchMode {
+    Disassemble,
+    Decompile,
+}
+
+impl BatchMode {
+    /// Extension of the files written, in place of the one of the chunk
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BatchMode::Disassemble => "disasm",
+            BatchMode::Decompile => "lua",
+        }
+    }
+
+    /// The text written for `file`
+    fn render(&self, file: LuaFile) -> Result<String, String> {
+        match self {
+            BatchMode::Disassemble => Ok(Disassembler::new(file).disassemble()),
+            BatchMode::Decompile => Decompiler::new(&file)
+                .decompile()
+                .map(|block| Printer::new().print_block(&block))
+                .map_err(|error| error.to_string()),
+        }
+    }
+}
+
+/// Why a chunk of a tree was not processed
+#[derive(Debug, PartialEq, Clone)]
+pub enum BatchError {
+    Io(String),
+    /// The error of `LuaFile::parse`
+    Parse(String),
+    Decompile(String),
+}
+
+impl Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatchError::Io(message) | BatchError::Parse(message) => write!(f, "{}", message),
+            BatchError::Decompile(message) => write!(f, "cannot decompile: {}", message),
+        }
+    }
+}
+
+/// The outcome of processing a tree
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct BatchReport {
+    /// Files written, relative to the output directory
+    pub written: Vec<PathBuf>,
+    /// Chunks that were not processed, relative to the input directory
+    pub failures: Vec<(PathBuf, BatchError)>,
+}
+
+impl BatchReport {
+    pub fn parse_failures(&self) -> impl Iterator<Item = &(PathBuf, BatchError)> {
+        self.failures
+            .iter()
+            .filter(|(_, error)| matches!(error, BatchError::Parse(_)))
+    }
+}
+
+impl Display for BatchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} chunks processed, {} failed",
+            self.written.len(),
+            self.failures.len()
+        )?;
+        for (path, error) in &self.failures {
+            writeln!(f, "  {}: {}", path.display(), error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the file at `path` starts like a binary chunk, whatever its extension
+pub fn is_chunk(path: &Path) -> io::Result<bool> {
+    let mut signature = [0; HeaderChunk::SIGNATURE.len()];
+    let mut file = fs::File::open(path)?;
+    match file.read_exact(&mut signature) {
+        Ok(()) => Ok(signature == HeaderChunk::SIGNATURE),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// The binary chunks of a tree
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FoundChunks {
+    /// Relative to the root of the tree and sorted
+    pub chunks: Vec<PathBuf>,
+    /// Files and directories of the tree that could not be read
+    pub failures: Vec<(PathBuf, BatchError)>,
+}
+
+/// Finds the binary chunks under `root`, keeping on past the files and
+/// directories below it that cannot be read. Symbolic links to directories
+/// are not followed.
+pub fn find_chunks(root: &Path) -> io::Result<FoundChunks> {
+    let mut chunks = vec![];
+    let mut failures = vec![];
+    let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_path_buf();
+    let mut failure = |path: &Path, error: io::Error| {
+        failures.push((
+            relative(path),
+            BatchError::Io(format!("{}: {}", path.display(), error)),
+        ))
+    };
+    let mut directories = vec![root.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(error) if directory == root => return Err(error),
+            Err(error) => {
+                failure(&directory, error);
+                continue;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    failure(&directory, error);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => directories.push(path),
+                Ok(_) if path.is_file() => match is_chunk(&path) {
+                    Ok(true) => chunks.push(relative(&path)),
+                    Ok(false) => {}
+                    Err(error) => failure(&path, error),
+                },
+                Ok(_) => {}
+                Err(error) => failure(&path, error),
+            }
+        }
+    }
+    chunks.sort();
+    Ok(FoundChunks { chunks, failures })
+}
+
+/// The path each chunk is written to, relative to the output directory: the
+/// chunk with the extension of `mode` in place of its own, or after it when
+/// that would give two chunks the same path
+fn destinations(chunks: &[PathBuf], mode: BatchMode) -> Vec<Result<PathBuf, BatchError>> {
+    let replaced: Vec<_> = chunks
+        .iter()
+        .map(|chunk| chunk.with_extension(mode.extension()))
+        .collect();
+    let destinations: Vec<_> = chunks
+        .iter()
+        .zip(&replaced)
+        .map(|(chunk, destination)| {
+            if replaced
+                .iter()
+                .filter(|other| *other == destination)
+                .count()
+                > 1
+            {
+                let mut appended = chunk.clone().into_os_string();
+                appended.push(".");
+                appended.push(mode.extension());
+                PathBuf::from(appended)
+            } else {
+                destination.clone()
+            }
+        })
+        .collect();
+    destinations
+        .iter()
+        .enumerate()
+        .map(|(index, destination)| {
+            match destinations[..index]
+                .iter()
+                .position(|other| other == destination)
+            {
+                Some(first) => Err(BatchError::Io(format!(
+                    "{} is also written for {}",
+                    destination.display(),
+                    chunks[first].display()
+                ))),
+                None => Ok(destination.clone()),
+            }
+        })
+        .collect()
+}
+
+/// Processes every chunk under `input` on all available threads, writing
+/// each result to the same relative path under `output` with the extension
+/// of `mode`. Chunks that would be written to the same path, like `a.lua`
+/// and `a.luac`, keep their own extension before it.
+pub fn process_tree(input: &Path, output: &Path, mode: BatchMode) -> io::Result<BatchReport> {
+    let FoundChunks { chunks, failures } = find_chunks(input)?;
+    let destinations = destinations(&chunks, mode);
+    let workers = thread::available_parallelism()
+        .map_or(1, |count| count.get())
+        .min(chunks.len().max(1));
+    let next = AtomicUsize::new(0);
+    let report = Mutex::new(BatchReport {
+        written: vec![],
+        failures,
+    });
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(relative) = chunks.get(index) else {
+                    break;
+                };
+                let result = destinations[index].clone().and_then(|written| {
+                    process_chunk(input, output, relative, &written, mode).map(|()| written)
+                });
+                let mut report = report.lock().unwrap_or_else(|error| error.into_inner());
+                match result {
+                    Ok(written) => report.written.push(written),
+                    Err(error) => report.failures.push((relative.clone(), error)),
+                }
+            });
+        }
+    });
+    let mut report = report
+        .into_inner()
+        .unwrap_or_else(|error| error.into_inner());
+    report.written.sort();
+    report
+        .failures
+        .sort_by(|first, second| first.0.cmp(&second.0));
+    Ok(report)
+}
+
+/// Processes the chunk at `relative` under `input` into `written` under `output`
+fn process_chunk(
+    input: &Path,
+    output: &Path,
+    relative: &Path,
+    written: &Path,
+    mode: BatchMode,
+) -> Result<(), BatchError> {
+    let io_error =
+        |path: &Path, error: io::Error| BatchError::Io(format!("{}: {}", path.display(), error));
+    let source = input.join(relative);
+    let data = fs::read(&source).map_err(|error| io_error(&source, error))?;
+    let file = match LuaFile::parse(&data) {
+        Ok((_, file)) => file,
+        Err(error) => return Err(BatchError::Parse(describe_parse_error(&data, &error))),
+    };
+    let text = mode.render(file).map_err(BatchError::Decompile)?;
+    let destination = output.join(written);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(|error| io_error(parent, error))?;
+    }
+    fs::write(&destination, text).map_err(|error| io_error(&destination, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{destinations, find_chunks, process_tree, BatchError, BatchMode, FoundChunks};
+
+    #[test]
+    fn test_process_tree() {
+        let root = std::env::temp_dir().join(format!("rusty_lua_dec_batch_{}", std::process::id()));
+        let input = root.join("input");
+        let output = root.join("output");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(input.join("scripts/ai")).unwrap();
+        let chunk = include_bytes!("../tests/closures.luac");
+        fs::write(input.join("main.luac"), chunk).unwrap();
+        // Games often keep the source extension on compiled scripts
+        fs::write(input.join("scripts/ai/enemy.lua"), chunk).unwrap();
+        fs::write(input.join("scripts/readme.txt"), "not a chunk").unwrap();
+        fs::write(input.join("scripts/broken.luac"), &chunk[..40]).unwrap();
+        // Both would be decompiled to `util.lua`
+        fs::write(input.join("util.lua"), chunk).unwrap();
+        fs::write(input.join("util.luac"), chunk).unwrap();
+
+        assert_eq!(
+            find_chunks(&input).unwrap(),
+            FoundChunks {
+                chunks: vec![
+                    PathBuf::from("main.luac"),
+                    PathBuf::from("scripts/ai/enemy.lua"),
+                    PathBuf::from("scripts/broken.luac"),
+                    PathBuf::from("util.lua"),
+                    PathBuf::from("util.luac"),
+                ],
+                failures: vec![],
+            }
+        );
+        let report = process_tree(&input, &output, BatchMode::Decompile).unwrap();
+        assert_eq!(
+            report.written,
+            vec![
+                PathBuf::from("main.lua"),
+                PathBuf::from("scripts/ai/enemy.lua"),
+                PathBuf::from("util.lua.lua"),
+                PathBuf::from("util.luac.lua"),
+            ]
+        );
+        assert_eq!(
+            report.failures,
+            vec![(
+                PathBuf::from("scripts/broken.luac"),
+                BatchError::Parse(
+                    "truncated Lua 5.4 binary chunk: data missing at byte 33".to_string()
+                )
+            )]
+        );
+        assert_eq!(report.parse_failures().count(), 1);
+        let decompiled = fs::read_to_string(output.join("scripts/ai/enemy.lua")).unwrap();
+        assert!(decompiled.starts_with("local counter = 0\n"));
+
+        let report = process_tree(&input, &output, BatchMode::Disassemble).unwrap();
+        assert_eq!(report.written[0], PathBuf::from("main.disasm"));
+        assert_eq!(report.written[3], PathBuf::from("util.luac.disasm"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_destinations() {
+        let chunks = ["a.lua", "a.luac", "a.lua.luac", "b.luac"].map(PathBuf::from);
+        assert_eq!(
+            destinations(&chunks, BatchMode::Decompile),
+            vec![
+                Ok(PathBuf::from("a.lua.lua")),
+                Ok(PathBuf::from("a.luac.lua")),
+                Err(BatchError::Io(
+                    "a.lua.lua is also written for a.lua".to_string()
+                )),
+                Ok(PathBuf::from("b.lua")),
+            ]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_process_tree_unreadable_file() {
+        let root =
+            std::env::temp_dir().join(format!("rusty_lua_dec_unreadable_{}", std::process::id()));
+        let input = root.join("input");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&input).unwrap();
+        fs::write(
+            input.join("main.luac"),
+            include_bytes!("../tests/closures.luac"),
+        )
+        .unwrap();
+        // A file that opens but cannot be read, even by root
+        std::os::unix::fs::symlink("/proc/self/mem", input.join("memory")).unwrap();
+        let report = process_tree(&input, &root.join("output"), BatchMode::Decompile).unwrap();
+        assert_eq!(report.written, vec![PathBuf::from("main.lua")]);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, PathBuf::from("memory"));
+        assert!(matches!(report.failures[0].1, BatchError::Io(_)));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,3 +1,6 @@
+// The code generated by `bitfield` for `IsVarargFlag` trips these lints
+#![allow(unused_parens, clippy::new_without_default)]
+
 use modular_bitfield::{
     bitfield,
     specifiers::{B1, B2, B3},
@@ -10,21 +13,40 @@ use nom::{
 };
 
 use crate::common_structs::{
-    constant::LuaConstant, debug_info::DebugInfo, string::lua_string_utf8, vector::lua_vector,
+    constant::LuaConstant,
+    debug_info::DebugInfo,
+    string::{lua_string_owned, write_lua_string},
+    vector::{lua_vector, write_lua_vector},
+};
+use crate::common_structs::{
+    size_t::{lua_size_t, write_lua_size_t},
+    upvalue::Upvalue,
 };
-use crate::common_structs::{size_t::lua_size_t, upvalue::Upvalue};
+
+use super::proto_path::ProtoPath;
 
 #[bitfield(filled = false)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct IsVarargFlag {
     pub has_arg: B1,
     pub is_vararg: B2,
     pub needs_arg: B3,
 }
 
+/// The debug information `FunctionBlockChunk::strip` removes
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StripMode {
+    /// Everything, like `luac -s`
+    All,
+    /// The names of sources, locals and upvalues, keeping the lines
+    Names,
+    /// Only the names of sources, which may reveal where the chunk was built
+    SourceNames,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct FunctionBlockChunk {
-    pub source_name: Option<String>,
+    pub source_name: Option<Vec<u8>>,
     pub source_line_start: u64,
     pub source_line_end: u64,
     pub number_of_parameters: u8,
@@ -41,7 +63,7 @@ impl FunctionBlockChunk {
     pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
         map(
             tuple((
-                lua_string_utf8,
+                lua_string_owned,
                 lua_size_t,
                 lua_size_t,
                 be_u8,
@@ -82,14 +104,93 @@ impl FunctionBlockChunk {
             },
         )(input)
     }
+
+    /// Writes the function block as `parse` reads it
+    pub fn write(&self, output: &mut Vec<u8>) {
+        write_lua_string(self.source_name.as_deref(), output);
+        write_lua_size_t(self.source_line_start, output);
+        write_lua_size_t(self.source_line_end, output);
+        output.extend_from_slice(&[
+            self.number_of_parameters,
+            self.is_vararg.clone().into_bytes()[0],
+            self.maximum_stack_size,
+        ]);
+        write_lua_vector(
+            &self.instructions,
+            |instruction, output| output.extend_from_slice(&instruction.to_le_bytes()),
+            output,
+        );
+        write_lua_vector(&self.constants, LuaConstant::write, output);
+        write_lua_vector(&self.upvalues, Upvalue::write, output);
+        write_lua_vector(&self.protos, Self::write, output);
+        self.debug_info.write(output);
+    }
+
+    /// Removes the debug information of `mode` from this function block and
+    /// every function block nested in it. Local variables go with the names,
+    /// as Lua expects each of them to have one.
+    pub fn strip(&mut self, mode: StripMode) {
+        self.source_name = None;
+        let debug_info = &mut self.debug_info;
+        if mode != StripMode::SourceNames {
+            debug_info.local_vars.clear();
+            debug_info.upvalue_names.clear();
+        }
+        if mode == StripMode::All {
+            debug_info.line_info.clear();
+            debug_info.abs_line_info.clear();
+        }
+        for proto in &mut self.protos {
+            proto.strip(mode);
+        }
+    }
+
+    /// Lists this function block and every function block nested in it, in
+    /// pre-order, each with its path relative to `path`
+    pub fn functions(&self, path: ProtoPath) -> Vec<(ProtoPath, &FunctionBlockChunk)> {
+        let mut functions = vec![(path.clone(), self)];
+        for (index, proto) in self.protos.iter().enumerate() {
+            functions.extend(proto.functions(path.child(index)));
+        }
+        functions
+    }
+
+    /// Finds a nested function block by its path relative to this one
+    pub fn function_at(&self, path: &ProtoPath) -> Option<&FunctionBlockChunk> {
+        path.0
+            .iter()
+            .try_fold(self, |function, index| function.protos.get(*index))
+    }
+
+    /// Source line of the instruction at `pc`, if line information was kept
+    pub fn line_at(&self, pc: usize) -> Option<u64> {
+        let line_info = &self.debug_info.line_info;
+        if pc >= line_info.len() {
+            return None;
+        }
+        let (first_delta, base_line) = self
+            .debug_info
+            .abs_line_info
+            .iter()
+            .take_while(|abs_line_info| abs_line_info.pc as usize <= pc)
+            .last()
+            .map(|abs_line_info| (abs_line_info.pc as usize + 1, abs_line_info.line))
+            .unwrap_or((0, self.source_line_start));
+        let line = line_info[first_delta..=pc]
+            .iter()
+            .fold(base_line as i64, |line, delta| line + *delta as i64);
+        Some(line as u64)
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use crate::{
-        binary_chunks::function_block::IsVarargFlag,
+        binary_chunks::{function_block::IsVarargFlag, proto_path::ProtoPath},
+        common_structs::debug_info::AbsLineInfo,
         common_structs::{debug_info::DebugInfo, upvalue::Upvalue, variable_kind::VariableKind},
+        lua_file::LuaFile,
     };
 
     use super::FunctionBlockChunk;
@@ -124,9 +225,28 @@ mod tests {
                     line_info: vec![1, 0, 1],
                     abs_line_info: vec![],
                     local_vars: vec![],
-                    upvalue_names: vec![Some("x".to_string())]
+                    upvalue_names: vec![Some(b"x".to_vec())]
                 }
             }
         );
     }
+
+    #[test]
+    fn test_line_at_with_absolute_line_info() {
+        let file = LuaFile::parse(include_bytes!("../../tests/all_opcodes.luac"))
+            .unwrap()
+            .1;
+        let main = &file.main_function_block;
+        assert_eq!(
+            main.debug_info.abs_line_info,
+            vec![AbsLineInfo { pc: 128, line: 45 }]
+        );
+        assert_eq!(main.line_at(1), Some(2));
+        assert_eq!(main.line_at(128), Some(45));
+        assert_eq!(main.line_at(151), Some(53));
+        assert_eq!(main.line_at(main.instructions.len()), None);
+        let nested = main.function_at(&ProtoPath(vec![1])).unwrap();
+        assert_eq!(nested.line_at(1), Some(78));
+        assert_eq!(main.functions(ProtoPath::main()).len(), 4);
+    }
 }
@@ -45,13 +45,23 @@ pub struct HeaderChunk {
 }
 
 impl HeaderChunk {
+    /// Number of bytes the header takes in a binary chunk
+    pub const SIZE: usize = 31;
+    /// Bytes every binary chunk starts with
+    pub const SIGNATURE: &[u8] = b"\x1BLua";
+    /// Bytes detecting conversions of the chunk
+    const DATA: &[u8] = b"\x19\x93\x0d\x0a\x1a\x0a";
+    /// Integer and float checking the formats of numbers
+    const INTEGER: u64 = 0x5678;
+    const NUMBER: f64 = 370.5;
+
     pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
         map(
             tuple((
-                tag("\x1BLua"),
+                tag(Self::SIGNATURE),
                 be_u8,
                 be_u8,
-                tag(b"\x19\x93\x0d\x0a\x1a\x0a"),
+                tag(Self::DATA),
                 be_u8,
                 be_u8,
                 be_u8,
@@ -79,6 +89,33 @@ impl HeaderChunk {
             },
         )(input)
     }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(Self::SIGNATURE);
+        output.push(self.version_number.major << 4 | self.version_number.minor);
+        output.push(self.format_version);
+        output.extend_from_slice(Self::DATA);
+        output.extend_from_slice(&[
+            self.size_of_int,
+            self.size_of_size_t,
+            self.size_of_lua_number,
+        ]);
+        output.extend_from_slice(&Self::INTEGER.to_le_bytes());
+        output.extend_from_slice(&Self::NUMBER.to_le_bytes());
+    }
+}
+
+impl Default for HeaderChunk {
+    /// The header `luac` 5.4 writes on 64-bit platforms
+    fn default() -> Self {
+        HeaderChunk {
+            version_number: 0x54.into(),
+            format_version: 0,
+            size_of_int: 4,
+            size_of_size_t: 8,
+            size_of_lua_number: 8,
+        }
+    }
 }
 
 #[cfg(test)]
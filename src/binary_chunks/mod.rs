@@ -1,2 +1,4 @@
 pub mod header;
-pub mod function_block;
\ No newline at end of file
+pub mod function_block;
+pub mod proto_path;
+pub mod patch;
\ No newline at end of file
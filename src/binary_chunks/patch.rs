@@ -0,0 +1,374 @@
+use std::{fmt::Display, ops::Range};
+
+use crate::{
+    assembler::line_info,
+    common_structs::constant::LuaConstant,
+    instruction_parsing::{
+        instruction::Instruction, instruction_encodings::InstructionEncoding, opcodes::Opcode,
+    },
+};
+
+use super::function_block::FunctionBlockChunk;
+
+/// Why a function block could not be patched
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatchError {
+    /// The pcs go past the end of the instructions
+    OutOfRange { range: Range<usize>, length: usize },
+    /// The pc would separate an instruction from the one it skips or extends
+    SplitsPair { pc: usize },
+    /// The jump at `pc` no longer fits its operand
+    JumpTooFar { pc: usize },
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchError::OutOfRange { range, length } => write!(
+                f,
+                "pcs {}..{} are out of range for {} instructions",
+                range.start, range.end, length
+            ),
+            PatchError::SplitsPair { pc } => write!(
+                f,
+                "pc {} separates an instruction from the one it skips or extends",
+                pc
+            ),
+            PatchError::JumpTooFar { pc } => write!(f, "the jump at pc {} is too far", pc),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Whether the instructions at `pc - 1` and `pc` have to stay together
+pub(crate) fn is_pair(instructions: &[u32], pc: usize) -> bool {
+    let opcode = |pc: usize| -> Option<Opcode> {
+        num::FromPrimitive::from_u32(*instructions.get(pc)? & 0x7f)
+    };
+    let skips_next = pc
+        .checked_sub(1)
+        .and_then(|previous| Instruction::parse_u32(*instructions.get(previous)?))
+        .is_some_and(|previous| {
+            previous.is_test()
+                || matches!(
+                    previous,
+                    Instruction::LFalseSkip(_) | Instruction::LoadKx(_) | Instruction::TForCall(..)
+                )
+        });
+    // Arithmetic skips the metamethod call following it when it succeeds, and
+    // `TForPrep` runs whatever its target is as the `TForCall`
+    skips_next
+        || matches!(
+            opcode(pc),
+            Some(
+                Opcode::Extraarg
+                    | Opcode::MmBin
+                    | Opcode::MmBinI
+                    | Opcode::MmBinK
+                    | Opcode::TForCall
+            )
+        )
+}
+
+/// `instruction`, found at `pc`, jumping to `target` instead
+fn retarget(instruction: u32, pc: usize, target: usize) -> Result<u32, PatchError> {
+    let too_far = || PatchError::JumpTooFar { pc };
+    let (opcode, encoding) = InstructionEncoding::decode(instruction).ok_or_else(too_far)?;
+    let (pc, target) = (pc as i64, target as i64);
+    let offset = match opcode {
+        Opcode::Jmp => target - pc - 1,
+        Opcode::ForLoop | Opcode::TForLoop => pc + 1 - target,
+        Opcode::ForPrep => target - pc - 2,
+        Opcode::TForPrep => target - pc - 1,
+        _ => return Ok(instruction),
+    };
+    let mut operands = encoding.operands(opcode);
+    // The offset is the last operand, sJ or Bx
+    *operands.last_mut().unwrap() = offset;
+    InstructionEncoding::from_operands(opcode, &operands)
+        .map(|encoding| encoding.encode())
+        .map_err(|_| too_far())
+}
+
+impl FunctionBlockChunk {
+    /// Inserts `instructions` before the one at `pc`. Jumps to `pc` then go
+    /// to the first inserted instruction, which takes the line of the one at
+    /// `pc`.
+    pub fn insert_instructions(
+        &mut self,
+        pc: usize,
+        instructions: &[u32],
+    ) -> Result<(), PatchError> {
+        self.splice_instructions(pc..pc, instructions)
+    }
+
+    /// Deletes the instructions at `range`. Jumps into it go to the
+    /// instruction following it.
+    pub fn delete_instructions(&mut self, range: Range<usize>) -> Result<(), PatchError> {
+        self.splice_instructions(range, &[])
+    }
+
+    /// Replaces the instruction at `pc`, whose jump, if any, is kept as given
+    pub fn replace_instruction(&mut self, pc: usize, instruction: u32) -> Result<(), PatchError> {
+        let length = self.instructions.len();
+        let slot = self
+            .instructions
+            .get_mut(pc)
+            .ok_or(PatchError::OutOfRange {
+                range: pc..pc + 1,
+                length,
+            })?;
+        *slot = instruction;
+        Ok(())
+    }
+
+    /// Replaces the instructions at `range` with `instructions`. Jumps to the
+    /// start of `range` or into it go to the first new instruction, which
+    /// takes the line of the first replaced one. The jumps of the new
+    /// instructions are kept as given, and every other jump, line and local
+    /// variable range follows the instructions it was on.
+    pub fn splice_instructions(
+        &mut self,
+        range: Range<usize>,
+        instructions: &[u32],
+    ) -> Result<(), PatchError> {
+        let length = self.instructions.len();
+        if range.start > range.end || range.end > length {
+            return Err(PatchError::OutOfRange { range, length });
+        }
+        for pc in [range.start, range.end] {
+            if is_pair(&self.instructions, pc) {
+                return Err(PatchError::SplitsPair { pc });
+            }
+        }
+        let inserted = instructions.len();
+        let moved = |pc: usize| pc - range.len() + inserted;
+        let new_target = |target: usize| {
+            if target <= range.start {
+                target
+            } else if target < range.end {
+                range.start
+            } else {
+                moved(target)
+            }
+        };
+        let mut patched = Vec::with_capacity(length - range.len() + inserted);
+        for (pc, instruction) in self.instructions.iter().enumerate() {
+            if pc == range.start {
+                patched.extend_from_slice(instructions);
+            }
+            if range.contains(&pc) {
+                continue;
+            }
+            let new_pc = if pc < range.start { pc } else { moved(pc) };
+            let target =
+                Instruction::parse_u32(*instruction).and_then(|parsed| parsed.jump_target(pc));
+            patched.push(match target {
+                Some(target) => retarget(*instruction, new_pc, new_target(target))?,
+                None => *instruction,
+            });
+        }
+        if range.start == length {
+            patched.extend_from_slice(instructions);
+        }
+        let debug_info = &self.debug_info;
+        if debug_info.line_info.len() == length && length > 0 {
+            let mut lines: Vec<_> = (0..length).map(|pc| self.line_at(pc)).collect();
+            let line = lines[range.start.min(length - 1)];
+            lines.splice(range.clone(), std::iter::repeat_n(line, inserted));
+            let (line_info, abs_line_info) = line_info(self.source_line_start, &lines);
+            self.debug_info.line_info = line_info;
+            self.debug_info.abs_line_info = abs_line_info;
+        }
+        for local_var in &mut self.debug_info.local_vars {
+            local_var.start_pc = new_target(local_var.start_pc as usize) as u64;
+            local_var.end_pc = new_target(local_var.end_pc as usize) as u64;
+        }
+        self.instructions = patched;
+        Ok(())
+    }
+
+    /// The index of `constant` in the constants, added if it is not there
+    pub fn add_constant(&mut self, constant: LuaConstant) -> usize {
+        match self
+            .constants
+            .iter()
+            .position(|other| other.is_same(&constant))
+        {
+            Some(index) => index,
+            None => {
+                self.constants.push(constant);
+                self.constants.len() - 1
+            }
+        }
+    }
+
+    /// Adds a nested function block, returning its index for `Closure`
+    pub fn add_proto(&mut self, proto: FunctionBlockChunk) -> usize {
+        self.protos.push(proto);
+        self.protos.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        common_structs::constant::LuaConstant,
+        instruction_parsing::{
+            instruction::Instruction, instruction_encodings::InstructionEncoding, opcodes::Opcode,
+        },
+        lua_file::LuaFile,
+    };
+
+    use super::PatchError;
+
+    fn encode(opcode: Opcode, operands: &[i64]) -> u32 {
+        InstructionEncoding::from_operands(opcode, operands)
+            .unwrap()
+            .encode()
+    }
+
+    /// Runs a chunk with the Lua 5.4 interpreter linked into the tests
+    fn run(file: &LuaFile) -> mlua::Result<String> {
+        let lua = unsafe { mlua::Lua::unsafe_new() };
+        let function = lua.load(file.to_bytes()).into_function()?;
+        function.call(())
+    }
+
+    #[test]
+    fn test_splice_instructions() {
+        let mut file = LuaFile::parse(include_bytes!("../../tests/control_flow.luac"))
+            .unwrap()
+            .1;
+        let original = LuaFile::parse(include_bytes!("../../tests/control_flow.luac"))
+            .unwrap()
+            .1;
+        let main = &mut file.main_function_block;
+        let length = main.instructions.len();
+        let moved = |pc: usize| pc + 2;
+        main.insert_instructions(1, &[encode(Opcode::Move, &[0, 0, 0, 0]); 2])
+            .unwrap();
+        assert_eq!(main.instructions.len(), length + 2);
+        for (pc, instruction) in original
+            .main_function_block
+            .instructions
+            .iter()
+            .enumerate()
+            .skip(1)
+        {
+            let old = Instruction::parse_u32(*instruction).unwrap();
+            let new = Instruction::parse_u32(main.instructions[moved(pc)]).unwrap();
+            assert_eq!(
+                new.jump_target(moved(pc)),
+                old.jump_target(pc)
+                    .map(|target| if target <= 1 { target } else { moved(target) })
+            );
+        }
+        for pc in 1..length {
+            assert_eq!(
+                main.line_at(moved(pc)),
+                original.main_function_block.line_at(pc)
+            );
+        }
+        assert_eq!(main.line_at(1), original.main_function_block.line_at(1));
+        for (local_var, old) in main
+            .debug_info
+            .local_vars
+            .iter()
+            .zip(&original.main_function_block.debug_info.local_vars)
+        {
+            assert_eq!(local_var.end_pc, old.end_pc + 2);
+        }
+        main.delete_instructions(1..3).unwrap();
+        assert_eq!(file, original);
+    }
+
+    #[test]
+    fn test_patched_chunk_runs() {
+        let mut file = LuaFile::parse(include_bytes!("../../tests/goto.luac"))
+            .unwrap()
+            .1;
+        let main = &mut file.main_function_block;
+        // Return a string before anything else
+        let constant = main.add_constant(LuaConstant::String(b"patched".to_vec()));
+        main.insert_instructions(
+            1,
+            &[
+                encode(Opcode::LoadK, &[0, constant as i64]),
+                // Vararg functions return with `Return`, k set
+                encode(Opcode::Return, &[0, 2, 1, 1]),
+            ],
+        )
+        .unwrap();
+        assert_eq!(run(&file).unwrap(), "patched");
+        assert_eq!(
+            file.main_function_block
+                .add_constant(LuaConstant::String(b"patched".to_vec())),
+            constant
+        );
+    }
+
+    #[test]
+    fn test_add_constant() {
+        let mut file = LuaFile::parse(include_bytes!("../../tests/goto.luac"))
+            .unwrap()
+            .1;
+        let main = &mut file.main_function_block;
+        let zero = main.add_constant(LuaConstant::Number(0.0));
+        assert_ne!(main.add_constant(LuaConstant::Number(-0.0)), zero);
+        assert_ne!(main.add_constant(LuaConstant::Integer(0)), zero);
+        let nan = main.add_constant(LuaConstant::Number(f64::NAN));
+        assert_eq!(main.add_constant(LuaConstant::Number(f64::NAN)), nan);
+        assert_eq!(main.add_constant(LuaConstant::Number(0.0)), zero);
+    }
+
+    #[test]
+    fn test_patch_errors() {
+        let mut file = LuaFile::parse(include_bytes!("../../tests/control_flow.luac"))
+            .unwrap()
+            .1;
+        let main = &mut file.main_function_block;
+        let length = main.instructions.len();
+        assert_eq!(
+            main.delete_instructions(length..length + 1),
+            Err(PatchError::OutOfRange {
+                range: length..length + 1,
+                length
+            })
+        );
+        let test = main
+            .instructions
+            .iter()
+            .position(|instruction| {
+                Instruction::parse_u32(*instruction)
+                    .is_some_and(|instruction| instruction.is_test())
+            })
+            .unwrap();
+        assert_eq!(
+            main.insert_instructions(test + 1, &[0]),
+            Err(PatchError::SplitsPair { pc: test + 1 })
+        );
+        // `TForPrep` jumps to the `TForCall`, whichever instruction is there
+        let tfor_call = main
+            .instructions
+            .iter()
+            .position(|instruction| *instruction & 0x7f == Opcode::TForCall as u32)
+            .unwrap();
+        assert_eq!(
+            main.insert_instructions(tfor_call, &[0]),
+            Err(PatchError::SplitsPair { pc: tfor_call })
+        );
+        // The jump over the body of a loop that grew too long
+        let for_prep = main
+            .instructions
+            .iter()
+            .position(|instruction| *instruction & 0x7f == Opcode::ForPrep as u32)
+            .unwrap();
+        let body = vec![encode(Opcode::Move, &[0, 0, 0, 0]); 0x20000];
+        assert_eq!(
+            main.insert_instructions(for_prep + 1, &body),
+            Err(PatchError::JumpTooFar { pc: for_prep })
+        );
+    }
+}
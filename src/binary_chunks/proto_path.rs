@@ -0,0 +1,73 @@
+use std::{fmt::Display, str::FromStr};
+
+/// Location of a function block inside a compiled file, as the chain of
+/// `protos` indices leading to it from the main function block.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ProtoPath(pub Vec<usize>);
+
+const MAIN_FUNCTION_NAME: &str = "main";
+
+impl ProtoPath {
+    /// Path of the main function block
+    pub fn main() -> Self {
+        ProtoPath(Vec::new())
+    }
+
+    /// Path of the `index`th nested function block of this one
+    pub fn child(&self, index: usize) -> Self {
+        let mut indices = self.0.clone();
+        indices.push(index);
+        ProtoPath(indices)
+    }
+
+    /// Path of the function block this one is nested in
+    pub fn parent(&self) -> Option<Self> {
+        if self.0.is_empty() {
+            return None;
+        }
+        Some(ProtoPath(self.0[..self.0.len() - 1].to_vec()))
+    }
+
+    /// Number of function blocks between the main function block and this one
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Display for ProtoPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", MAIN_FUNCTION_NAME)?;
+        for index in &self.0 {
+            write!(f, "/{}", index)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ProtoPath {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s.strip_prefix(MAIN_FUNCTION_NAME).unwrap_or(s);
+        rest.split('/')
+            .filter(|part| !part.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map(ProtoPath)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProtoPath;
+
+    #[test]
+    fn test_proto_path_display_and_parse() {
+        let path = ProtoPath::main().child(2).child(0);
+        assert_eq!(path.to_string(), "main/2/0");
+        assert_eq!("main/2/0".parse::<ProtoPath>().unwrap(), path);
+        assert_eq!("main".parse::<ProtoPath>().unwrap(), ProtoPath::main());
+        assert_eq!(path.parent(), Some(ProtoPath(vec![2])));
+        assert_eq!(ProtoPath::main().parent(), None);
+    }
+}
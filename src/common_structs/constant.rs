@@ -5,7 +5,10 @@ use nom::{
     IResult,
 };
 
-use super::string::lua_string_utf8;
+use super::string::{lua_string_owned, write_lua_string};
+
+/// Strings up to this length are dumped as short strings
+const MAX_SHORT_STRING_LENGTH: usize = 40;
 
 #[derive(Debug, PartialEq)]
 /// A constant in the function block binary chunk
@@ -14,7 +17,8 @@ pub enum LuaConstant {
     Boolean(bool),
     Number(f64),
     Integer(i64),
-    String(String),
+    /// The bytes of the string, which need not be UTF-8
+    String(Vec<u8>),
 }
 
 impl LuaConstant {
@@ -25,7 +29,7 @@ impl LuaConstant {
             0x0 => Ok((input, LuaConstant::Nil)),
             0x1 => Ok((input, LuaConstant::Boolean(false))),
             0x3 => map(le_i64, LuaConstant::Integer)(input),
-            0x4 | 0x14 => map(lua_string_utf8, |string_data| {
+            0x4 | 0x14 => map(lua_string_owned, |string_data| {
                 if let Some(string_data) = string_data {
                     LuaConstant::String(string_data)
                 } else {
@@ -40,4 +44,53 @@ impl LuaConstant {
             })),
         }
     }
+
+    /// Writes the constant as `parse` reads it
+    pub fn write(&self, output: &mut Vec<u8>) {
+        match self {
+            LuaConstant::Nil => output.push(0x0),
+            LuaConstant::Boolean(false) => output.push(0x1),
+            LuaConstant::Boolean(true) => output.push(0x11),
+            LuaConstant::Integer(value) => {
+                output.push(0x3);
+                output.extend_from_slice(&value.to_le_bytes());
+            }
+            LuaConstant::Number(value) => {
+                output.push(0x13);
+                output.extend_from_slice(&value.to_le_bytes());
+            }
+            LuaConstant::String(value) => {
+                output.push(if value.len() <= MAX_SHORT_STRING_LENGTH {
+                    0x4
+                } else {
+                    0x14
+                });
+                write_lua_string(Some(value), output);
+            }
+        }
+    }
+
+    /// Whether the two constants are the same, numbers being compared by their
+    /// bits so that `-0.0` is not `0.0` and a NaN is itself
+    pub fn is_same(&self, other: &LuaConstant) -> bool {
+        match (self, other) {
+            (LuaConstant::Number(number), LuaConstant::Number(other)) => {
+                number.to_bits() == other.to_bits()
+            }
+            (constant, other) => constant == other,
+        }
+    }
+
+    /// The string value of the constant, if it is a UTF-8 string
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(self.as_bytes()?).ok()
+    }
+
+    /// The bytes of the constant, if it is a string
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            LuaConstant::String(string) => Some(string),
+            _ => None,
+        }
+    }
 }
@@ -1,6 +1,10 @@
 use nom::{combinator::map, number::complete::be_i8, sequence::tuple, IResult};
 
-use super::{size_t::lua_size_t, string::lua_string_utf8, vector::lua_vector};
+use super::{
+    size_t::{lua_size_t, write_lua_size_t},
+    string::{lua_string_owned, write_lua_string},
+    vector::{lua_vector, write_lua_vector},
+};
 
 #[derive(Debug, PartialEq)]
 pub struct AbsLineInfo {
@@ -14,18 +18,53 @@ impl AbsLineInfo {
             line,
         })(input)
     }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        write_lua_size_t(self.pc, output);
+        write_lua_size_t(self.line, output);
+    }
+}
+
+/// The locals of a function block and the register each one is held in,
+/// computed once for all the lookups
+#[derive(Debug)]
+pub struct LocalScopes<'a> {
+    local_vars: &'a [LocalVar],
+    registers: Vec<u8>,
+}
+
+impl<'a> LocalScopes<'a> {
+    /// Index in `local_vars` of the local held by `register` at `pc`, if any
+    pub fn local_index(&self, register: u8, pc: usize) -> Option<usize> {
+        let pc = pc as u64;
+        self.local_vars
+            .iter()
+            .zip(&self.registers)
+            .enumerate()
+            .rfind(|(_, (local_var, local_register))| {
+                **local_register == register && local_var.start_pc <= pc && pc < local_var.end_pc
+            })
+            .map(|(index, _)| index)
+    }
+
+    /// Name of the local held by `register` at `pc`, if any
+    pub fn local_name(&self, register: u8, pc: usize) -> Option<&'a str> {
+        self.local_index(register, pc)
+            .and_then(|index| self.local_vars[index].name.as_deref())
+            .and_then(|name| std::str::from_utf8(name).ok())
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct LocalVar {
-    pub name: Option<String>,
+    pub name: Option<Vec<u8>>,
     pub start_pc: u64,
     pub end_pc: u64,
 }
 impl LocalVar {
     pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
         map(
-            tuple((lua_string_utf8, lua_size_t, lua_size_t)),
+            tuple((lua_string_owned, lua_size_t, lua_size_t)),
             |(name, start_pc, end_pc)| LocalVar {
                 name,
                 start_pc,
@@ -33,6 +72,12 @@ impl LocalVar {
             },
         )(input)
     }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        write_lua_string(self.name.as_deref(), output);
+        write_lua_size_t(self.start_pc, output);
+        write_lua_size_t(self.end_pc, output);
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -40,7 +85,7 @@ pub struct DebugInfo {
     pub line_info: Vec<i8>,
     pub abs_line_info: Vec<AbsLineInfo>,
     pub local_vars: Vec<LocalVar>,
-    pub upvalue_names: Vec<Option<String>>,
+    pub upvalue_names: Vec<Option<Vec<u8>>>,
 }
 
 impl DebugInfo {
@@ -50,7 +95,7 @@ impl DebugInfo {
                 lua_vector(|input| be_i8(input)),
                 lua_vector(AbsLineInfo::parse),
                 lua_vector(LocalVar::parse),
-                lua_vector(lua_string_utf8),
+                lua_vector(lua_string_owned),
             )),
             |(line_info, abs_line_info, local_vars, upvalue_names)| DebugInfo {
                 line_info,
@@ -60,6 +105,57 @@ impl DebugInfo {
             },
         )(input)
     }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        write_lua_vector(
+            &self.line_info,
+            |delta, output| output.push(*delta as u8),
+            output,
+        );
+        write_lua_vector(&self.abs_line_info, AbsLineInfo::write, output);
+        write_lua_vector(&self.local_vars, LocalVar::write, output);
+        write_lua_vector(
+            &self.upvalue_names,
+            |name, output| write_lua_string(name.as_deref(), output),
+            output,
+        );
+    }
+
+    /// Computes the register held by each entry of `local_vars`.
+    /// Locals are allocated on the stack in declaration order, so a local
+    /// lives right above every earlier local that is still active when it starts.
+    pub fn local_registers(&self) -> Vec<u8> {
+        self.local_vars
+            .iter()
+            .enumerate()
+            .map(|(index, local_var)| {
+                self.local_vars[..index]
+                    .iter()
+                    .filter(|earlier| {
+                        earlier.start_pc <= local_var.start_pc
+                            && earlier.end_pc > local_var.start_pc
+                    })
+                    .count() as u8
+            })
+            .collect()
+    }
+
+    /// The locals of `local_vars` with their registers, for looking them up
+    /// by register
+    pub fn local_scopes(&self) -> LocalScopes<'_> {
+        LocalScopes {
+            local_vars: &self.local_vars,
+            registers: self.local_registers(),
+        }
+    }
+
+    /// Name of the upvalue at `index`, if it was kept and is UTF-8
+    pub fn upvalue_name(&self, index: usize) -> Option<&str> {
+        self.upvalue_names
+            .get(index)?
+            .as_deref()
+            .and_then(|name| std::str::from_utf8(name).ok())
+    }
 }
 
 #[cfg(test)]
@@ -85,13 +181,39 @@ mod tests {
         assert_eq!(
             local_var_res.unwrap().1,
             LocalVar {
-                name: Some("r".to_string()),
+                name: Some(b"r".to_vec()),
                 start_pc: 2,
                 end_pc: 192
             }
         )
     }
 
+    #[test]
+    fn test_local_registers() {
+        let local_var = |name: &str, start_pc, end_pc| LocalVar {
+            name: Some(name.as_bytes().to_vec()),
+            start_pc,
+            end_pc,
+        };
+        let debug_info = DebugInfo {
+            line_info: vec![],
+            abs_line_info: vec![],
+            local_vars: vec![
+                local_var("a", 1, 10),
+                local_var("b", 2, 5),
+                local_var("c", 5, 10),
+                local_var("d", 6, 6),
+            ],
+            upvalue_names: vec![],
+        };
+        assert_eq!(debug_info.local_registers(), vec![0, 1, 1, 2]);
+        let scopes = debug_info.local_scopes();
+        assert_eq!(scopes.local_index(0, 9), Some(0));
+        assert_eq!(scopes.local_name(1, 4), Some("b"));
+        assert_eq!(scopes.local_name(1, 5), Some("c"));
+        assert_eq!(scopes.local_name(2, 6), None);
+    }
+
     #[test]
     fn test_debug_info_parsing() {
         let data: [u8; 0x0D] = [
@@ -105,7 +227,7 @@ mod tests {
                 line_info: vec![1, 0, 0, 0, 0, 0, 1],
                 abs_line_info: vec![],
                 local_vars: vec![],
-                upvalue_names: vec![Some("x".to_string())],
+                upvalue_names: vec![Some(b"x".to_vec())],
             }
         )
     }
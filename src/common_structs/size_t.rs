@@ -19,4 +19,28 @@ pub fn lua_size_t(input: &[u8]) -> IResult<&[u8], u64> {
         size+=1;
     }
     Ok((&input[(size+1)..], current_size))
-}
\ No newline at end of file
+}
+
+/**
+ * Writes a size_t, 7 bits per byte with the last byte flagged
+ */
+pub fn write_lua_size_t(value: u64, output: &mut Vec<u8>) {
+    let size = lua_size_t_size(value);
+    for index in (0..size).rev() {
+        let byte = ((value >> (7 * index)) & 0x7f) as u8;
+        output.push(if index == 0 { byte | 0x80 } else { byte });
+    }
+}
+
+/**
+ * Number of bytes a size_t takes when encoded
+ */
+pub fn lua_size_t_size(value: u64) -> usize {
+    let mut size = 1;
+    let mut value = value >> 7;
+    while value != 0 {
+        size += 1;
+        value >>= 7;
+    }
+    size
+}
@@ -4,7 +4,7 @@ use nom::{
     IResult,
 };
 
-use super::size_t::lua_size_t;
+use super::size_t::{lua_size_t, lua_size_t_size, write_lua_size_t};
 
 /**
  * Parses a lua string
@@ -17,14 +17,33 @@ pub fn lua_string(input: &[u8]) -> IResult<&[u8], Option<&[u8]>> {
 }
 
 /**
- * Parses a UTF-8 encoded lua string
+ * Parses a lua string into owned bytes, which need not be UTF-8
  */
-pub fn lua_string_utf8(input: &[u8]) -> IResult<&[u8], Option<String>> {
-    map(lua_string, |utf_lossy| {
-        utf_lossy.map(
-            |lossy_data| String::from_utf8_lossy(lossy_data).to_string(), // Parse UTF-8 data
-        )
-    })(input)
+pub fn lua_string_owned(input: &[u8]) -> IResult<&[u8], Option<Vec<u8>>> {
+    map(lua_string, |data| data.map(<[u8]>::to_vec))(input)
+}
+
+/**
+ * Writes a lua string, `None` being the string that does not exist
+ */
+pub fn write_lua_string(string: Option<&[u8]>, output: &mut Vec<u8>) {
+    match string {
+        Some(string) => {
+            write_lua_size_t(string.len() as u64 + 1, output);
+            output.extend_from_slice(string);
+        }
+        None => write_lua_size_t(0, output),
+    }
+}
+
+/**
+ * Number of bytes a lua string takes when encoded
+ */
+pub fn lua_string_size(string: Option<&[u8]>) -> usize {
+    match string {
+        Some(string) => lua_size_t_size(string.len() as u64 + 1) + string.len(),
+        None => lua_size_t_size(0),
+    }
 }
 
 fn lua_string_data(size: u64) -> impl FnMut(&[u8]) -> IResult<&[u8], Option<&[u8]>> {
@@ -33,7 +52,7 @@ fn lua_string_data(size: u64) -> impl FnMut(&[u8]) -> IResult<&[u8], Option<&[u8
             // String does not exist
             return Ok((input, None));
         }
-        map(take(size - 1), |data| Some(data))(input) // Resolve the string
+        map(take(size - 1), Some)(input) // Resolve the string
     }
 }
 
@@ -44,7 +63,7 @@ mod tests {
     #[test]
     fn test_string_does_not_exist() {
         let buf = &[0x80u8];
-        let res = lua_string_utf8(buf);
+        let res = lua_string_owned(buf);
         assert!(res.is_ok());
         if let Ok((next_input, str)) = res {
             assert_eq!(None, str);
@@ -65,4 +84,13 @@ mod tests {
         assert!(res.is_ok());
         assert_eq!(Some(&b"abc"[..]), res.unwrap().1);
     }
+    #[test]
+    fn test_non_utf8_string() {
+        let buf = &b"\x83\xff\xfe"[..];
+        let res = lua_string_owned(buf);
+        assert_eq!(Some(b"\xff\xfe".to_vec()), res.unwrap().1);
+        let mut output = vec![];
+        write_lua_string(Some(b"\xff\xfe"), &mut output);
+        assert_eq!(buf, output);
+    }
 }
@@ -24,7 +24,7 @@ impl Upvalue {
                 if let Some(variable_kind) = VariableKind::from_u8(kind_byte) {
                 Ok(Upvalue {
                     in_stack: in_stack_byte == 1,
-                    index: index,
+                    index,
                     kind: variable_kind,
                 })
 
@@ -34,6 +34,10 @@ impl Upvalue {
             },
         )(input)
     }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&[self.in_stack as u8, self.index, self.kind as u8]);
+    }
 }
 
 #[cfg(test)]
@@ -1,9 +1,18 @@
 
-#[derive(FromPrimitive, PartialEq, Debug)]
+#[derive(FromPrimitive, PartialEq, Clone, Copy, Debug)]
 // Kinds of variables
 pub enum VariableKind {
     Regular,
     Constant,
     ToBeClosed,
     CompileTimeConstant
-}
\ No newline at end of file
+}
+
+impl VariableKind {
+    /// The kind whose `Debug` name is `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..=VariableKind::CompileTimeConstant as u8)
+            .filter_map(num::FromPrimitive::from_u8)
+            .find(|kind: &VariableKind| format!("{:?}", kind) == name)
+    }
+}
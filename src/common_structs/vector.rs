@@ -1,6 +1,6 @@
 use nom::{IResult, multi::length_count};
 
-use super::size_t::lua_size_t;
+use super::size_t::{lua_size_t, write_lua_size_t};
 
 // Parses a vector
 pub fn lua_vector<T, F>(parser: F) -> impl Fn(&[u8]) -> IResult<&[u8], Vec<T>> 
@@ -10,4 +10,16 @@ where
     move |input| {
         length_count(lua_size_t, parser)(input)
     }
-}
\ No newline at end of file
+}
+
+// Writes a vector
+pub fn write_lua_vector<T>(
+    items: &[T],
+    write: impl Fn(&T, &mut Vec<u8>),
+    output: &mut Vec<u8>,
+) {
+    write_lua_size_t(items.len() as u64, output);
+    for item in items {
+        write(item, output);
+    }
+}
@@ -0,0 +1,295 @@
+use std::fmt::Display;
+
+use crate::{
+    analysis::{basic_blocks, ENV_UPVALUE_NAME},
+    binary_chunks::{function_block::FunctionBlockChunk, patch::is_pair, proto_path::ProtoPath},
+    common_structs::{constant::LuaConstant, upvalue::Upvalue, variable_kind::VariableKind},
+    instruction_parsing::{
+        instruction::Instruction, instruction_encodings::InstructionEncoding, opcodes::Opcode,
+    },
+    lua_file::LuaFile,
+};
+
+/// The global function probes call by default
+pub const DEFAULT_HOOK: &str = "__cov";
+/// Largest constant index `GetTabup` reads
+const MAX_C: usize = 0xff;
+/// Largest constant index `LoadK` reads
+const MAX_BX: usize = 0x1ffff;
+/// Largest integer `LoadI` loads
+const MAX_SBX: usize = 0xffff;
+/// Registers a probe uses above those of the function
+const PROBE_REGISTERS: u8 = 2;
+
+/// A call to the hook inserted at the start of a basic block
+#[derive(Debug, PartialEq, Clone)]
+pub struct Probe {
+    /// The argument passed to the hook
+    pub id: usize,
+    pub path: ProtoPath,
+    /// The pc of the block in the function before instrumentation
+    pub pc: usize,
+    pub line: Option<u64>,
+}
+
+/// The probes of an instrumented file, by id
+#[derive(Debug, PartialEq, Default)]
+pub struct CoverageMap {
+    pub probes: Vec<Probe>,
+}
+
+impl Display for CoverageMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "id\tfunction\tpc\tline")?;
+        for probe in &self.probes {
+            let line = probe
+                .line
+                .map_or_else(|| "-".to_string(), |line| line.to_string());
+            writeln!(f, "{}\t{}\t{}\t{}", probe.id, probe.path, probe.pc, line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Inserts a call to the global function `hook`, with the id of the probe,
+/// at the start of every basic block of every function block. Blocks are
+/// left out where a call cannot be inserted: between an instruction and the
+/// one it skips or extends, at the iterator call of a generic `for`, after a
+/// call leaving its results open, and in functions without two registers to
+/// spare.
+pub fn instrument(file: &mut LuaFile, hook: &str) -> CoverageMap {
+    let main = &mut file.main_function_block;
+    // Lua sets the first upvalue of the main function to the globals
+    if main.upvalues.is_empty() {
+        add_upvalue(main, true, 0);
+        file.number_of_upvalues = 1;
+    }
+    let mut map = CoverageMap::default();
+    instrument_function(main, ProtoPath::main(), 0, hook, &mut map);
+    map
+}
+
+fn add_upvalue(function: &mut FunctionBlockChunk, in_stack: bool, index: u8) -> u8 {
+    function.upvalues.push(Upvalue {
+        in_stack,
+        index,
+        kind: VariableKind::Regular,
+    });
+    if !function.debug_info.upvalue_names.is_empty() {
+        function
+            .debug_info
+            .upvalue_names
+            .push(Some(ENV_UPVALUE_NAME.as_bytes().to_vec()));
+    }
+    (function.upvalues.len() - 1) as u8
+}
+
+fn encode(opcode: Opcode, operands: &[i64]) -> u32 {
+    InstructionEncoding::from_operands(opcode, operands)
+        .expect("probe operands are in range")
+        .encode()
+}
+
+/// The instructions calling `hook`, from the upvalue `env`, with `id`, using
+/// the registers from `register`
+fn probe(
+    function: &mut FunctionBlockChunk,
+    env: u8,
+    hook: &str,
+    id: usize,
+    register: u8,
+) -> Option<Vec<u32>> {
+    let (register, env) = (register as i64, env as i64);
+    let name = function.add_constant(LuaConstant::String(hook.as_bytes().to_vec()));
+    let mut instructions = if name <= MAX_C {
+        vec![encode(Opcode::GetTabup, &[register, env, name as i64, 0])]
+    } else if name <= MAX_BX {
+        vec![
+            encode(Opcode::GetUpval, &[register, env, 0, 0]),
+            encode(Opcode::LoadK, &[register + 1, name as i64]),
+            encode(Opcode::GetTable, &[register, register, register + 1, 0]),
+        ]
+    } else {
+        return None;
+    };
+    if id <= MAX_SBX {
+        instructions.push(encode(Opcode::LoadI, &[register + 1, id as i64]));
+    } else {
+        let id = function.add_constant(LuaConstant::Integer(id as i64));
+        if id > MAX_BX {
+            return None;
+        }
+        instructions.push(encode(Opcode::LoadK, &[register + 1, id as i64]));
+    }
+    instructions.push(encode(Opcode::Call, &[register, 2, 1, 0]));
+    Some(instructions)
+}
+
+/// Whether the instruction before `pc` leaves the stack top to the next one
+fn follows_open_results(function: &FunctionBlockChunk, pc: usize) -> bool {
+    pc.checked_sub(1)
+        .and_then(|previous| Instruction::parse_u32(function.instructions[previous]))
+        .is_some_and(|previous| {
+            matches!(
+                previous,
+                Instruction::Call(_, _, 0) | Instruction::Vararg(_, 0)
+            )
+        })
+}
+
+fn instrument_function(
+    function: &mut FunctionBlockChunk,
+    path: ProtoPath,
+    env: u8,
+    hook: &str,
+    map: &mut CoverageMap,
+) {
+    let register = function.maximum_stack_size;
+    if register <= u8::MAX - PROBE_REGISTERS {
+        let mut pcs = vec![];
+        for block in basic_blocks(function) {
+            let mut pc = block.start;
+            // The frame of a vararg function is only set up after `VarargPrep`
+            if let Some(Instruction::VarargPrep(_)) =
+                Instruction::parse_u32(function.instructions[pc])
+            {
+                pc += 1;
+            }
+            if !is_pair(&function.instructions, pc) && !follows_open_results(function, pc) {
+                pcs.push(pc);
+            }
+        }
+        pcs.dedup();
+        let first_id = map.probes.len();
+        let mut probes = vec![];
+        for pc in &pcs {
+            let id = first_id + probes.len();
+            let Some(instructions) = probe(function, env, hook, id, register) else {
+                break;
+            };
+            probes.push((*pc, instructions));
+            map.probes.push(Probe {
+                id,
+                path: path.clone(),
+                pc: *pc,
+                line: function.line_at(*pc),
+            });
+        }
+        // From the last block, so that the pcs of the others stay the same
+        for (pc, instructions) in probes.into_iter().rev() {
+            function
+                .insert_instructions(pc, &instructions)
+                .expect("probes are inserted between blocks");
+        }
+        function.maximum_stack_size = register + PROBE_REGISTERS;
+    }
+    for index in 0..function.protos.len() {
+        let proto = &mut function.protos[index];
+        let found = proto
+            .upvalues
+            .iter()
+            .position(|upvalue| !upvalue.in_stack && upvalue.index == env);
+        let proto_env = match found {
+            Some(index) => index as u8,
+            None => add_upvalue(proto, false, env),
+        };
+        instrument_function(proto, path.child(index), proto_env, hook, map);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{binary_chunks::proto_path::ProtoPath, lua_file::LuaFile};
+
+    use super::{instrument, DEFAULT_HOOK};
+
+    /// Runs `chunk` with `print` and the hook recording their arguments,
+    /// returning what was printed and the probes hit
+    fn run(chunk: Vec<u8>) -> (String, Vec<usize>) {
+        let lua = unsafe { mlua::Lua::unsafe_new() };
+        lua.load(indoc! {"
+            printed, hits, callbacks = {}, {}, {}
+            function print(...)
+                for _, value in ipairs({...}) do
+                    printed[#printed + 1] = tostring(value)
+                end
+            end
+            function __cov(id)
+                hits[#hits + 1] = id
+            end
+        "})
+            .exec()
+            .unwrap();
+        lua.load(chunk).exec().unwrap();
+        lua.load("if callbacks[1] then callbacks[1]() end")
+            .exec()
+            .unwrap();
+        let printed: Vec<String> = lua
+            .load("printed")
+            .eval::<mlua::Table>()
+            .unwrap()
+            .sequence_values()
+            .collect::<mlua::Result<_>>()
+            .unwrap();
+        let hits = lua.load("hits").eval::<Vec<usize>>().unwrap();
+        (printed.join(" "), hits)
+    }
+
+    #[test]
+    fn test_instrument() {
+        let chunk = include_bytes!("../tests/goto.luac");
+        let mut file = LuaFile::parse(chunk).unwrap().1;
+        let map = instrument(&mut file, DEFAULT_HOOK);
+        let (printed, hits) = run(file.to_bytes());
+        assert_eq!(printed, run(chunk.to_vec()).0);
+        assert_eq!(hits[0], 0);
+        assert!(hits.iter().all(|id| *id < map.probes.len()));
+        // The function made in the loop is called once
+        let callback = map
+            .probes
+            .iter()
+            .find(|probe| probe.path == ProtoPath(vec![0]))
+            .unwrap();
+        assert_eq!(hits.last(), Some(&callback.id));
+        assert_eq!(
+            map.to_string().lines().take(2).collect::<Vec<_>>(),
+            ["id\tfunction\tpc\tline", "0\tmain\t1\t2"]
+        );
+    }
+
+    #[test]
+    fn test_instrument_generic_for() {
+        let lua = mlua::Lua::new();
+        let function = lua
+            .load("for _, value in ipairs({1, 2, 3}) do print(value) end")
+            .into_function()
+            .unwrap();
+        let chunk = function.dump(false);
+        let mut file = LuaFile::parse(&chunk).unwrap().1;
+        let map = instrument(&mut file, DEFAULT_HOOK);
+        let (printed, hits) = run(file.to_bytes());
+        assert_eq!(printed, "1 2 3");
+        // The start, the body three times and the end, the call of the
+        // iterator having no probe before it
+        assert_eq!(map.probes.len(), 3);
+        assert_eq!(hits, [0, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn test_instrument_closures() {
+        let chunk = include_bytes!("../tests/closures.luac");
+        let mut file = LuaFile::parse(chunk).unwrap().1;
+        let map = instrument(&mut file, DEFAULT_HOOK);
+        let lua = unsafe { mlua::Lua::unsafe_new() };
+        lua.load("hit = {} function __cov(id) hit[id] = true end print = function() end")
+            .exec()
+            .unwrap();
+        lua.load(file.to_bytes()).exec().unwrap();
+        // Every function runs, through upvalues added to reach the globals
+        for path in (0..4).map(|index| ProtoPath(vec![index])) {
+            let probe = map.probes.iter().find(|probe| probe.path == path).unwrap();
+            let hit: bool = lua.load(format!("hit[{}]", probe.id)).eval().unwrap();
+            assert!(hit, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,377 @@
+const KEYWORDS: [&str; 22] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Checks whether a string can be written as a Lua name
+pub fn is_name(string: &str) -> bool {
+    let mut chars = string.chars();
+    matches!(chars.next(), Some(first) if first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|char| char.is_ascii_alphanumeric() || char == '_')
+        && !KEYWORDS.contains(&string)
+}
+
+/// Binary operators of Lua 5.4
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Ne,
+    Eq,
+    BOr,
+    BXor,
+    BAnd,
+    Shl,
+    Shr,
+    Concat,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    IDiv,
+    Mod,
+    Pow,
+}
+
+impl BinaryOperator {
+    /// Precedence of the operator, higher binds tighter
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::Lt
+            | BinaryOperator::Gt
+            | BinaryOperator::Le
+            | BinaryOperator::Ge
+            | BinaryOperator::Ne
+            | BinaryOperator::Eq => 3,
+            BinaryOperator::BOr => 4,
+            BinaryOperator::BXor => 5,
+            BinaryOperator::BAnd => 6,
+            BinaryOperator::Shl | BinaryOperator::Shr => 7,
+            BinaryOperator::Concat => 8,
+            BinaryOperator::Add | BinaryOperator::Sub => 9,
+            BinaryOperator::Mul
+            | BinaryOperator::Div
+            | BinaryOperator::IDiv
+            | BinaryOperator::Mod => 10,
+            BinaryOperator::Pow => 12,
+        }
+    }
+
+    pub fn is_right_associative(&self) -> bool {
+        matches!(self, BinaryOperator::Concat | BinaryOperator::Pow)
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOperator::Or => "or",
+            BinaryOperator::And => "and",
+            BinaryOperator::Lt => "<",
+            BinaryOperator::Gt => ">",
+            BinaryOperator::Le => "<=",
+            BinaryOperator::Ge => ">=",
+            BinaryOperator::Ne => "~=",
+            BinaryOperator::Eq => "==",
+            BinaryOperator::BOr => "|",
+            BinaryOperator::BXor => "~",
+            BinaryOperator::BAnd => "&",
+            BinaryOperator::Shl => "<<",
+            BinaryOperator::Shr => ">>",
+            BinaryOperator::Concat => "..",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Sub => "-",
+            BinaryOperator::Mul => "*",
+            BinaryOperator::Div => "/",
+            BinaryOperator::IDiv => "//",
+            BinaryOperator::Mod => "%",
+            BinaryOperator::Pow => "^",
+        }
+    }
+}
+
+/// Unary operators of Lua 5.4
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UnaryOperator {
+    Neg,
+    Not,
+    Len,
+    BNot,
+}
+
+impl UnaryOperator {
+    /// Precedence of every unary operator, between `*` and `^`
+    pub const PRECEDENCE: u8 = 11;
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOperator::Neg => "-",
+            UnaryOperator::Not => "not ",
+            UnaryOperator::Len => "#",
+            UnaryOperator::BNot => "~",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expression {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    /// The bytes of a string, which need not be UTF-8
+    String(Vec<u8>),
+    Vararg,
+    /// A local, upvalue or global variable
+    Name(String),
+    /// `table[key]`, printed as `table.key` when the key is a name
+    Index(Box<Expression>, Box<Expression>),
+    Call(Box<FunctionCall>),
+    Function(Box<FunctionBody>),
+    Table(Vec<TableField>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Unary(UnaryOperator, Box<Expression>),
+    /// A parenthesized expression, which truncates multiple results to one
+    Paren(Box<Expression>),
+}
+
+impl Expression {
+    pub fn name(name: &str) -> Self {
+        Expression::Name(name.to_string())
+    }
+
+    pub fn index(table: Expression, key: Expression) -> Self {
+        Expression::Index(Box::new(table), Box::new(key))
+    }
+
+    /// `table.field`
+    pub fn field(table: Expression, field: &str) -> Self {
+        Expression::index(table, Expression::String(field.as_bytes().to_vec()))
+    }
+
+    /// The string this expression is, when it can be written as a name
+    pub fn as_name(&self) -> Option<&str> {
+        match self {
+            Expression::String(string) => std::str::from_utf8(string)
+                .ok()
+                .filter(|name| is_name(name)),
+            _ => None,
+        }
+    }
+
+    pub fn binary(operator: BinaryOperator, left: Expression, right: Expression) -> Self {
+        Expression::Binary(operator, Box::new(left), Box::new(right))
+    }
+
+    pub fn unary(operator: UnaryOperator, operand: Expression) -> Self {
+        Expression::Unary(operator, Box::new(operand))
+    }
+
+    pub fn call(function: Expression, arguments: Vec<Expression>) -> Self {
+        Expression::Call(Box::new(FunctionCall::new(function, arguments)))
+    }
+
+    /// Checks whether the expression can result in several values
+    pub fn is_multiple_results(&self) -> bool {
+        matches!(self, Expression::Call(_) | Expression::Vararg)
+    }
+
+    /// Checks whether `expression` is this expression or part of it
+    pub fn contains(&self, expression: &Expression) -> bool {
+        if self == expression {
+            return true;
+        }
+        match self {
+            Expression::Index(table, key) => table.contains(expression) || key.contains(expression),
+            Expression::Call(call) => {
+                call.function.contains(expression)
+                    || call
+                        .arguments
+                        .iter()
+                        .any(|argument| argument.contains(expression))
+            }
+            Expression::Table(fields) => fields.iter().any(|field| match field {
+                TableField::Positional(value) => value.contains(expression),
+                TableField::Keyed(key, value) => {
+                    key.contains(expression) || value.contains(expression)
+                }
+            }),
+            Expression::Binary(_, left, right) => {
+                left.contains(expression) || right.contains(expression)
+            }
+            Expression::Unary(_, operand) | Expression::Paren(operand) => {
+                operand.contains(expression)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A function or method call
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionCall {
+    pub function: Expression,
+    /// Method name of `function:method(arguments)` calls
+    pub method: Option<String>,
+    pub arguments: Vec<Expression>,
+}
+
+impl FunctionCall {
+    pub fn new(function: Expression, arguments: Vec<Expression>) -> Self {
+        FunctionCall {
+            function,
+            method: None,
+            arguments,
+        }
+    }
+
+    pub fn method(object: Expression, method: &str, arguments: Vec<Expression>) -> Self {
+        FunctionCall {
+            function: object,
+            method: Some(method.to_string()),
+            arguments,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TableField {
+    /// `value`, stored at the next array index
+    Positional(Expression),
+    /// `[key] = value`, printed as `key = value` when the key is a name
+    Keyed(Expression, Expression),
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FunctionBody {
+    pub parameters: Vec<String>,
+    pub is_vararg: bool,
+    pub body: Block,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LocalAttribute {
+    Const,
+    Close,
+}
+
+/// A name declared by a `local` statement
+#[derive(Debug, PartialEq, Clone)]
+pub struct LocalName {
+    pub name: String,
+    pub attribute: Option<LocalAttribute>,
+}
+
+impl LocalName {
+    pub fn new(name: &str) -> Self {
+        LocalName {
+            name: name.to_string(),
+            attribute: None,
+        }
+    }
+}
+
+/// Name of a `function a.b.c:m() end` statement
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionName {
+    pub base: String,
+    pub fields: Vec<String>,
+    pub method: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    Local {
+        names: Vec<LocalName>,
+        values: Vec<Expression>,
+    },
+    Assign {
+        targets: Vec<Expression>,
+        values: Vec<Expression>,
+    },
+    Call(FunctionCall),
+    Do(Block),
+    While {
+        condition: Expression,
+        body: Block,
+    },
+    Repeat {
+        body: Block,
+        condition: Expression,
+    },
+    If {
+        /// The `if` and `elseif` conditions with their blocks
+        branches: Vec<(Expression, Block)>,
+        else_block: Option<Block>,
+    },
+    NumericFor {
+        variable: String,
+        start: Expression,
+        limit: Expression,
+        step: Option<Expression>,
+        body: Block,
+    },
+    GenericFor {
+        names: Vec<String>,
+        expressions: Vec<Expression>,
+        body: Block,
+    },
+    Function {
+        name: FunctionName,
+        body: FunctionBody,
+    },
+    LocalFunction {
+        name: String,
+        body: FunctionBody,
+    },
+    Return(Vec<Expression>),
+    Break,
+    Goto(String),
+    Label(String),
+    Comment(String),
+}
+
+/// Where a statement was compiled from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Position {
+    /// Pc of the first instruction of the statement
+    pub pc: usize,
+    /// Source line of the statement, if the line information was kept
+    pub line: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    /// Positions of the statements, empty when they are not known
+    pub positions: Vec<Position>,
+}
+
+impl Block {
+    pub fn new(statements: Vec<Statement>) -> Self {
+        Block {
+            statements,
+            positions: vec![],
+        }
+    }
+
+    pub fn with_positions(statements: Vec<Statement>, positions: Vec<Position>) -> Self {
+        Block {
+            statements,
+            positions,
+        }
+    }
+
+    /// Position of the statement at `index`, if known
+    pub fn position(&self, index: usize) -> Option<Position> {
+        if self.positions.len() == self.statements.len() {
+            self.positions.get(index).copied()
+        } else {
+            None
+        }
+    }
+}
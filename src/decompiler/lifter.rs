@@ -0,0 +1,1295 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    analysis::{decode, is_env_upvalue, ENV_UPVALUE_NAME},
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    common_structs::{constant::LuaConstant, upvalue::Upvalue},
+    instruction_parsing::instruction::Instruction,
+};
+
+use super::{
+    ast::{
+        BinaryOperator, Block, Expression, FunctionBody, FunctionCall, FunctionName,
+        LocalAttribute, LocalName, Position, Statement, TableField, UnaryOperator,
+    },
+    locals::{debug_locals, infer_locals, is_stripped, Local},
+    DecompileError, Decompiler,
+};
+
+mod control_flow;
+mod values;
+
+/// Operator of the metamethod event of a `MmBin` instruction, numbered as in `ltm.h`
+fn metamethod_operator(event: u8) -> Option<BinaryOperator> {
+    match event {
+        6 => Some(BinaryOperator::Add),
+        7 => Some(BinaryOperator::Sub),
+        8 => Some(BinaryOperator::Mul),
+        9 => Some(BinaryOperator::Mod),
+        10 => Some(BinaryOperator::Pow),
+        11 => Some(BinaryOperator::Div),
+        12 => Some(BinaryOperator::IDiv),
+        13 => Some(BinaryOperator::BAnd),
+        14 => Some(BinaryOperator::BOr),
+        15 => Some(BinaryOperator::BXor),
+        16 => Some(BinaryOperator::Shl),
+        17 => Some(BinaryOperator::Shr),
+        _ => None,
+    }
+}
+
+/// Whether a function block or the functions nested in it have the string
+/// constant `name`, like the name of a global they use
+fn mentions(function: &FunctionBlockChunk, name: &str) -> bool {
+    function
+        .constants
+        .iter()
+        .any(|constant| constant.as_bytes() == Some(name.as_bytes()))
+        || function.protos.iter().any(|proto| mentions(proto, name))
+}
+
+/// The name of `function a.b.c() end` that assigns `target`
+fn function_name(target: &Expression) -> Option<FunctionName> {
+    match target {
+        Expression::Name(base) => Some(FunctionName {
+            base: base.clone(),
+            fields: vec![],
+            method: None,
+        }),
+        Expression::Index(table, key) => {
+            let field = key.as_name()?;
+            let mut name = function_name(table)?;
+            name.fields.push(field.to_string());
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+/// A value held by a register that was not assigned to a variable yet
+#[derive(Debug, Clone)]
+enum Value {
+    Expression(Expression),
+    /// Function of a `Self_` instruction, called as `object:method()`
+    Method(Expression, String),
+    /// Object of a `Self_` instruction, passed to its method
+    SelfArgument,
+    /// A result after the first of a call or vararg with several results
+    ExtraResult,
+    /// An item of a table constructor that was already added to it
+    ListItem,
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    value: Value,
+    /// Number of statements emitted before the value was computed
+    statement: usize,
+}
+
+/// Operand of an instruction, which is a register or a constant
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Constant(u32),
+}
+
+impl Operand {
+    fn rk(c: u8, k: u8) -> Self {
+        if k == 1 {
+            Operand::Constant(c as u32)
+        } else {
+            Operand::Register(c)
+        }
+    }
+}
+
+/// Translates the instructions of a function block to statements by
+/// evaluating its registers symbolically. Values are kept as expressions until
+/// they are stored in a variable, so temporaries fold into nested expressions.
+pub(super) struct Lifter<'a> {
+    decompiler: &'a Decompiler<'a>,
+    path: ProtoPath,
+    function: &'a FunctionBlockChunk,
+    instructions: Vec<Option<Instruction>>,
+    locals: Vec<Local>,
+    /// Whether the locals were inferred, so their scopes are not the ones of the source
+    inferred: bool,
+    /// Names of the variables of the enclosing function captured as upvalues
+    captures: Vec<String>,
+    registers: HashMap<u8, Pending>,
+    /// Register of the last expression with multiple results
+    top: Option<u8>,
+    /// Oldest statement count of the values read by the current instruction
+    oldest_read: Option<usize>,
+    /// Targets assigned the extra results of a call or vararg so far
+    extra_targets: Vec<Expression>,
+    /// Statements of the block being lifted
+    statements: Vec<Statement>,
+    positions: Vec<Position>,
+    /// Pc of the first instruction of the statement being lifted
+    statement_pc: Option<usize>,
+    /// Number of statements emitted so far in any block
+    emitted: usize,
+    /// Pcs of the jumps to each pc
+    jump_sources: HashMap<usize, Vec<usize>>,
+    /// Pcs reached by a `break` in the loops being lifted, innermost last
+    loop_exits: Vec<usize>,
+    /// Pc of the jump back of the `repeat` loop being lifted
+    until: Option<usize>,
+    /// Condition of that loop, once lifted
+    until_condition: Option<Expression>,
+    /// Pc after the `and`/`or` value being lifted, whose locals wait for it
+    value_end: Option<usize>,
+    /// Names of the labels jumped to by `goto` statements, by pc
+    labels: HashMap<usize, String>,
+    placed_labels: HashSet<usize>,
+}
+
+impl<'a> Lifter<'a> {
+    /// Creates the lifter of the function block at `path`, whose upvalues
+    /// capture the variables of the enclosing function named `captures`
+    pub(super) fn new(
+        decompiler: &'a Decompiler<'a>,
+        path: &ProtoPath,
+        captures: Vec<String>,
+    ) -> Result<Self, DecompileError> {
+        let function = decompiler
+            .file
+            .function_at(path)
+            .ok_or_else(|| DecompileError::MissingFunction { path: path.clone() })?;
+        let instructions: Vec<_> = decode(function)
+            .map(|(_, instruction)| instruction)
+            .collect();
+        let mut lifter = Lifter {
+            decompiler,
+            path: path.clone(),
+            function,
+            jump_sources: Lifter::jump_sources(&instructions),
+            instructions,
+            locals: vec![],
+            inferred: is_stripped(function),
+            captures,
+            registers: HashMap::new(),
+            top: None,
+            oldest_read: None,
+            extra_targets: vec![],
+            statements: vec![],
+            positions: vec![],
+            statement_pc: None,
+            emitted: 0,
+            loop_exits: vec![],
+            until: None,
+            until_condition: None,
+            value_end: None,
+            labels: HashMap::new(),
+            placed_labels: HashSet::new(),
+        };
+        lifter.locals = if lifter.inferred {
+            let upvalue_names: Vec<_> = (0..function.upvalues.len())
+                .map(|index| lifter.upvalue_name(index as u8))
+                .collect();
+            infer_locals(function, path.depth(), &upvalue_names)
+        } else {
+            debug_locals(function)
+        };
+        Ok(lifter)
+    }
+
+    pub(super) fn lift(mut self) -> Result<FunctionBody, DecompileError> {
+        let parameters = (0..self.function.number_of_parameters)
+            .map(|register| self.register_name(register, 0))
+            .collect();
+        self.structure(0, self.instructions.len())?;
+        Ok(FunctionBody {
+            parameters,
+            is_vararg: self.function.is_vararg.has_arg() != 0,
+            body: Block::with_positions(self.statements, self.positions),
+        })
+    }
+
+    /// Translates the instruction at `pc`, returning the pc of the next one
+    fn lift_instruction(&mut self, pc: usize) -> Result<usize, DecompileError> {
+        let instruction = self.instruction(pc)?;
+        let next = self.instructions.get(pc + 1).copied().flatten();
+        let mut next_pc = pc + 1;
+        self.oldest_read = None;
+        match instruction {
+            Instruction::Move(a, b) => self.move_register(a, b, pc),
+            Instruction::LoadI(a, sbx) => self.write(a, Expression::Integer(sbx as i64), pc),
+            Instruction::LoadF(a, sbx) => self.write(a, Expression::Number(sbx), pc),
+            Instruction::LoadK(a, bx) => {
+                let constant = self.constant(bx, pc)?;
+                self.write(a, constant, pc)
+            }
+            Instruction::LoadKx(a) => {
+                let Some(Instruction::Extraarg(ax)) = next else {
+                    return Err(self.invalid(pc));
+                };
+                let constant = self.constant(ax, pc)?;
+                self.write(a, constant, pc);
+                next_pc += 1;
+            }
+            Instruction::LoadFalse(a) => self.write(a, Expression::Boolean(false), pc),
+            Instruction::LoadTrue(a) => self.write(a, Expression::Boolean(true), pc),
+            Instruction::LoadNil(a, b) => {
+                for register in a..=a + b {
+                    self.write(register, Expression::Nil, pc);
+                }
+            }
+            Instruction::GetUpval(a, b) => {
+                let upvalue = Expression::Name(self.upvalue_name(b));
+                self.write(a, upvalue, pc)
+            }
+            Instruction::SetUpval(a, b) => {
+                let upvalue = Expression::Name(self.upvalue_name(b));
+                self.assign(upvalue, Operand::Register(a), pc)?
+            }
+            Instruction::GetTabup(a, b, c) => {
+                let key = self.constant(c as u32, pc)?;
+                let value = self.upvalue_index(b, key);
+                self.write(a, value, pc)
+            }
+            Instruction::GetTable(a, b, c) => {
+                let table = self.read(b, pc);
+                let key = self.read(c, pc);
+                self.write(a, Expression::index(table, key), pc)
+            }
+            Instruction::GetI(a, b, c) => {
+                let table = self.read(b, pc);
+                self.write(
+                    a,
+                    Expression::index(table, Expression::Integer(c as i64)),
+                    pc,
+                )
+            }
+            Instruction::GetField(a, b, c) => {
+                let table = self.read(b, pc);
+                let key = self.constant(c as u32, pc)?;
+                self.write(a, Expression::index(table, key), pc)
+            }
+            Instruction::SetTabup(a, b, c, k) => {
+                let key = self.constant(b as u32, pc)?;
+                let target = self.upvalue_index(a, key);
+                self.assign(target, Operand::rk(c, k), pc)?
+            }
+            Instruction::SetTable(a, b, c, k) => {
+                let key = self.read(b, pc);
+                self.set_field(a, key, Operand::rk(c, k), pc)?
+            }
+            Instruction::SetI(a, b, c, k) => {
+                self.set_field(a, Expression::Integer(b as i64), Operand::rk(c, k), pc)?
+            }
+            Instruction::SetField(a, b, c, k) => {
+                let key = self.constant(b as u32, pc)?;
+                self.set_field(a, key, Operand::rk(c, k), pc)?
+            }
+            Instruction::NewTable(a, _, _, _) => self.write(a, Expression::Table(vec![]), pc),
+            Instruction::SetList(a, b, _, k) => {
+                self.set_list(a, b, pc)?;
+                // The `Extraarg` extends the first index, which the items follow anyway
+                if k == 1 {
+                    if let Some(Instruction::Extraarg(_)) = next {
+                        next_pc += 1;
+                    }
+                }
+            }
+            Instruction::Self_(a, b, c, k) => {
+                let object = self.read(b, pc);
+                let key = self.operand(Operand::rk(c, k), pc)?;
+                let statement = self.oldest_read.unwrap_or(self.emitted);
+                let (method, argument) = match key.as_name().map(str::to_string) {
+                    Some(method) => (Value::Method(object, method), Value::SelfArgument),
+                    None => (
+                        Value::Expression(Expression::index(object.clone(), key)),
+                        Value::Expression(object),
+                    ),
+                };
+                self.set_pending(a, method, statement);
+                self.set_pending(a + 1, argument, statement);
+            }
+            Instruction::AddI(a, b, sc) => {
+                self.arithmetic_immediate(a, b, BinaryOperator::Add, sc, false, next, pc)
+            }
+            Instruction::ShrI(a, b, sc) => {
+                self.arithmetic_immediate(a, b, BinaryOperator::Shr, sc, false, next, pc)
+            }
+            Instruction::ShlI(a, b, sc) => {
+                self.arithmetic_immediate(a, b, BinaryOperator::Shl, sc, true, next, pc)
+            }
+            Instruction::AddK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::Add, c, next, pc)?
+            }
+            Instruction::SubK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::Sub, c, next, pc)?
+            }
+            Instruction::MulK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::Mul, c, next, pc)?
+            }
+            Instruction::ModK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::Mod, c, next, pc)?
+            }
+            Instruction::PowK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::Pow, c, next, pc)?
+            }
+            Instruction::DivK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::Div, c, next, pc)?
+            }
+            Instruction::IDivK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::IDiv, c, next, pc)?
+            }
+            Instruction::BAndK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::BAnd, c, next, pc)?
+            }
+            Instruction::BOrK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::BOr, c, next, pc)?
+            }
+            Instruction::BXorK(a, b, c) => {
+                self.arithmetic_constant(a, b, BinaryOperator::BXor, c, next, pc)?
+            }
+            Instruction::Add(a, b, c) => self.arithmetic(a, b, BinaryOperator::Add, c, pc),
+            Instruction::Sub(a, b, c) => self.arithmetic(a, b, BinaryOperator::Sub, c, pc),
+            Instruction::Mul(a, b, c) => self.arithmetic(a, b, BinaryOperator::Mul, c, pc),
+            Instruction::Mod(a, b, c) => self.arithmetic(a, b, BinaryOperator::Mod, c, pc),
+            Instruction::Pow(a, b, c) => self.arithmetic(a, b, BinaryOperator::Pow, c, pc),
+            Instruction::Div(a, b, c) => self.arithmetic(a, b, BinaryOperator::Div, c, pc),
+            Instruction::IDiv(a, b, c) => self.arithmetic(a, b, BinaryOperator::IDiv, c, pc),
+            Instruction::BAnd(a, b, c) => self.arithmetic(a, b, BinaryOperator::BAnd, c, pc),
+            Instruction::BOr(a, b, c) => self.arithmetic(a, b, BinaryOperator::BOr, c, pc),
+            Instruction::BXor(a, b, c) => self.arithmetic(a, b, BinaryOperator::BXor, c, pc),
+            Instruction::Shl(a, b, c) => self.arithmetic(a, b, BinaryOperator::Shl, c, pc),
+            Instruction::Shr(a, b, c) => self.arithmetic(a, b, BinaryOperator::Shr, c, pc),
+            // Metamethod fallbacks were folded into the arithmetic before them
+            Instruction::MmBin(_, _, _)
+            | Instruction::MmBinI(_, _, _, _)
+            | Instruction::MmBinK(_, _, _, _) => {}
+            Instruction::Unm(a, b) => self.unary(a, b, UnaryOperator::Neg, pc),
+            Instruction::BNot(a, b) => self.unary(a, b, UnaryOperator::BNot, pc),
+            Instruction::Not(a, b) => self.unary(a, b, UnaryOperator::Not, pc),
+            Instruction::Len(a, b) => self.unary(a, b, UnaryOperator::Len, pc),
+            Instruction::Concat(a, b) => {
+                let mut operands = self.read_list(a, a + b, false, pc);
+                let last = operands.pop().ok_or_else(|| self.invalid(pc))?;
+                let concatenation = operands.into_iter().rev().fold(last, |right, left| {
+                    Expression::binary(BinaryOperator::Concat, left, right)
+                });
+                self.write(a, concatenation, pc)
+            }
+            // `<close>` variables are declared by `declare_locals` and closed by their block
+            Instruction::Close(_) | Instruction::Tbc(_) => {}
+            Instruction::Call(a, b, c) => {
+                let call = self.function_call(a, b, pc);
+                match c {
+                    0 => {
+                        self.set_pending(
+                            a,
+                            Value::Expression(Expression::Call(Box::new(call))),
+                            self.emitted,
+                        );
+                        self.top = Some(a);
+                    }
+                    1 => self.emit(Statement::Call(call)),
+                    _ => self.write_results(a, c - 1, Expression::Call(Box::new(call)), pc),
+                }
+            }
+            Instruction::TailCall(a, b, _, _) => {
+                let call = self.function_call(a, b, pc);
+                self.emit(Statement::Return(vec![Expression::Call(Box::new(call))]));
+                if let Some(Instruction::Return(_, _, _, _)) = next {
+                    next_pc += 1;
+                }
+            }
+            Instruction::Return(a, b, _, _) => {
+                let end = if b == 0 { self.top_end(a) } else { a + b - 1 };
+                let values = self.read_list(a, end, b != 0, pc);
+                self.return_values(values, pc)
+            }
+            Instruction::Return0() => self.return_values(vec![], pc),
+            Instruction::Return1(a) => {
+                let values = self.read_list(a, a + 1, true, pc);
+                self.return_values(values, pc)
+            }
+            Instruction::Closure(a, bx) => {
+                let proto = self
+                    .function
+                    .protos
+                    .get(bx as usize)
+                    .ok_or_else(|| self.invalid(pc))?;
+                let captures = proto
+                    .upvalues
+                    .iter()
+                    .map(|upvalue| self.capture_name(upvalue, pc))
+                    .collect();
+                let body = Lifter::new(self.decompiler, &self.path.child(bx as usize), captures)?
+                    .lift()?;
+                self.write(a, Expression::Function(Box::new(body)), pc)
+            }
+            Instruction::Vararg(a, c) => {
+                if c == 0 {
+                    self.set_pending(a, Value::Expression(Expression::Vararg), self.emitted);
+                    self.top = Some(a);
+                } else if c > 1 {
+                    self.write_results(a, c - 1, Expression::Vararg, pc)
+                }
+            }
+            Instruction::VarargPrep(_) | Instruction::Extraarg(_) => {}
+            instruction => {
+                return Err(DecompileError::UnsupportedInstruction {
+                    path: self.path.clone(),
+                    pc,
+                    instruction: format!("{:?}", instruction),
+                })
+            }
+        }
+        self.declare_locals(pc + 1..=next_pc);
+        Ok(next_pc)
+    }
+
+    fn emit(&mut self, statement: Statement) {
+        // Statements lifted from the same instruction share its position
+        let pc = self
+            .statement_pc
+            .take()
+            .or_else(|| self.positions.last().map(|position| position.pc))
+            .unwrap_or_default();
+        self.positions.push(self.position(pc));
+        self.statements.push(statement);
+        self.emitted += 1;
+    }
+
+    /// Position of the statement starting at `pc`. A closure is created at
+    /// the end of its function, which is defined at its first line.
+    fn position(&self, pc: usize) -> Position {
+        let line = self
+            .function
+            .line_at(pc)
+            .map(|line| match self.instructions[pc] {
+                Some(Instruction::Closure(_, bx)) => self
+                    .function
+                    .protos
+                    .get(bx as usize)
+                    .map_or(line, |proto| proto.source_line_start),
+                _ => line,
+            });
+        Position { pc, line }
+    }
+
+    fn instruction(&self, pc: usize) -> Result<Instruction, DecompileError> {
+        self.instructions
+            .get(pc)
+            .copied()
+            .flatten()
+            .ok_or_else(|| self.invalid(pc))
+    }
+
+    fn invalid(&self, pc: usize) -> DecompileError {
+        DecompileError::InvalidInstruction {
+            path: self.path.clone(),
+            pc,
+        }
+    }
+
+    fn constant(&self, index: u32, pc: usize) -> Result<Expression, DecompileError> {
+        match self.function.constants.get(index as usize) {
+            Some(LuaConstant::Nil) => Ok(Expression::Nil),
+            Some(LuaConstant::Boolean(boolean)) => Ok(Expression::Boolean(*boolean)),
+            Some(LuaConstant::Number(number)) => Ok(Expression::Number(*number)),
+            Some(LuaConstant::Integer(integer)) => Ok(Expression::Integer(*integer)),
+            Some(LuaConstant::String(string)) => Ok(Expression::String(string.clone())),
+            None => Err(self.invalid(pc)),
+        }
+    }
+
+    fn operand(&mut self, operand: Operand, pc: usize) -> Result<Expression, DecompileError> {
+        match operand {
+            Operand::Register(register) => Ok(self.read(register, pc)),
+            Operand::Constant(index) => self.constant(index, pc),
+        }
+    }
+
+    /// Index in the locals of the local held by `register` at `pc`
+    fn active_local(&self, register: u8, pc: usize) -> Option<usize> {
+        self.locals.iter().rposition(|local| {
+            local.register == register && local.start_pc <= pc && pc < local.end_pc
+        })
+    }
+
+    fn local_name(&self, index: usize) -> String {
+        self.locals[index].name.clone()
+    }
+
+    /// Name of the variable held by `register` at `pc`
+    fn register_name(&self, register: u8, pc: usize) -> String {
+        match self.active_local(register, pc) {
+            Some(index) => self.local_name(index),
+            None => format!("r{}", register),
+        }
+    }
+
+    fn upvalue_name(&self, index: u8) -> String {
+        match self.function.debug_info.upvalue_name(index as usize) {
+            Some(name) => name.to_string(),
+            None => match self.captures.get(index as usize) {
+                Some(name) => name.clone(),
+                None if is_env_upvalue(self.decompiler.file, &self.path, index) => {
+                    ENV_UPVALUE_NAME.to_string()
+                }
+                None => format!("upv{}", index),
+            },
+        }
+    }
+
+    /// Name of the variable captured by an upvalue of a closure made at `pc`.
+    /// A `local function` captures its own local, which starts after `pc`.
+    fn capture_name(&self, upvalue: &Upvalue, pc: usize) -> String {
+        if !upvalue.in_stack {
+            return self.upvalue_name(upvalue.index);
+        }
+        match self
+            .active_local(upvalue.index, pc)
+            .or_else(|| self.active_local(upvalue.index, pc + 1))
+        {
+            Some(index) => self.local_name(index),
+            None => format!("r{}", upvalue.index),
+        }
+    }
+
+    /// `UpValue[index][key]`, which is a global variable when the upvalue is `_ENV`
+    fn upvalue_index(&self, index: u8, key: Expression) -> Expression {
+        if is_env_upvalue(self.decompiler.file, &self.path, index) {
+            match key.as_name() {
+                Some(name) => Expression::name(name),
+                None => Expression::index(Expression::name(ENV_UPVALUE_NAME), key),
+            }
+        } else {
+            Expression::index(Expression::Name(self.upvalue_name(index)), key)
+        }
+    }
+
+    fn take(&mut self, register: u8) -> Option<Value> {
+        let pending = self.registers.remove(&register)?;
+        self.oldest_read = Some(
+            self.oldest_read
+                .map_or(pending.statement, |oldest| oldest.min(pending.statement)),
+        );
+        Some(pending.value)
+    }
+
+    /// Reads a register as an expression, consuming the value it holds
+    fn read(&mut self, register: u8, pc: usize) -> Expression {
+        match self.take(register) {
+            Some(Value::Expression(expression)) => expression,
+            Some(Value::Method(object, method)) => {
+                Expression::index(object, Expression::String(method.into_bytes()))
+            }
+            Some(Value::SelfArgument | Value::ExtraResult | Value::ListItem) | None => {
+                Expression::Name(self.register_name(register, pc))
+            }
+        }
+    }
+
+    /// Reads the registers `first..end`. When the list has a fixed length,
+    /// a last expression with multiple results is truncated to one.
+    fn read_list(&mut self, first: u8, end: u8, fixed: bool, pc: usize) -> Vec<Expression> {
+        let mut expressions: Vec<_> = (first..end)
+            .map(|register| self.read(register, pc))
+            .collect();
+        if fixed {
+            if let Some(last) = expressions.pop() {
+                expressions.push(if last.is_multiple_results() {
+                    Expression::Paren(Box::new(last))
+                } else {
+                    last
+                });
+            }
+        } else {
+            self.top = None;
+        }
+        expressions
+    }
+
+    /// End of a list of registers that goes up to the last multiple results
+    fn top_end(&self, first: u8) -> u8 {
+        self.top.map_or(first, |top| top + 1)
+    }
+
+    fn set_pending(&mut self, register: u8, value: Value, statement: usize) {
+        // A call whose result is never used still has to be made
+        if let Some(Pending {
+            value: Value::Expression(Expression::Call(call)),
+            ..
+        }) = self.registers.remove(&register)
+        {
+            self.emit(Statement::Call(*call));
+        }
+        self.registers
+            .insert(register, Pending { value, statement });
+    }
+
+    /// Stores a value in a register, assigning it when the register holds a local
+    fn write(&mut self, register: u8, value: Expression, pc: usize) {
+        match self.active_local(register, pc) {
+            Some(index) => {
+                let target = Expression::Name(self.local_name(index));
+                self.emit_assignment(target, value)
+            }
+            None => {
+                let statement = self.oldest_read.unwrap_or(self.emitted);
+                self.set_pending(register, Value::Expression(value), statement)
+            }
+        }
+    }
+
+    /// Stores the `count` results of a call or vararg starting at `register`
+    fn write_results(&mut self, register: u8, count: u8, value: Expression, pc: usize) {
+        self.write(register, value, pc);
+        for extra in register + 1..register + count {
+            self.set_pending(extra, Value::ExtraResult, self.emitted);
+        }
+    }
+
+    fn move_register(&mut self, a: u8, b: u8, pc: usize) {
+        if self.active_local(a, pc).is_some() {
+            let target = Expression::Name(self.register_name(a, pc));
+            // Moving a register never fails
+            let _ = self.assign(target, Operand::Register(b), pc);
+            return;
+        }
+        let value = match self.take(b) {
+            Some(value) => value,
+            None => Value::Expression(Expression::Name(self.register_name(b, pc))),
+        };
+        let statement = self.oldest_read.unwrap_or(self.emitted);
+        self.set_pending(a, value, statement);
+    }
+
+    fn assign(
+        &mut self,
+        target: Expression,
+        value: Operand,
+        pc: usize,
+    ) -> Result<(), DecompileError> {
+        if let Operand::Register(register) = value {
+            if let Some(Pending {
+                value: Value::ExtraResult,
+                ..
+            }) = self.registers.get(&register)
+            {
+                self.registers.remove(&register);
+                self.extra_targets.push(target);
+                return Ok(());
+            }
+        }
+        let value = self.operand(value, pc)?;
+        if self.extra_targets.is_empty() {
+            self.emit_assignment(target, value);
+        } else {
+            // The extra results are assigned last to first, before the first one
+            let mut targets = vec![target];
+            targets.extend(self.extra_targets.drain(..).rev());
+            self.emit(Statement::Assign {
+                targets,
+                values: vec![value],
+            });
+        }
+        Ok(())
+    }
+
+    /// Emits `target = value`. A value computed before the last assignment
+    /// belongs to the same multiple assignment, which assigns right to left.
+    /// A function assigned on its own is declared with `function name()`.
+    fn emit_assignment(&mut self, target: Expression, value: Expression) {
+        let computed = self.oldest_read.unwrap_or(self.emitted);
+        if computed + 1 == self.emitted {
+            if let Some(Statement::Assign { targets, values }) = self.statements.last_mut() {
+                if targets.len() == values.len() {
+                    targets.insert(0, target);
+                    values.insert(0, value);
+                    return;
+                }
+            }
+        }
+        if let (Some(mut name), Expression::Function(body)) = (function_name(&target), &value) {
+            let mut body = body.as_ref().clone();
+            if !name.fields.is_empty()
+                && body.parameters.first().map(String::as_str) == Some("self")
+            {
+                body.parameters.remove(0);
+                name.method = name.fields.pop();
+            }
+            self.emit(Statement::Function { name, body });
+            return;
+        }
+        self.emit(Statement::Assign {
+            targets: vec![target],
+            values: vec![value],
+        });
+    }
+
+    /// `R[table][key] := value`, which adds a field to a table being constructed
+    fn set_field(
+        &mut self,
+        table: u8,
+        key: Expression,
+        value: Operand,
+        pc: usize,
+    ) -> Result<(), DecompileError> {
+        if let Some(Pending {
+            value: Value::Expression(Expression::Table(_)),
+            ..
+        }) = self.registers.get(&table)
+        {
+            let value = self.operand(value, pc)?;
+            // Items loaded before the field come before it in the constructor
+            let items = self.list_items(table, false);
+            if let Some(Pending {
+                value: Value::Expression(Expression::Table(fields)),
+                ..
+            }) = self.registers.get_mut(&table)
+            {
+                fields.extend(items.into_iter().map(TableField::Positional));
+                fields.push(TableField::Keyed(key, value));
+            }
+            return Ok(());
+        }
+        let table = self.read(table, pc);
+        self.assign(Expression::index(table, key), value, pc)
+    }
+
+    /// Takes the items of the table constructor in `table` loaded in the
+    /// registers after it, leaving them marked for `SetList`
+    fn list_items(&mut self, table: u8, fixed_end: bool) -> Vec<Expression> {
+        let mut items = vec![];
+        let mut register = table + 1;
+        while let Some(pending) = self.registers.get_mut(&register) {
+            match std::mem::replace(&mut pending.value, Value::ListItem) {
+                Value::Expression(item) => items.push(item),
+                Value::ListItem => {}
+                value => {
+                    pending.value = value;
+                    break;
+                }
+            }
+            register += 1;
+        }
+        if fixed_end {
+            if let Some(last) = items.pop() {
+                items.push(if last.is_multiple_results() {
+                    Expression::Paren(Box::new(last))
+                } else {
+                    last
+                });
+            }
+        }
+        items
+    }
+
+    /// `SetList`, which stores the items loaded after the table in it
+    fn set_list(&mut self, table: u8, count: u8, pc: usize) -> Result<(), DecompileError> {
+        let items = self.list_items(table, count != 0);
+        let end = if count == 0 {
+            self.top_end(table + 1)
+        } else {
+            table + count + 1
+        };
+        for register in table + 1..end {
+            self.registers.remove(&register);
+        }
+        self.top = None;
+        match self.registers.get_mut(&table) {
+            Some(Pending {
+                value: Value::Expression(Expression::Table(fields)),
+                ..
+            }) => {
+                fields.extend(items.into_iter().map(TableField::Positional));
+                Ok(())
+            }
+            _ => Err(self.invalid(pc)),
+        }
+    }
+
+    fn unary(&mut self, a: u8, b: u8, operator: UnaryOperator, pc: usize) {
+        let operand = self.read(b, pc);
+        self.write(a, Expression::unary(operator, operand), pc)
+    }
+
+    fn arithmetic(&mut self, a: u8, b: u8, operator: BinaryOperator, c: u8, pc: usize) {
+        let left = self.read(b, pc);
+        let right = self.read(c, pc);
+        self.write(a, Expression::binary(operator, left, right), pc)
+    }
+
+    /// Arithmetic with an immediate operand. The `MmBinI` after it has the
+    /// operator and immediate of the source, which the compiler may have changed,
+    /// like `x - 1` compiled to `AddI` with -1.
+    #[allow(clippy::too_many_arguments)]
+    fn arithmetic_immediate(
+        &mut self,
+        a: u8,
+        b: u8,
+        operator: BinaryOperator,
+        immediate: i8,
+        flipped: bool,
+        next: Option<Instruction>,
+        pc: usize,
+    ) {
+        let (operator, immediate, flipped) = match next {
+            Some(Instruction::MmBinI(_, immediate, event, k)) => (
+                metamethod_operator(event).unwrap_or(operator),
+                immediate,
+                k == 1,
+            ),
+            _ => (operator, immediate, flipped),
+        };
+        let register = self.read(b, pc);
+        let immediate = Expression::Integer(immediate as i64);
+        let value = if flipped {
+            Expression::binary(operator, immediate, register)
+        } else {
+            Expression::binary(operator, register, immediate)
+        };
+        self.write(a, value, pc)
+    }
+
+    /// Arithmetic with a constant operand, flipped when the `MmBinK` after it says so
+    fn arithmetic_constant(
+        &mut self,
+        a: u8,
+        b: u8,
+        operator: BinaryOperator,
+        c: u8,
+        next: Option<Instruction>,
+        pc: usize,
+    ) -> Result<(), DecompileError> {
+        let (operator, flipped) = match next {
+            Some(Instruction::MmBinK(_, _, event, k)) => {
+                (metamethod_operator(event).unwrap_or(operator), k == 1)
+            }
+            _ => (operator, false),
+        };
+        let register = self.read(b, pc);
+        let constant = self.constant(c as u32, pc)?;
+        let value = if flipped {
+            Expression::binary(operator, constant, register)
+        } else {
+            Expression::binary(operator, register, constant)
+        };
+        self.write(a, value, pc);
+        Ok(())
+    }
+
+    /// The call of `R[a]` with `b - 1` arguments, or up to the top when `b` is 0
+    fn function_call(&mut self, a: u8, b: u8, pc: usize) -> FunctionCall {
+        let end = if b == 0 { self.top_end(a + 1) } else { a + b };
+        match self.take(a) {
+            Some(Value::Method(object, method)) => {
+                self.take(a + 1);
+                let arguments = self.read_list(a + 2, end.max(a + 2), b != 0, pc);
+                FunctionCall::method(object, &method, arguments)
+            }
+            value => {
+                let function = match value {
+                    Some(Value::Expression(expression)) => expression,
+                    _ => Expression::Name(self.register_name(a, pc)),
+                };
+                let arguments = self.read_list(a + 1, end, b != 0, pc);
+                FunctionCall::new(function, arguments)
+            }
+        }
+    }
+
+    fn return_values(&mut self, values: Vec<Expression>, pc: usize) {
+        // Every function ends with an implicit return
+        if values.is_empty() && pc + 1 == self.instructions.len() {
+            return;
+        }
+        self.emit(Statement::Return(values));
+    }
+
+    /// Declares the locals starting at `pcs`, with the values in their registers.
+    /// The state of `for` loops is declared by the loops themselves.
+    fn declare_locals(&mut self, pcs: std::ops::RangeInclusive<usize>) {
+        let mut locals: Vec<(u8, usize)> = self
+            .locals
+            .iter()
+            .enumerate()
+            .filter(|(_, local)| {
+                pcs.contains(&local.start_pc)
+                    && !self.is_loop_state(local.start_pc)
+                    && self.value_end != Some(local.start_pc)
+            })
+            .map(|(index, local)| (local.register, index))
+            .collect();
+        if locals.is_empty() {
+            return;
+        }
+        locals.sort();
+        if let [(register, index)] = locals[..] {
+            if let Some(body) = self.local_function(register, index) {
+                let name = self.local_name(index);
+                self.emit(Statement::LocalFunction { name, body });
+                return;
+            }
+        }
+        let mut names = vec![];
+        let mut values = vec![];
+        let mut extra_results = false;
+        for (register, index) in locals {
+            let start_pc = self.locals[index].start_pc;
+            let to_be_closed = matches!(
+                self.instructions.get(start_pc),
+                Some(Some(Instruction::Tbc(tbc))) if *tbc == register
+            );
+            names.push(LocalName {
+                name: self.local_name(index),
+                attribute: to_be_closed.then_some(LocalAttribute::Close),
+            });
+            match self
+                .registers
+                .remove(&register)
+                .map(|pending| pending.value)
+            {
+                Some(Value::Expression(expression)) => values.push(expression),
+                Some(Value::Method(object, method)) => values.push(Expression::index(
+                    object,
+                    Expression::String(method.into_bytes()),
+                )),
+                Some(Value::ExtraResult) => extra_results = true,
+                Some(Value::SelfArgument | Value::ListItem) | None => values.push(Expression::Nil),
+            }
+        }
+        while values.last() == Some(&Expression::Nil) {
+            values.pop();
+        }
+        if !extra_results && values.len() < names.len() {
+            if let Some(last) = values.pop() {
+                values.push(if last.is_multiple_results() {
+                    Expression::Paren(Box::new(last))
+                } else {
+                    last
+                });
+            }
+        }
+        self.emit(Statement::Local { names, values });
+    }
+
+    /// The function of a local declared right after its closure, written as
+    /// `local function` unless that would capture a global of the same name
+    fn local_function(&mut self, register: u8, index: usize) -> Option<FunctionBody> {
+        let start_pc = self.locals[index].start_pc;
+        let Some(Instruction::Closure(a, bx)) = self.instructions[start_pc.checked_sub(1)?] else {
+            return None;
+        };
+        let proto = self.function.protos.get(bx as usize)?;
+        let captures_itself = proto
+            .upvalues
+            .iter()
+            .any(|upvalue| upvalue.in_stack && upvalue.index == register);
+        if a != register || !captures_itself && mentions(proto, &self.local_name(index)) {
+            return None;
+        }
+        if !matches!(
+            self.registers.get(&register)?.value,
+            Value::Expression(Expression::Function(_))
+        ) {
+            return None;
+        }
+        match self.registers.remove(&register)?.value {
+            Value::Expression(Expression::Function(body)) => Some(*body),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        binary_chunks::function_block::StripMode,
+        decompiler::{printer::Printer, Decompiler},
+        lua_file::LuaFile,
+    };
+
+    #[test]
+    fn test_straight_line_expressions() {
+        let file = LuaFile::parse(include_bytes!("../../tests/expressions.luac"))
+            .unwrap()
+            .1;
+        let block = Decompiler::new(&file).decompile().unwrap();
+        assert_eq!(
+            block.to_string(),
+            indoc!(
+                r##"
+                local x = 10
+                print(("a"):rep(3) .. x)
+                local s = string.format("%d-%d", x * 2, -x)
+                local t = {}
+                t.name = s
+                t[1] = x // 3
+                local a, b = x, s
+                a, b = b, a
+                print(select("#", ...))
+                local biggest = math.max(x, 1, 2.5)
+                local y = x - 1
+                local z = 1 + x
+                local w = 2 ^ x << 1
+                obj = {}
+                obj.count = #t .. "items"
+                g1, g2 = math.huge, 0
+                local m, n = string.find(s, "-")
+                print(m, n, ...)
+                return x, s
+                "##
+            )
+            .trim_start()
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let file = LuaFile::parse(include_bytes!("../../tests/control_flow.luac"))
+            .unwrap()
+            .1;
+        let block = Decompiler::new(&file).decompile().unwrap();
+        assert_eq!(
+            block.to_string(),
+            indoc!(
+                r#"
+                local n = tonumber(...)
+                local total = 0
+                for i = 1, n do
+                    if i % 2 == 0 then
+                        total = total + i
+                    elseif i % 3 == 0 then
+                        total = total - 1
+                    else
+                        print(i)
+                    end
+                end
+                for i = n, 1, -1 do
+                    print(i)
+                end
+                local t = {}
+                for k, v in pairs(t) do
+                    print(k, v)
+                    if v == nil then
+                        break
+                    end
+                end
+                local j = 0
+                while j < n and total > 0 do
+                    j = j + 1
+                    if j > 5 then
+                        break
+                    end
+                end
+                repeat
+                    local done = check(j, n)
+                    j = j + 1
+                until done or j > 100
+                while not f(j) do
+                    j = j - 1
+                end
+                if n > 1 then
+                    print("big")
+                end
+                if n == 1 or n == 2 then
+                    print("small")
+                else
+                    print("other")
+                end
+                do
+                    local n = 5
+                    print(n)
+                end
+                print(n)
+                "#
+            )
+            .trim_start()
+        );
+    }
+
+    #[test]
+    fn test_short_circuit_values() {
+        let file = LuaFile::parse(include_bytes!("../../tests/values.luac"))
+            .unwrap()
+            .1;
+        let block = Decompiler::new(&file).decompile().unwrap();
+        assert_eq!(block.to_string(), include_str!("../../tests/values.lua"));
+    }
+
+    #[test]
+    fn test_table_constructors() {
+        let file = LuaFile::parse(include_bytes!("../../tests/tables.luac"))
+            .unwrap()
+            .1;
+        let block = Decompiler::new(&file).decompile().unwrap();
+        assert_eq!(block.to_string(), include_str!("../../tests/tables.lua"));
+    }
+
+    #[test]
+    fn test_functions() {
+        let file = LuaFile::parse(include_bytes!("../../tests/functions.luac"))
+            .unwrap()
+            .1;
+        let block = Decompiler::new(&file).decompile().unwrap();
+        assert_eq!(block.to_string(), include_str!("../../tests/functions.lua"));
+    }
+
+    #[test]
+    fn test_goto() {
+        let file = LuaFile::parse(include_bytes!("../../tests/goto.luac"))
+            .unwrap()
+            .1;
+        let block = Decompiler::new(&file).decompile().unwrap();
+        assert_eq!(
+            block.to_string(),
+            indoc!(
+                r#"
+                local n = 0
+                ::label_2::
+                local step = 1
+                n = n + step
+                if n < 3 then
+                    goto label_2
+                end
+                for i = 1, 3 do
+                    if i ~= 2 then
+                        print(i)
+                    end
+                end
+                for i = 1, 3 do
+                    for j = 1, 3 do
+                        if i * j == 4 then
+                            goto label_35
+                        end
+                    end
+                end
+                ::label_35::
+                for i = 1, 3 do
+                    for j = 1, 3 do
+                        if i < j then
+                            goto label_50
+                        end
+                    end
+                    print(i)
+                    ::label_50::
+                end
+                do
+                    local k = 0
+                    ::label_52::
+                    local v = k
+                    callbacks[k] = function()
+                        return v
+                    end
+                    k = k + 1
+                    if k < 2 then
+                        goto label_52
+                    end
+                end
+                print(n)
+                "#
+            )
+            .trim_start()
+        );
+    }
+
+    #[test]
+    fn test_stripped() {
+        let file = LuaFile::parse(include_bytes!("../../tests/stripped.luac"))
+            .unwrap()
+            .1;
+        let block = Decompiler::new(&file).decompile().unwrap();
+        assert_eq!(block.to_string(), include_str!("../../tests/stripped.lua"));
+    }
+
+    /// Decompiles `source` compiled and stripped with `mode`
+    fn decompile_stripped(source: &str, mode: StripMode) -> String {
+        let chunk = mlua::Lua::new()
+            .load(source)
+            .into_function()
+            .unwrap()
+            .dump(false);
+        let mut file = LuaFile::parse(&chunk).unwrap().1;
+        file.strip_with(mode);
+        Decompiler::new(&file).decompile().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_stripped_loop_variables() {
+        let source = indoc! {"
+            for a, b, c in next, {} do print(a, b, c) end
+            for a = 1, 2 do
+                for b = 1, 2 do
+                    for c = 1, 2 do
+                        for d = 1, 2 do print(a, b, c, d) end
+                    end
+                end
+            end
+        "};
+        assert_eq!(
+            decompile_stripped(source, StripMode::All),
+            indoc! {"
+                for l_0_4, l_0_5, v in next, {} do
+                    print(l_0_4, l_0_5, v)
+                end
+                for i = 1, 2 do
+                    for j = 1, 2 do
+                        for k = 1, 2 do
+                            for l_0_15 = 1, 2 do
+                                print(i, j, k, l_0_15)
+                            end
+                        end
+                    end
+                end
+            "}
+        );
+    }
+
+    #[test]
+    fn test_stripped_names() {
+        let source = indoc! {"
+            local v = f()
+            print(v)
+            print(v)
+            local obj = {}
+            function obj:m(a) return self, a end
+            obj:m(1)
+        "};
+        // Keeping the lines does not keep the locals from being inferred
+        let decompiled = decompile_stripped(source, StripMode::Names);
+        assert_eq!(
+            decompiled,
+            indoc! {"
+                local l_0_0 = f()
+                print(l_0_0)
+                print(l_0_0)
+                local l_0_1 = {
+                    m = function(arg1, arg2)
+                        return arg1, arg2
+                    end,
+                }
+                l_0_1:m(1)
+            "}
+        );
+        assert_eq!(decompiled, decompile_stripped(source, StripMode::All));
+    }
+
+    #[test]
+    fn test_original_lines() {
+        let file = LuaFile::parse(include_bytes!("../../tests/closures.luac"))
+            .unwrap()
+            .1;
+        let block = Decompiler::new(&file).decompile().unwrap();
+        let source = include_str!("../../tests/closures.lua");
+        let output = Printer::with_indentation("\t")
+            .preserving_lines()
+            .print_block(&block);
+        // Only the comments of the source are lost
+        for (line, (output_line, source_line)) in output.lines().zip(source.lines()).enumerate() {
+            if !source_line.starts_with("--") {
+                assert_eq!(output_line, source_line, "line {}", line + 1);
+            }
+        }
+        assert_eq!(output.lines().count(), source.lines().count());
+    }
+}
@@ -0,0 +1,856 @@
+use std::collections::HashMap;
+
+use crate::instruction_parsing::instruction::Instruction;
+
+use super::{Lifter, Pending, Value};
+use crate::decompiler::{
+    ast::{BinaryOperator, Block, Expression, Statement, UnaryOperator},
+    DecompileError,
+};
+
+/// A test with the `Jmp` after it, which jumps when `condition` holds
+#[derive(Debug, Clone)]
+pub(super) struct Branch {
+    /// First instruction computing the operands of the test
+    pub(super) start: usize,
+    /// Pc of the `Jmp`
+    pub(super) jump: usize,
+    pub(super) target: usize,
+    pub(super) condition: Expression,
+}
+
+impl Branch {
+    /// Pc reached when the jump is not taken
+    pub(super) fn fallthrough(&self) -> usize {
+        self.jump + 1
+    }
+}
+
+/// The lifter state to go back to when a speculative lift is abandoned
+#[derive(Clone)]
+pub(super) struct Snapshot {
+    registers: HashMap<u8, Pending>,
+    top: Option<u8>,
+    extra_targets: Vec<Expression>,
+    statements: usize,
+    statement_pc: Option<usize>,
+    emitted: usize,
+}
+
+/// Negates a condition, whose value only matters for its truthiness
+pub(super) fn negate(condition: Expression) -> Expression {
+    match condition {
+        Expression::Unary(UnaryOperator::Not, operand) => *operand,
+        Expression::Binary(BinaryOperator::Eq, left, right) => {
+            Expression::Binary(BinaryOperator::Ne, left, right)
+        }
+        Expression::Binary(BinaryOperator::Ne, left, right) => {
+            Expression::Binary(BinaryOperator::Eq, left, right)
+        }
+        condition => Expression::unary(UnaryOperator::Not, condition),
+    }
+}
+
+/// `left operator right` for `and` and `or`, kept left associative
+fn combine(operator: BinaryOperator, left: Expression, right: Expression) -> Expression {
+    match right {
+        Expression::Binary(inner, middle, right) if inner == operator => {
+            Expression::Binary(operator, Box::new(combine(operator, left, *middle)), right)
+        }
+        right => Expression::binary(operator, left, right),
+    }
+}
+
+/// The condition under which a chain of branches exits to `on_true` rather
+/// than `on_false`. A jump to the start of a later branch groups the branches
+/// before it, as in `(a or b) and c`.
+fn chain_condition(branches: &[Branch], on_true: usize, on_false: usize) -> Option<Expression> {
+    chain_value(branches, None, on_true, on_false)
+}
+
+/// The value of a chain of branches ending with the code of `tail`, which
+/// starts at the given pc. Jumping to `on_true` gives the condition of the
+/// branch, and jumping to `on_false` its negation.
+pub(super) fn chain_value(
+    branches: &[Branch],
+    tail: Option<&(usize, Expression)>,
+    on_true: usize,
+    on_false: usize,
+) -> Option<Expression> {
+    let Some((first, rest)) = branches.split_first() else {
+        return tail.map(|(_, value)| value.clone());
+    };
+    if rest.is_empty() && tail.is_none() {
+        return if first.target == on_true && first.fallthrough() == on_false {
+            Some(first.condition.clone())
+        } else if first.target == on_false && first.fallthrough() == on_true {
+            Some(negate(first.condition.clone()))
+        } else {
+            None
+        };
+    }
+    let mut starts = rest
+        .iter()
+        .map(|branch| branch.start)
+        .chain(tail.map(|(start, _)| *start));
+    if let Some(position) = starts.position(|start| start == first.target) {
+        let (group, rest) = branches.split_at(position + 1);
+        let middle = rest
+            .first()
+            .map(|branch| branch.start)
+            .or(tail.map(|(start, _)| *start))?;
+        let exit = group.iter().map(|branch| branch.target).find(|target| {
+            *target != middle && !group[1..].iter().any(|branch| branch.start == *target)
+        })?;
+        let right = chain_value(rest, tail, on_true, on_false)?;
+        if exit == on_true {
+            let left = chain_value(group, None, on_true, middle)?;
+            Some(combine(BinaryOperator::Or, left, right))
+        } else if exit == on_false {
+            let left = chain_value(group, None, middle, on_false)?;
+            Some(combine(BinaryOperator::And, left, right))
+        } else {
+            None
+        }
+    } else if first.target == on_true {
+        let right = chain_value(rest, tail, on_true, on_false)?;
+        Some(combine(BinaryOperator::Or, first.condition.clone(), right))
+    } else if first.target == on_false {
+        let right = chain_value(rest, tail, on_true, on_false)?;
+        Some(combine(
+            BinaryOperator::And,
+            negate(first.condition.clone()),
+            right,
+        ))
+    } else {
+        None
+    }
+}
+
+impl<'a> Lifter<'a> {
+    /// Pcs of the `Jmp` instructions by their targets
+    pub(super) fn jump_sources(instructions: &[Option<Instruction>]) -> HashMap<usize, Vec<usize>> {
+        let mut sources: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (pc, instruction) in instructions.iter().enumerate() {
+            if let Some(instruction @ Instruction::Jmp(_)) = instruction {
+                if let Some(target) = instruction.jump_target(pc) {
+                    sources.entry(target).or_default().push(pc);
+                }
+            }
+        }
+        sources
+    }
+
+    pub(super) fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers.clone(),
+            top: self.top,
+            extra_targets: self.extra_targets.clone(),
+            statements: self.statements.len(),
+            statement_pc: self.statement_pc,
+            emitted: self.emitted,
+        }
+    }
+
+    pub(super) fn restore(&mut self, snapshot: Snapshot) {
+        self.registers = snapshot.registers;
+        self.top = snapshot.top;
+        self.extra_targets = snapshot.extra_targets;
+        self.statements.truncate(snapshot.statements);
+        self.positions.truncate(snapshot.statements);
+        self.statement_pc = snapshot.statement_pc;
+        self.emitted = snapshot.emitted;
+    }
+
+    /// Lifts the instructions `start..end` as a block of their own
+    pub(super) fn block(&mut self, start: usize, end: usize) -> Result<Block, DecompileError> {
+        self.block_after(Block::default(), start, end)
+    }
+
+    /// Lifts the instructions `start..end` into a block beginning with `block`.
+    /// The statement containing the block starts before it.
+    fn block_after(
+        &mut self,
+        block: Block,
+        start: usize,
+        end: usize,
+    ) -> Result<Block, DecompileError> {
+        let outer = std::mem::replace(&mut self.statements, block.statements);
+        let outer_positions = std::mem::replace(&mut self.positions, block.positions);
+        let statement_pc = self.statement_pc.take();
+        let result = self.structure(start, end);
+        self.statement_pc = statement_pc;
+        let statements = std::mem::replace(&mut self.statements, outer);
+        let positions = std::mem::replace(&mut self.positions, outer_positions);
+        result.map(|()| Block::with_positions(statements, positions))
+    }
+
+    /// Lifts the instructions `start..end` into structured statements
+    pub(super) fn structure(&mut self, start: usize, end: usize) -> Result<(), DecompileError> {
+        let mut pc = start;
+        while pc < end {
+            pc = self.structure_statement(pc, end)?;
+        }
+        Ok(())
+    }
+
+    /// Lifts the statement starting at `pc`, returning the pc after it
+    fn structure_statement(&mut self, pc: usize, end: usize) -> Result<usize, DecompileError> {
+        if self.labels.contains_key(&pc) && !self.placed_labels.contains(&pc) {
+            self.place_label(pc);
+        }
+        // Instructions preparing the function or ending the previous
+        // statement do not start one
+        if !matches!(
+            self.instructions[pc],
+            Some(
+                Instruction::VarargPrep(_)
+                    | Instruction::Tbc(_)
+                    | Instruction::Close(_)
+                    | Instruction::MmBin(..)
+                    | Instruction::MmBinI(..)
+                    | Instruction::MmBinK(..)
+                    | Instruction::Extraarg(_)
+            )
+        ) {
+            self.statement_pc.get_or_insert(pc);
+        }
+        if let Some(back) = self.back_jump(pc, end) {
+            if self.is_backward_goto(pc, back) {
+                self.place_label(pc);
+            } else {
+                return self.structure_loop(pc, back);
+            }
+        }
+        match self.instruction(pc)? {
+            Instruction::ForPrep(a, bx) => self.numeric_for(pc, a, bx),
+            Instruction::TForPrep(a, bx) => self.generic_for(pc, a, bx),
+            Instruction::Jmp(_) => Ok(self.jump(pc)),
+            instruction if instruction.is_test() && self.is_jump_after(pc) => {
+                if let Some(next) = self.value_expression(pc, end)? {
+                    Ok(next)
+                } else if self.is_branch(instruction, pc) {
+                    self.conditional(pc, end)
+                } else {
+                    self.simple_statement(pc, end)
+                }
+            }
+            _ => self.simple_statement(pc, end),
+        }
+    }
+
+    /// Whether `instruction` at `pc` is a test followed by its `Jmp`
+    fn is_branch(&self, instruction: Instruction, pc: usize) -> bool {
+        instruction.is_test()
+            && !matches!(instruction, Instruction::TestSet(..))
+            && self.is_jump_after(pc)
+    }
+
+    fn is_jump_after(&self, pc: usize) -> bool {
+        matches!(
+            self.instructions.get(pc + 1),
+            Some(Some(Instruction::Jmp(_)))
+        )
+    }
+
+    fn is_conditional_jump(&self, pc: usize) -> bool {
+        pc > 0
+            && matches!(self.instructions.get(pc - 1), Some(Some(instruction)) if instruction.is_test())
+    }
+
+    /// The last pc before `end` jumping back to `header`, which makes it a loop.
+    /// The jump of the `repeat` loop being lifted is its `until` condition.
+    fn back_jump(&self, header: usize, end: usize) -> Option<usize> {
+        self.jump_sources
+            .get(&header)?
+            .iter()
+            .copied()
+            .filter(|source| *source >= header && *source < end && Some(*source) != self.until)
+            .max()
+    }
+
+    pub(super) fn is_loop_header(&self, pc: usize) -> bool {
+        self.jump_sources
+            .get(&pc)
+            .is_some_and(|sources| sources.iter().any(|source| *source >= pc))
+    }
+
+    /// Follows unconditional jumps from `pc` to the instruction reached in the end
+    fn resolve(&self, mut pc: usize) -> usize {
+        for _ in 0..self.instructions.len() {
+            match self.instructions.get(pc) {
+                Some(Some(instruction @ Instruction::Jmp(_))) if !self.is_conditional_jump(pc) => {
+                    match instruction.jump_target(pc) {
+                        Some(target) if target != pc => pc = target,
+                        _ => break,
+                    }
+                }
+                _ => break,
+            }
+        }
+        pc
+    }
+
+    /// Whether jumping to `first` and `second` ends up at the same place
+    fn equivalent(&self, first: usize, second: usize) -> bool {
+        first == second || self.resolve(first) == self.resolve(second)
+    }
+
+    fn is_loop_exit(&self, target: usize) -> bool {
+        self.loop_exits
+            .last()
+            .is_some_and(|exit| self.equivalent(target, *exit))
+    }
+
+    fn label(&mut self, target: usize) -> String {
+        self.labels
+            .entry(target)
+            .or_insert_with(|| format!("label_{}", target))
+            .clone()
+    }
+
+    /// `::label::` of the jumps to `pc`
+    fn place_label(&mut self, pc: usize) {
+        let label = Statement::Label(self.label(pc));
+        self.placed_labels.insert(pc);
+        self.statement_pc = Some(pc);
+        self.emit(label);
+    }
+
+    /// The label of the jumps to `pc` that was not placed yet
+    fn unplaced_label(&mut self, pc: usize) -> Option<String> {
+        let label = self.labels.get(&pc)?.clone();
+        self.placed_labels.insert(pc).then_some(label)
+    }
+
+    /// Whether the jump back to `header` at `back` is a `goto` rather than the
+    /// end of a loop. The locals declared after its label stay in scope after
+    /// it, and are closed by it even when none of them is captured.
+    fn is_backward_goto(&self, header: usize, back: usize) -> bool {
+        if self.is_conditional_jump(back) {
+            return false;
+        }
+        if !self.inferred {
+            return self
+                .locals
+                .iter()
+                .any(|local| (header + 1..back).contains(&local.start_pc) && local.end_pc > back);
+        }
+        let Some(Some(Instruction::Close(level))) = back
+            .checked_sub(1)
+            .filter(|close| *close >= header)
+            .map(|close| self.instructions[close])
+        else {
+            return false;
+        };
+        // The block of a loop only closes its locals when it needs to
+        !(header..back).any(|pc| match self.instructions[pc] {
+            Some(Instruction::Closure(_, bx)) => {
+                self.function.protos.get(bx as usize).is_some_and(|proto| {
+                    proto
+                        .upvalues
+                        .iter()
+                        .any(|upvalue| upvalue.in_stack && upvalue.index >= level)
+                })
+            }
+            Some(Instruction::Tbc(register)) => register >= level,
+            _ => false,
+        })
+    }
+
+    /// Lifts the body of a loop continuing at `continuation`. A `goto` there
+    /// from a nested loop jumps to a label ending the body.
+    fn loop_body(&mut self, start: usize, continuation: usize) -> Result<Block, DecompileError> {
+        let mut body = self.block(start, continuation)?;
+        if let Some(label) = self.unplaced_label(continuation) {
+            body.positions.push(self.position(continuation));
+            body.statements.push(Statement::Label(label));
+        }
+        Ok(body)
+    }
+
+    /// Lifts an instruction that does not affect control flow. Locals ending
+    /// before the block does were declared in a `do` block.
+    fn simple_statement(&mut self, pc: usize, end: usize) -> Result<usize, DecompileError> {
+        let statements = self.statements.len();
+        let next = self.lift_instruction(pc)?;
+        // Inferred locals only live as long as their values, unless closed
+        let scope_end = self
+            .locals
+            .iter()
+            .filter(|local| {
+                (!self.inferred
+                    || matches!(
+                        self.instructions.get(local.end_pc),
+                        Some(Some(Instruction::Close(_)))
+                    ))
+                    && (pc + 1..=next).contains(&local.start_pc)
+                    && !self.is_loop_state(local.start_pc)
+            })
+            .map(|local| local.end_pc)
+            .max();
+        match scope_end {
+            Some(scope_end)
+                if scope_end > next
+                    && scope_end < end
+                    && self.statements.len() > statements
+                    && matches!(self.statements.last(), Some(Statement::Local { .. })) =>
+            {
+                let local = self.statements.pop().into_iter().collect();
+                let position = self.positions.pop();
+                let block = self.block_after(
+                    Block::with_positions(local, position.into_iter().collect()),
+                    next,
+                    scope_end,
+                )?;
+                self.statement_pc = position.map(|position| position.pc);
+                self.emit(Statement::Do(block));
+                Ok(scope_end)
+            }
+            _ => Ok(next),
+        }
+    }
+
+    /// Whether locals starting at `pc` are the hidden state of a `for` loop
+    pub(super) fn is_loop_state(&self, pc: usize) -> bool {
+        matches!(
+            self.instructions.get(pc),
+            Some(Some(Instruction::ForPrep(..) | Instruction::TForPrep(..)))
+        )
+    }
+
+    /// A `Jmp` that is not part of a test, which is a `break` or a `goto`
+    fn jump(&mut self, pc: usize) -> usize {
+        let target = self.instructions[pc]
+            .and_then(|instruction| instruction.jump_target(pc))
+            .unwrap_or(pc + 1);
+        if self.is_loop_exit(target) {
+            self.emit(Statement::Break);
+        } else if target != pc + 1 {
+            let label = self.label(target);
+            self.emit(Statement::Goto(label));
+        }
+        pc + 1
+    }
+
+    /// The jump condition of the test at `pc`, reading its operands
+    pub(super) fn test_condition(&mut self, pc: usize) -> Result<Expression, DecompileError> {
+        let (condition, k) = match self.instruction(pc)? {
+            Instruction::Eq(a, b, k) => (self.compare(BinaryOperator::Eq, a, b, pc), k),
+            Instruction::Lt(a, b, k) => (self.compare(BinaryOperator::Lt, a, b, pc), k),
+            Instruction::Le(a, b, k) => (self.compare(BinaryOperator::Le, a, b, pc), k),
+            Instruction::EqK(a, b, k) => {
+                let left = self.read(a, pc);
+                let constant = self.constant(b as u32, pc)?;
+                (Expression::binary(BinaryOperator::Eq, left, constant), k)
+            }
+            Instruction::EqI(a, sb, k) => {
+                (self.compare_immediate(BinaryOperator::Eq, a, sb, pc), k)
+            }
+            Instruction::LtI(a, sb, k) => {
+                (self.compare_immediate(BinaryOperator::Lt, a, sb, pc), k)
+            }
+            Instruction::LeI(a, sb, k) => {
+                (self.compare_immediate(BinaryOperator::Le, a, sb, pc), k)
+            }
+            Instruction::GtI(a, sb, k) => {
+                (self.compare_immediate(BinaryOperator::Gt, a, sb, pc), k)
+            }
+            Instruction::GeI(a, sb, k) => {
+                (self.compare_immediate(BinaryOperator::Ge, a, sb, pc), k)
+            }
+            Instruction::Test(a, k) => (self.read(a, pc), k),
+            _ => return Err(self.invalid(pc)),
+        };
+        Ok(if k == 1 { condition } else { negate(condition) })
+    }
+
+    fn compare(&mut self, operator: BinaryOperator, a: u8, b: u8, pc: usize) -> Expression {
+        let left = self.read(a, pc);
+        let right = self.read(b, pc);
+        // The compiler turns `x > y` into `y < x` after evaluating `x` and `y`
+        // in that order. Temporaries are taken in order, so two compared from
+        // the higher register were swapped, whereas locals compare the same
+        // way whichever operator is written.
+        let swapped =
+            a > b && self.active_local(a, pc).is_none() && self.active_local(b, pc).is_none();
+        match operator {
+            BinaryOperator::Lt if swapped => Expression::binary(BinaryOperator::Gt, right, left),
+            BinaryOperator::Le if swapped => Expression::binary(BinaryOperator::Ge, right, left),
+            _ => Expression::binary(operator, left, right),
+        }
+    }
+
+    fn compare_immediate(
+        &mut self,
+        operator: BinaryOperator,
+        a: u8,
+        sb: i8,
+        pc: usize,
+    ) -> Expression {
+        let left = self.read(a, pc);
+        Expression::binary(operator, left, Expression::Integer(sb as i64))
+    }
+
+    /// The test of a branch starting at `start`, after instructions that only
+    /// compute its operands. `TestSet` is only a branch of values.
+    pub(super) fn branch_test(&self, start: usize, end: usize, values: bool) -> Option<usize> {
+        for pc in start..end {
+            if pc > start && (self.jump_sources.contains_key(&pc) || self.is_loop_header(pc)) {
+                return None;
+            }
+            let instruction = self.instructions[pc]?;
+            if instruction.is_test() {
+                let is_branch =
+                    self.is_branch(instruction, pc) || (values && self.is_jump_after(pc));
+                return (pc + 1 < end && is_branch).then_some(pc);
+            }
+            if instruction.jump_target(pc).is_some()
+                || matches!(
+                    instruction,
+                    Instruction::TForCall(..)
+                        | Instruction::Return(..)
+                        | Instruction::Return0()
+                        | Instruction::Return1(..)
+                        | Instruction::TailCall(..)
+                        | Instruction::LFalseSkip(..)
+                        | Instruction::Close(..)
+                        | Instruction::Tbc(..)
+                )
+            {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Lifts the operands of a branch, which must not emit statements
+    pub(super) fn lift_operands(&mut self, start: usize, test: usize) -> bool {
+        let statements = self.emitted;
+        let mut pc = start;
+        while pc < test {
+            match self.lift_instruction(pc) {
+                Ok(next) => pc = next,
+                Err(_) => return false,
+            }
+        }
+        self.emitted == statements
+    }
+
+    /// Reads the longest chain of branches starting at `start` that exits to
+    /// two places, returning it with the place reached by falling through and
+    /// the other one
+    fn chain(
+        &mut self,
+        start: usize,
+        end: usize,
+    ) -> Result<Option<(Vec<Branch>, usize, usize)>, DecompileError> {
+        let initial = self.snapshot();
+        let mut branches: Vec<Branch> = vec![];
+        let mut snapshots = vec![];
+        let mut branch_start = start;
+        while let Some(test) = self.branch_test(branch_start, end, false) {
+            if branch_start != start && self.is_loop_header(branch_start) {
+                break;
+            }
+            let before = self.snapshot();
+            if !self.lift_operands(branch_start, test) {
+                self.restore(before);
+                break;
+            }
+            let condition = self.test_condition(test)?;
+            let jump = test + 1;
+            let target = self.instructions[jump]
+                .and_then(|instruction| instruction.jump_target(jump))
+                .ok_or_else(|| self.invalid(jump))?;
+            branches.push(Branch {
+                start: branch_start,
+                jump,
+                target,
+                condition,
+            });
+            snapshots.push(self.snapshot());
+            branch_start = jump + 1;
+        }
+        while !branches.is_empty() {
+            if let Some(exits) = self.chain_exits(&branches) {
+                let snapshot = snapshots.swap_remove(branches.len() - 1);
+                self.restore(snapshot);
+                return Ok(Some((branches, exits.0, exits.1)));
+            }
+            branches.pop();
+        }
+        self.restore(initial);
+        Ok(None)
+    }
+
+    /// The fallthrough and the other exit of a chain that is well formed
+    fn chain_exits(&self, branches: &[Branch]) -> Option<(usize, usize)> {
+        let fallthrough = branches.last()?.fallthrough();
+        let mut exit = None;
+        for (index, branch) in branches.iter().enumerate() {
+            if branch.target == fallthrough {
+                continue;
+            }
+            match branches
+                .iter()
+                .position(|other| other.start == branch.target)
+            {
+                Some(position) if position > index => continue,
+                Some(_) => return None,
+                None => match exit {
+                    None => exit = Some(branch.target),
+                    Some(exit) if exit == branch.target => {}
+                    Some(_) => return None,
+                },
+            }
+        }
+        // Branches inside the chain are only reached from it
+        let inside = |source: &usize| branches.iter().any(|branch| branch.jump == *source);
+        for branch in &branches[1..] {
+            if let Some(sources) = self.jump_sources.get(&branch.start) {
+                if !sources.iter().all(inside) {
+                    return None;
+                }
+            }
+        }
+        let exit = exit?;
+        chain_condition(branches, fallthrough, exit)?;
+        Some((fallthrough, exit))
+    }
+
+    /// A conditional statement starting with the test at `pc`
+    fn conditional(&mut self, pc: usize, end: usize) -> Result<usize, DecompileError> {
+        let Some((branches, fallthrough, target)) = self.chain(pc, end)? else {
+            // The test jumps to the next statement either way
+            let condition = self.test_condition(pc)?;
+            self.emit(Statement::If {
+                branches: vec![(negate(condition), Block::default())],
+                else_block: None,
+            });
+            return Ok(pc + 2);
+        };
+        let invalid = self.invalid(pc);
+        let condition = |on_true, on_false| {
+            chain_condition(&branches, on_true, on_false).ok_or_else(|| invalid.clone())
+        };
+        if self.until == Some(fallthrough - 1) {
+            self.until_condition = Some(condition(fallthrough, target)?);
+            return Ok(fallthrough);
+        }
+        let else_start = if fallthrough < target && target <= end {
+            Some(target)
+        } else if self.equivalent(target, end) {
+            Some(end)
+        } else {
+            None
+        };
+        if let Some(else_start) = else_start {
+            let condition = condition(fallthrough, target)?;
+            return self.if_statement(condition, fallthrough, else_start, end);
+        }
+        let condition = condition(target, fallthrough)?;
+        let statement = if self.is_loop_exit(target) {
+            Statement::Break
+        } else {
+            Statement::Goto(self.label(target))
+        };
+        self.emit(Statement::If {
+            branches: vec![(condition, Block::new(vec![statement]))],
+            else_block: None,
+        });
+        Ok(fallthrough)
+    }
+
+    /// `if condition then ... end` of the instructions `then_start..else_start`,
+    /// with an else block when a jump over it ends the then block
+    fn if_statement(
+        &mut self,
+        condition: Expression,
+        then_start: usize,
+        else_start: usize,
+        end: usize,
+    ) -> Result<usize, DecompileError> {
+        if then_start < else_start && else_start < end && !self.is_conditional_jump(else_start - 1)
+        {
+            let jump = else_start - 1;
+            let target = match self.instructions[jump] {
+                Some(instruction @ Instruction::Jmp(_)) => instruction.jump_target(jump),
+                _ => None,
+            };
+            let after = target
+                .filter(|target| !self.is_loop_exit(*target))
+                .and_then(|target| {
+                    if target > jump && target <= end {
+                        Some(target)
+                    } else if self.equivalent(target, end) {
+                        Some(end)
+                    } else {
+                        None
+                    }
+                });
+            if let Some(after) = after {
+                let then_block = self.block(then_start, jump)?;
+                let mut else_block = self.block(else_start, after)?;
+                // `if a then else b end` as `if not a then b end`
+                if then_block.statements.is_empty() {
+                    self.emit(Statement::If {
+                        branches: vec![(negate(condition), else_block)],
+                        else_block: None,
+                    });
+                    return Ok(after);
+                }
+                let mut branches = vec![(condition, then_block)];
+                let else_block = match else_block.statements.as_mut_slice() {
+                    [Statement::If {
+                        branches: elseif_branches,
+                        else_block,
+                    }] => {
+                        branches.append(elseif_branches);
+                        else_block.take()
+                    }
+                    _ => Some(else_block),
+                };
+                self.emit(Statement::If {
+                    branches,
+                    else_block,
+                });
+                return Ok(after);
+            }
+        }
+        let then_block = self.block(then_start, else_start)?;
+        self.emit(Statement::If {
+            branches: vec![(condition, then_block)],
+            else_block: None,
+        });
+        Ok(else_start)
+    }
+
+    /// A loop from `header` to the jump back to it at `back`. The other jumps
+    /// to `header` are gotos to a label before the loop.
+    fn structure_loop(&mut self, header: usize, back: usize) -> Result<usize, DecompileError> {
+        let exit = back + 1;
+        let index = self.statements.len();
+        self.loop_exits.push(exit);
+        let result = if self.is_conditional_jump(back) {
+            self.repeat_loop(header, back)
+        } else {
+            self.while_loop(header, back)
+        };
+        self.loop_exits.pop();
+        result?;
+        if let Some(label) = self.unplaced_label(header) {
+            self.positions.insert(index, self.position(header));
+            self.statements.insert(index, Statement::Label(label));
+            self.emitted += 1;
+        }
+        Ok(exit)
+    }
+
+    /// `repeat ... until condition`, whose condition jumps back at `back`
+    fn repeat_loop(&mut self, header: usize, back: usize) -> Result<(), DecompileError> {
+        let outer = self.until.replace(back);
+        let body = self.block(header, back + 1);
+        self.until = outer;
+        let body = body?;
+        let condition = self
+            .until_condition
+            .take()
+            .ok_or_else(|| self.invalid(back))?;
+        self.emit(Statement::Repeat { body, condition });
+        Ok(())
+    }
+
+    /// `while condition do ... end`, or `while true` when the loop does not
+    /// start with a test leaving it
+    fn while_loop(&mut self, header: usize, back: usize) -> Result<(), DecompileError> {
+        let snapshot = self.snapshot();
+        if let Some((branches, fallthrough, target)) = self.chain(header, back)? {
+            if self.equivalent(target, back + 1) {
+                if let Some(condition) = chain_condition(&branches, fallthrough, target) {
+                    let body = self.loop_body(fallthrough, back)?;
+                    self.emit(Statement::While { condition, body });
+                    return Ok(());
+                }
+            }
+        }
+        self.restore(snapshot);
+        let body = self.loop_body(header, back)?;
+        self.emit(Statement::While {
+            condition: Expression::Boolean(true),
+            body,
+        });
+        Ok(())
+    }
+
+    /// Name of the loop variable in `register` declared at `pc`, whose scope
+    /// is empty when the loop body is
+    fn loop_variable(&self, register: u8, pc: usize) -> String {
+        self.locals
+            .iter()
+            .position(|local| local.register == register && local.start_pc == pc)
+            .map_or_else(
+                || self.register_name(register, pc),
+                |index| self.local_name(index),
+            )
+    }
+
+    /// `for variable = start, limit, step do ... end` of the `ForPrep` at `pc`
+    fn numeric_for(&mut self, pc: usize, a: u8, bx: u32) -> Result<usize, DecompileError> {
+        let for_loop = pc + bx as usize + 1;
+        let exit = for_loop + 1;
+        let start = self.read(a, pc);
+        let limit = self.read(a + 1, pc);
+        let step = Some(self.read(a + 2, pc)).filter(|step| *step != Expression::Integer(1));
+        let variable = self.loop_variable(a + 3, pc + 1);
+        self.loop_exits.push(exit);
+        let body = self.loop_body(pc + 1, for_loop);
+        self.loop_exits.pop();
+        self.emit(Statement::NumericFor {
+            variable,
+            start,
+            limit,
+            step,
+            body: body?,
+        });
+        Ok(exit)
+    }
+
+    /// `for names in expressions do ... end` of the `TForPrep` at `pc`
+    fn generic_for(&mut self, pc: usize, a: u8, bx: u32) -> Result<usize, DecompileError> {
+        let call = pc + bx as usize + 1;
+        let Instruction::TForCall(_, count) = self.instruction(call)? else {
+            return Err(self.invalid(call));
+        };
+        // The `Close` after the loop ends the scope of its state
+        let exit = call + 2;
+        let mut expressions = vec![];
+        for register in a..a + 4 {
+            match self.registers.get(&register) {
+                Some(Pending {
+                    value: Value::ExtraResult,
+                    ..
+                }) => {
+                    self.registers.remove(&register);
+                }
+                Some(_) => expressions.push(self.read(register, pc)),
+                None => {}
+            }
+        }
+        while expressions.len() > 1 && expressions.last() == Some(&Expression::Nil) {
+            expressions.pop();
+        }
+        let names = (a + 4..a + 4 + count)
+            .map(|register| self.loop_variable(register, pc + 1))
+            .collect();
+        self.loop_exits.push(exit);
+        let body = self.loop_body(pc + 1, call);
+        self.loop_exits.pop();
+        self.emit(Statement::GenericFor {
+            names,
+            expressions,
+            body: body?,
+        });
+        Ok(exit)
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{
+    decompiler::{
+        ast::{Expression, Statement, UnaryOperator},
+        DecompileError,
+    },
+    instruction_parsing::instruction::Instruction,
+};
+
+use super::{
+    control_flow::{chain_value, Branch},
+    Lifter, Pending, Value,
+};
+
+/// Exits of a chain of values that has no `LFalseSkip` and `LoadTrue`
+const TRUE_EXIT: usize = usize::MAX - 1;
+const FALSE_EXIT: usize = usize::MAX;
+
+/// A branch in a chain of values
+struct Leaf {
+    branch: Branch,
+    /// Register and `k` of a `Test` or `TestSet`, whose jump keeps the operand
+    /// in the register when it is truthy for `k` 1 or falsy for `k` 0
+    operand: Option<(u8, u8)>,
+}
+
+impl<'a> Lifter<'a> {
+    /// Lifts `and`, `or` and comparisons used as values, starting with the
+    /// test at `pc`. Returns the pc after the value, or `None` when the test
+    /// belongs to a statement.
+    pub(super) fn value_expression(
+        &mut self,
+        pc: usize,
+        end: usize,
+    ) -> Result<Option<usize>, DecompileError> {
+        let initial = self.snapshot();
+        let mut leaves = vec![];
+        let mut snapshots = vec![];
+        let mut start = pc;
+        while let Some(test) = self.branch_test(start, end, true) {
+            if start != pc && self.is_loop_header(start) {
+                break;
+            }
+            let before = self.snapshot();
+            if !self.lift_operands(start, test) {
+                self.restore(before);
+                break;
+            }
+            let (condition, operand) = self.value_test(test)?;
+            let jump = test + 1;
+            let target = self.instructions[jump]
+                .and_then(|instruction| instruction.jump_target(jump))
+                .ok_or_else(|| self.invalid(jump))?;
+            leaves.push(Leaf {
+                branch: Branch {
+                    start,
+                    jump,
+                    target,
+                    condition,
+                },
+                operand,
+            });
+            snapshots.push(self.snapshot());
+            start = jump + 1;
+        }
+        while let Some(snapshot) = snapshots.pop() {
+            self.restore(snapshot);
+            if let Some(next) = self.value_region(&leaves, end)? {
+                return Ok(Some(next));
+            }
+            leaves.pop();
+        }
+        self.restore(initial);
+        Ok(None)
+    }
+
+    /// The jump condition of the test at `pc` with the operand it keeps
+    fn value_test(&mut self, pc: usize) -> Result<(Expression, Option<(u8, u8)>), DecompileError> {
+        let (register, operand, k) = match self.instruction(pc)? {
+            Instruction::TestSet(a, b, k) => (a, b, k),
+            Instruction::Test(a, k) => (a, a, k),
+            _ => return Ok((self.test_condition(pc)?, None)),
+        };
+        let value = self.read(operand, pc);
+        let condition = if k == 1 {
+            value
+        } else {
+            Expression::unary(UnaryOperator::Not, value)
+        };
+        Ok((condition, Some((register, k))))
+    }
+
+    /// `LFalseSkip` and `LoadTrue` of the same register at or around `pc`,
+    /// which turn jumps into booleans
+    fn boolean_pair(&self, pc: usize) -> Option<(usize, u8)> {
+        let instruction = |pc: usize| self.instructions.get(pc).copied().flatten();
+        [pc, pc.checked_sub(1)?].into_iter().find_map(|pc| {
+            match (instruction(pc), instruction(pc + 1)) {
+                (Some(Instruction::LFalseSkip(a)), Some(Instruction::LoadTrue(b))) if a == b => {
+                    Some((pc, a))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Whether `start..end` only computes values, jumping within itself
+    fn is_expression_code(&self, start: usize, end: usize) -> bool {
+        (start..end).all(|pc| match self.instructions[pc] {
+            Some(
+                Instruction::SetTabup(..)
+                | Instruction::SetTable(..)
+                | Instruction::SetI(..)
+                | Instruction::SetField(..)
+                | Instruction::SetUpval(..)
+                | Instruction::Call(_, _, 1)
+                | Instruction::TailCall(..)
+                | Instruction::Return(..)
+                | Instruction::Return0()
+                | Instruction::Return1(..)
+                | Instruction::Close(..)
+                | Instruction::Tbc(..)
+                | Instruction::TForCall(..),
+            )
+            | None => false,
+            Some(instruction) => instruction
+                .jump_target(pc)
+                .is_none_or(|target| (start..=end).contains(&target)),
+        })
+    }
+
+    /// Lifts the value computed by `leaves` and the code after them, if they
+    /// compute one
+    fn value_region(
+        &mut self,
+        leaves: &[Leaf],
+        end: usize,
+    ) -> Result<Option<usize>, DecompileError> {
+        let (Some(first), Some(last)) = (leaves.first(), leaves.last()) else {
+            return Ok(None);
+        };
+        let tail_start = last.branch.fallthrough();
+        let booleans = leaves
+            .iter()
+            .map(|leaf| leaf.branch.target)
+            .chain([tail_start])
+            .find_map(|pc| self.boolean_pair(pc))
+            .filter(|(false_pc, _)| tail_start <= *false_pc);
+        let (final_pc, tail_end, mut register) = match booleans {
+            Some((false_pc, register)) => {
+                // Code before the booleans jumps over them
+                let tail_end = if tail_start == false_pc {
+                    false_pc
+                } else {
+                    let jump = false_pc - 1;
+                    match self.instructions[jump] {
+                        Some(instruction @ Instruction::Jmp(_))
+                            if instruction.jump_target(jump) == Some(false_pc + 2) =>
+                        {
+                            jump
+                        }
+                        _ => return Ok(None),
+                    }
+                };
+                (false_pc + 2, tail_end, Some(register))
+            }
+            None => match leaves
+                .iter()
+                .map(|leaf| leaf.branch.target)
+                .filter(|target| *target > tail_start)
+                .max()
+            {
+                Some(final_pc) => (final_pc, final_pc, None),
+                None => return Ok(None),
+            },
+        };
+        if final_pc > end || tail_end < tail_start {
+            return Ok(None);
+        }
+        let (on_true, on_false) = booleans.map_or((TRUE_EXIT, FALSE_EXIT), |(false_pc, _)| {
+            (false_pc + 1, false_pc)
+        });
+        let mut branches = vec![];
+        for leaf in leaves {
+            let mut branch = leaf.branch.clone();
+            if branch.target == final_pc {
+                let Some((operand_register, k)) = leaf.operand else {
+                    return Ok(None);
+                };
+                if *register.get_or_insert(operand_register) != operand_register {
+                    return Ok(None);
+                }
+                branch.target = if k == 1 { on_true } else { on_false };
+            } else if branch.target != on_true
+                && branch.target != on_false
+                && branch.target != tail_start
+                && !leaves
+                    .iter()
+                    .any(|other| other.branch.start == branch.target)
+            {
+                return Ok(None);
+            }
+            branches.push(branch);
+        }
+        let Some(register) = register else {
+            return Ok(None);
+        };
+        let declares_local = self
+            .locals
+            .iter()
+            .any(|local| (first.branch.start + 1..final_pc).contains(&local.start_pc));
+        if declares_local {
+            return Ok(None);
+        }
+        let tail = if tail_start < tail_end {
+            match self.value_tail(register, tail_start, tail_end, final_pc) {
+                Some(value) => Some((tail_start, value)),
+                None => return Ok(None),
+            }
+        } else if booleans.is_some_and(|(false_pc, _)| false_pc == tail_start) {
+            None
+        } else {
+            return Ok(None);
+        };
+        let Some(expression) = chain_value(&branches, tail.as_ref(), on_true, on_false) else {
+            return Ok(None);
+        };
+        self.oldest_read = None;
+        self.write(register, expression, final_pc - 1);
+        self.declare_locals(final_pc..=final_pc);
+        Ok(Some(final_pc))
+    }
+
+    /// The value the instructions `start..end` leave in `register`
+    fn value_tail(
+        &mut self,
+        register: u8,
+        start: usize,
+        end: usize,
+        final_pc: usize,
+    ) -> Option<Expression> {
+        if !self.is_expression_code(start, end) {
+            return None;
+        }
+        let outer = self.value_end.replace(final_pc);
+        let block = self.block(start, end);
+        self.value_end = outer;
+        match block.ok()?.statements.as_slice() {
+            [] => match self.registers.remove(&register) {
+                Some(Pending {
+                    value: Value::Expression(value),
+                    ..
+                }) => Some(value),
+                _ => None,
+            },
+            // A local is assigned the value directly
+            [Statement::Assign { targets, values }]
+                if values.len() == 1
+                    && *targets == [Expression::Name(self.register_name(register, end - 1))] =>
+            {
+                values.first().cloned()
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,695 @@
+use std::collections::{BTreeSet, HashSet};
+
+use crate::{
+    analysis::decode, binary_chunks::function_block::FunctionBlockChunk,
+    common_structs::constant::LuaConstant, instruction_parsing::instruction::Instruction,
+};
+
+use super::ast::is_name;
+
+/// Name the compiler gives the hidden locals of `for` loops
+const LOOP_STATE_NAME: &str = "(for state)";
+
+/// Names tried for the variables of nested numeric `for` loops
+const COUNTER_NAMES: [&str; 3] = ["i", "j", "k"];
+
+/// A local variable of a function block, held by `register` in `start_pc..end_pc`
+#[derive(Debug, PartialEq, Clone)]
+pub(super) struct Local {
+    pub(super) name: String,
+    pub(super) register: u8,
+    pub(super) start_pc: usize,
+    pub(super) end_pc: usize,
+}
+
+impl Local {
+    fn overlaps(&self, other: &Local) -> bool {
+        self.start_pc < other.end_pc && other.start_pc < self.end_pc
+    }
+}
+
+/// Whether the names of the locals of a function block were stripped, as by
+/// `luac -s` or `strip --keep-lines`
+pub(super) fn is_stripped(function: &FunctionBlockChunk) -> bool {
+    function.debug_info.local_vars.is_empty() && !function.instructions.is_empty()
+}
+
+/// The locals listed in the debug information of a function block
+pub(super) fn debug_locals(function: &FunctionBlockChunk) -> Vec<Local> {
+    function
+        .debug_info
+        .local_vars
+        .iter()
+        .zip(function.debug_info.local_registers())
+        .map(|(local_var, register)| Local {
+            name: local_var.name.as_deref().map_or_else(
+                || format!("r{}", register),
+                |name| String::from_utf8_lossy(name).into_owned(),
+            ),
+            register,
+            start_pc: local_var.start_pc as usize,
+            end_pc: local_var.end_pc as usize,
+        })
+        .collect()
+}
+
+/// Infers the locals of a stripped function block from the lifetimes of
+/// the values in its registers, naming them `l_<depth>_<register>` unless
+/// their use suggests a better name. `upvalue_names` are the names the
+/// block sees through its upvalues, which its locals must not shadow.
+pub(super) fn infer_locals(
+    function: &FunctionBlockChunk,
+    depth: usize,
+    upvalue_names: &[String],
+) -> Vec<Local> {
+    let mut inference = Inference::new(function, depth);
+    inference.collect_evidence();
+    inference.settle();
+    let mut locals = inference.locals();
+    let mut reserved: HashSet<String> = upvalue_names.iter().cloned().collect();
+    global_names(function, &mut reserved);
+    name_locals(function, &mut locals, &reserved);
+    locals
+}
+
+/// A set of registers
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+struct Registers([u64; 4]);
+
+impl Registers {
+    fn insert(&mut self, register: u8) {
+        self.0[register as usize / 64] |= 1 << (register % 64);
+    }
+
+    fn remove(&mut self, register: u8) {
+        self.0[register as usize / 64] &= !(1 << (register % 64));
+    }
+
+    fn contains(&self, register: u8) -> bool {
+        self.0[register as usize / 64] & (1 << (register % 64)) != 0
+    }
+
+    fn union(mut self, other: Registers) -> Registers {
+        for (word, other) in self.0.iter_mut().zip(other.0) {
+            *word |= other;
+        }
+        self
+    }
+
+    fn highest(&self) -> Option<u8> {
+        (0..=u8::MAX)
+            .rev()
+            .find(|register| self.contains(*register))
+    }
+}
+
+/// Evidence of which registers hold locals at each pc. Lua keeps the active
+/// locals in the lowest registers and its temporaries right above them,
+/// each one holding a value until it is used, so the number of active locals
+/// can be bounded from below by the values held across each instruction.
+struct Inference<'a> {
+    function: &'a FunctionBlockChunk,
+    /// Depth of the function block, for the names of its locals
+    depth: usize,
+    instructions: Vec<Option<Instruction>>,
+    successors: Vec<Vec<usize>>,
+    /// Registers read by each instruction, including the captured ones
+    reads: Vec<Vec<u8>>,
+    writes: Vec<Vec<u8>>,
+    live_in: Vec<Registers>,
+    live_out: Vec<Registers>,
+    /// Lower bound of the number of active locals before each instruction
+    active: Vec<u16>,
+    /// The hidden state and variables of `for` loops, whose scopes are known
+    loop_locals: Vec<Local>,
+}
+
+impl<'a> Inference<'a> {
+    fn new(function: &'a FunctionBlockChunk, depth: usize) -> Self {
+        let instructions: Vec<_> = decode(function)
+            .map(|(_, instruction)| instruction)
+            .collect();
+        let length = instructions.len();
+        let mut inference = Inference {
+            function,
+            depth,
+            successors: vec![vec![]; length],
+            reads: vec![vec![]; length],
+            writes: vec![vec![]; length],
+            live_in: vec![Registers::default(); length],
+            live_out: vec![Registers::default(); length],
+            active: vec![function.number_of_parameters as u16; length],
+            loop_locals: vec![],
+            instructions,
+        };
+        inference.decode_registers();
+        inference.liveness();
+        inference
+    }
+
+    /// Fills the successors and the registers read and written by each instruction
+    fn decode_registers(&mut self) {
+        let length = self.instructions.len();
+        // First register of the last call or vararg with multiple results
+        let mut top = None;
+        for pc in 0..length {
+            let Some(instruction) = self.instructions[pc] else {
+                continue;
+            };
+            let range = |(first, count): (u8, Option<u8>)| match count {
+                Some(count) => first..first.saturating_add(count),
+                None => first..top.map_or(first, |top: u8| top.max(first) + 1),
+            };
+            self.reads[pc] = instruction
+                .read_registers()
+                .into_iter()
+                .flat_map(range)
+                .collect();
+            self.writes[pc] = instruction
+                .written_registers()
+                .map(|(first, count)| (first..first.saturating_add(count.unwrap_or(1))).collect())
+                .unwrap_or_default();
+            // A `local function` captures its own register once it is written
+            if let Instruction::Closure(a, bx) = instruction {
+                if let Some(proto) = self.function.protos.get(bx as usize) {
+                    self.reads[pc].extend(
+                        proto
+                            .upvalues
+                            .iter()
+                            .filter(|upvalue| upvalue.in_stack && upvalue.index != a)
+                            .map(|upvalue| upvalue.index),
+                    );
+                }
+            }
+            top = match instruction {
+                Instruction::Call(a, _, 0) | Instruction::Vararg(a, 0) => Some(a),
+                _ => top,
+            };
+            let mut successors = match instruction {
+                Instruction::Jmp(_) | Instruction::TForPrep(..) => vec![],
+                Instruction::LFalseSkip(_) => vec![pc + 2],
+                Instruction::Return(..)
+                | Instruction::Return0()
+                | Instruction::Return1(_)
+                | Instruction::TailCall(..) => vec![],
+                instruction if instruction.is_test() => vec![pc + 1, pc + 2],
+                _ => vec![pc + 1],
+            };
+            successors.extend(instruction.jump_target(pc));
+            successors.retain(|successor| *successor < length);
+            self.successors[pc] = successors;
+        }
+    }
+
+    /// Registers holding a value that is read later, before and after each instruction
+    fn liveness(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for pc in (0..self.instructions.len()).rev() {
+                let live_out = self.successors[pc]
+                    .iter()
+                    .fold(Registers::default(), |live, successor| {
+                        live.union(self.live_in[*successor])
+                    });
+                let mut live_in = live_out;
+                for register in &self.writes[pc] {
+                    live_in.remove(*register);
+                }
+                for register in &self.reads[pc] {
+                    live_in.insert(*register);
+                }
+                if live_in != self.live_in[pc] || live_out != self.live_out[pc] {
+                    self.live_in[pc] = live_in;
+                    self.live_out[pc] = live_out;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// Requires `count` active locals before the instructions `pcs`
+    fn raise(&mut self, pcs: std::ops::Range<usize>, count: u16) -> bool {
+        let mut changed = false;
+        for pc in pcs {
+            if let Some(active) = self.active.get_mut(pc) {
+                if *active < count {
+                    *active = count;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Bounds the active locals by the instructions that only appear at the
+    /// start of a statement, the registers captured by closures, the state
+    /// of loops and the registers left unused below live temporaries
+    fn collect_evidence(&mut self) {
+        for pc in 0..self.instructions.len() {
+            let Some(instruction) = self.instructions[pc] else {
+                continue;
+            };
+            match instruction {
+                // Calls as statements and returns of lists start right above the locals
+                Instruction::Call(a, _, 1) | Instruction::TailCall(a, _, _, _) => {
+                    self.raise(pc..pc + 1, a as u16);
+                }
+                Instruction::Return(a, b, _, _) if b != 2 => {
+                    self.raise(pc..pc + 1, a as u16);
+                }
+                Instruction::Tbc(a) => {
+                    self.raise(pc..pc + 1, a as u16 + 1);
+                }
+                Instruction::Closure(a, bx) => {
+                    let captured: Vec<u8> = self
+                        .function
+                        .protos
+                        .get(bx as usize)
+                        .map(|proto| {
+                            proto
+                                .upvalues
+                                .iter()
+                                .filter(|upvalue| upvalue.in_stack)
+                                .map(|upvalue| upvalue.index)
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    for register in captured {
+                        // A `local function` captures itself once it is declared
+                        let at = if register == a { pc + 1 } else { pc };
+                        self.raise(at..at + 1, register as u16 + 1);
+                    }
+                }
+                Instruction::ForPrep(a, bx) => {
+                    let for_loop = pc + bx as usize + 1;
+                    self.loop_local(a..a + 3, pc, for_loop + 1, Some(LOOP_STATE_NAME));
+                    self.loop_local(a + 3..a + 4, pc + 1, for_loop, None);
+                }
+                Instruction::TForPrep(a, bx) => {
+                    let call = pc + bx as usize + 1;
+                    let variables = match self.instructions.get(call) {
+                        Some(Some(Instruction::TForCall(_, c))) => *c,
+                        _ => 0,
+                    };
+                    self.loop_local(a..a + 4, pc, call + 2, Some(LOOP_STATE_NAME));
+                    self.loop_local(a + 4..a + 4 + variables, pc + 1, call, None);
+                }
+                _ => {}
+            }
+            let mut occupied = self.live_in[pc];
+            for register in &self.writes[pc] {
+                occupied.insert(*register);
+            }
+            if let Some(highest) = occupied.highest() {
+                if let Some(hole) = (0..highest)
+                    .rev()
+                    .find(|register| !occupied.contains(*register))
+                {
+                    self.raise(pc..pc + 1, hole as u16 + 1);
+                }
+            }
+        }
+        self.multiple_uses();
+        self.held_values();
+    }
+
+    /// Adds the locals of a loop, which hold `registers` in `start..end`, named
+    /// `name` or like the other inferred locals
+    fn loop_local(
+        &mut self,
+        registers: std::ops::Range<u8>,
+        start: usize,
+        end: usize,
+        name: Option<&str>,
+    ) {
+        for register in registers.clone() {
+            self.loop_locals.push(Local {
+                name: name.map_or_else(|| self.local_name(register), str::to_string),
+                register,
+                start_pc: start,
+                end_pc: end,
+            });
+        }
+        self.raise(start..end, registers.end as u16);
+    }
+
+    /// Pcs of the writes of `register` that may hold its value before each instruction
+    fn reaching_writes(&self, register: u8) -> Vec<BTreeSet<usize>> {
+        let length = self.instructions.len();
+        let mut reaching = vec![BTreeSet::new(); length];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for pc in 0..length {
+                let mut out = reaching[pc].clone();
+                if self.writes[pc].contains(&register) {
+                    out.clear();
+                    out.insert(pc);
+                }
+                for successor in &self.successors[pc] {
+                    if !out.is_subset(&reaching[*successor]) {
+                        reaching[*successor].extend(out.iter().copied());
+                        changed = true;
+                    }
+                }
+            }
+        }
+        reaching
+    }
+
+    /// Pcs of the instructions using `register` as written by each write.
+    /// A `Test` only looks at a value that is used afterwards, and tables
+    /// being filled by a constructor are only used once it is complete.
+    fn uses(&self, register: u8, reaching: &[BTreeSet<usize>]) -> Vec<(usize, usize)> {
+        let mut uses = vec![];
+        for (pc, reads) in self.reads.iter().enumerate() {
+            let inspected = match self.instructions[pc] {
+                Some(
+                    Instruction::Test(a, _)
+                    | Instruction::SetTable(a, _, _, _)
+                    | Instruction::SetI(a, _, _, _)
+                    | Instruction::SetField(a, _, _, _)
+                    | Instruction::SetList(a, _, _, _),
+                ) => Some(a),
+                _ => None,
+            };
+            let mut reads = reads.iter().filter(|read| **read == register).peekable();
+            if inspected == Some(register) {
+                reads.next();
+            }
+            for _ in reads {
+                uses.extend(reaching[pc].iter().map(|write| (*write, pc)));
+            }
+        }
+        uses
+    }
+
+    /// Values used more than once are held by locals, except the variables
+    /// of generic `for` loops, which the loop itself reads once more
+    fn multiple_uses(&mut self) {
+        for register in 0..self.function.maximum_stack_size {
+            let reaching = self.reaching_writes(register);
+            let uses = self.uses(register, &reaching);
+            let writes: BTreeSet<usize> = uses.iter().map(|(write, _)| *write).collect();
+            for write in writes {
+                if let Some(Instruction::TForCall(..)) = self.instructions[write] {
+                    continue;
+                }
+                let pcs: Vec<usize> = uses
+                    .iter()
+                    .filter(|(use_write, _)| *use_write == write)
+                    .map(|(_, pc)| *pc)
+                    .collect();
+                // The local starts after the write, which `settle` finds
+                if let (Some(first), Some(last), true) =
+                    (pcs.iter().min(), pcs.iter().max(), pcs.len() > 1)
+                {
+                    self.raise(*first..*last + 1, register as u16 + 1);
+                }
+            }
+        }
+    }
+
+    /// Values read after a lower register is assigned are held by locals,
+    /// unless they are the pending values of a multiple assignment
+    fn held_values(&mut self) {
+        for register in 0..self.function.maximum_stack_size {
+            let reaching = self.reaching_writes(register);
+            for (pc, writes) in reaching.iter().enumerate() {
+                let assigned = match self.instructions[pc] {
+                    Some(
+                        Instruction::Move(a, _)
+                        | Instruction::SetTable(a, _, _, _)
+                        | Instruction::SetI(a, _, _, _)
+                        | Instruction::SetField(a, _, _, _)
+                        | Instruction::SetUpval(a, _),
+                    ) => a != register,
+                    Some(Instruction::SetTabup(..)) => true,
+                    _ => false,
+                };
+                if assigned || !self.reads[pc].contains(&register) {
+                    continue;
+                }
+                let held = writes.iter().any(|write| {
+                    (*write + 1..pc).any(|between| {
+                        !matches!(self.instructions[between], Some(Instruction::Move(..)))
+                            && self.writes[between].iter().any(|written| {
+                                *written < register && !self.in_loop_local(*written, between)
+                            })
+                    })
+                });
+                if held {
+                    self.raise(pc..pc + 1, register as u16 + 1);
+                }
+            }
+        }
+    }
+
+    /// Pc after the value written to `register` at `write` is complete,
+    /// which is after the fields a table constructor sets and the fallback
+    /// of an arithmetic instruction
+    fn initialized(&self, register: u8, write: usize) -> usize {
+        let constructor = matches!(self.instructions[write], Some(Instruction::NewTable(..)));
+        let mut end = write + 1;
+        while let Some(Some(instruction)) = self.instructions.get(end) {
+            match instruction {
+                Instruction::Extraarg(_)
+                | Instruction::MmBin(..)
+                | Instruction::MmBinI(..)
+                | Instruction::MmBinK(..) => {}
+                Instruction::SetTable(a, _, _, _)
+                | Instruction::SetI(a, _, _, _)
+                | Instruction::SetField(a, _, _, _)
+                | Instruction::SetList(a, _, _, _)
+                    if constructor && *a == register => {}
+                // Items of the constructor are loaded above the table
+                instruction
+                    if constructor
+                        && !instruction.is_test()
+                        && instruction.jump_target(end).is_none()
+                        && !matches!(instruction, Instruction::Call(_, _, 1))
+                        && self.writes[end].iter().all(|written| *written > register) => {}
+                _ => break,
+            }
+            end += 1;
+        }
+        // A constructor ends with the last of its fields
+        if constructor {
+            while end > write + 1
+                && !matches!(
+                    self.instructions[end - 1],
+                    Some(
+                        Instruction::SetTable(..)
+                            | Instruction::SetI(..)
+                            | Instruction::SetField(..)
+                            | Instruction::SetList(..)
+                            | Instruction::Extraarg(_)
+                    )
+                )
+            {
+                end -= 1;
+            }
+        }
+        end
+    }
+
+    /// Whether `register` is held by a loop local at `pc`
+    fn in_loop_local(&self, register: u8, pc: usize) -> bool {
+        self.loop_locals
+            .iter()
+            .any(|local| local.register == register && (local.start_pc..local.end_pc).contains(&pc))
+    }
+
+    /// Ranges of pcs where `register` holds a local other than a loop local
+    fn runs(&self, register: u8) -> Vec<std::ops::Range<usize>> {
+        let mut runs = vec![];
+        let mut start = None;
+        for pc in 0..=self.active.len() {
+            let held = pc < self.active.len()
+                && self.active[pc] > register as u16
+                && !self.in_loop_local(register, pc);
+            match (held, start) {
+                (true, None) => start = Some(pc),
+                (false, Some(run_start)) => {
+                    runs.push(run_start..pc);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        runs
+    }
+
+    /// Extends the evidence until it is consistent: locals stay active while
+    /// their value is used, start right after their register is written and
+    /// are one local across the writes that their uses see
+    fn settle(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for pc in 0..self.instructions.len() {
+                for register in 0..self.active[pc].min(256) as u8 {
+                    if !self.live_out[pc].contains(register) {
+                        continue;
+                    }
+                    for successor in self.successors[pc].clone() {
+                        if self.live_in[successor].contains(register) {
+                            changed |= self.raise(successor..successor + 1, register as u16 + 1);
+                        }
+                    }
+                }
+            }
+            for register in 0..self.function.maximum_stack_size {
+                let runs = self.runs(register);
+                for run in &runs {
+                    if run.start == 0 || self.writes[run.start - 1].contains(&register) {
+                        continue;
+                    }
+                    if let Some(write) = (0..run.start - 1)
+                        .rev()
+                        .find(|pc| self.writes[*pc].contains(&register))
+                    {
+                        let start = self.initialized(register, write);
+                        if start < run.start
+                            && !(start..run.start).any(|pc| self.in_loop_local(register, pc))
+                        {
+                            changed |= self.raise(start..run.start, register as u16 + 1);
+                        }
+                    }
+                }
+                let reaching = self.reaching_writes(register);
+                let uses = self.uses(register, &reaching);
+                for pair in runs.windows(2) {
+                    let (first, second) = (&pair[0], &pair[1]);
+                    let joined = uses.iter().any(|(write, pc)| {
+                        *write + 1 >= first.start && *write < first.end && second.contains(pc)
+                    });
+                    if joined
+                        && !(first.end..second.start).any(|pc| self.in_loop_local(register, pc))
+                    {
+                        changed |= self.raise(first.end..second.start, register as u16 + 1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The name of an inferred local held by `register`
+    fn local_name(&self, register: u8) -> String {
+        format!("l_{}_{}", self.depth, register)
+    }
+
+    /// The inferred locals, with the parameters and loop locals, ordered by start
+    fn locals(&self) -> Vec<Local> {
+        let length = self.instructions.len();
+        let mut locals: Vec<Local> = (0..self.function.number_of_parameters)
+            .map(|register| Local {
+                name: format!("arg{}", register + 1),
+                register,
+                start_pc: 0,
+                end_pc: length,
+            })
+            .collect();
+        for register in self.function.number_of_parameters..self.function.maximum_stack_size {
+            locals.extend(self.runs(register).into_iter().map(|run| Local {
+                name: self.local_name(register),
+                register,
+                start_pc: run.start,
+                end_pc: run.end,
+            }));
+        }
+        locals.extend(self.loop_locals.iter().cloned());
+        locals.sort_by_key(|local| (local.start_pc, local.register));
+        locals
+    }
+}
+
+/// Adds the names of the globals used by a function block and the ones nested in it
+fn global_names(function: &FunctionBlockChunk, names: &mut HashSet<String>) {
+    for (_, instruction) in decode(function) {
+        let key = match instruction {
+            Some(Instruction::GetTabup(_, _, c)) => c as usize,
+            Some(Instruction::SetTabup(_, b, _, _)) => b as usize,
+            _ => continue,
+        };
+        if let Some(name) = function.constants.get(key).and_then(LuaConstant::as_str) {
+            names.insert(name.to_string());
+        }
+    }
+    for proto in &function.protos {
+        global_names(proto, names);
+    }
+}
+
+/// Replaces the synthesized names of locals with names suggested by their
+/// use, when no other visible variable has them: `self` for a first parameter
+/// used as a method receiver, `i` for loop counters, `k` and `v` for the
+/// variables of generic loops and the key a local was loaded from.
+fn name_locals(function: &FunctionBlockChunk, locals: &mut [Local], reserved: &HashSet<String>) {
+    let instructions: Vec<_> = decode(function)
+        .map(|(_, instruction)| instruction)
+        .collect();
+    let constant_name = |index: u8| {
+        function
+            .constants
+            .get(index as usize)
+            .and_then(LuaConstant::as_str)
+            .filter(|name| is_name(name))
+            .map(str::to_string)
+    };
+    for index in 0..locals.len() {
+        let local = &locals[index];
+        if local.name == LOOP_STATE_NAME {
+            continue;
+        }
+        let previous = local
+            .start_pc
+            .checked_sub(1)
+            .and_then(|pc| instructions.get(pc).copied().flatten());
+        let hints: Vec<String> = match previous {
+            _ if local.start_pc == 0 && local.register == 0 => {
+                let receiver = instructions
+                    .iter()
+                    .any(|instruction| matches!(instruction, Some(Instruction::Self_(_, 0, _, _))));
+                if receiver {
+                    vec!["self".to_string()]
+                } else {
+                    vec![]
+                }
+            }
+            _ if local.start_pc == 0 => vec![],
+            Some(Instruction::ForPrep(..)) => {
+                COUNTER_NAMES.iter().map(|name| name.to_string()).collect()
+            }
+            Some(Instruction::TForPrep(a, _)) => {
+                let variables = locals
+                    .iter()
+                    .filter(|other| other.start_pc == local.start_pc && other.register >= a + 4)
+                    .count();
+                match (variables, local.register.checked_sub(a + 4)) {
+                    (2, Some(0)) => vec!["k".to_string()],
+                    (_, Some(position)) if position as usize + 1 == variables => {
+                        vec!["v".to_string()]
+                    }
+                    _ => vec![],
+                }
+            }
+            Some(Instruction::GetField(a, _, c) | Instruction::GetTabup(a, _, c))
+                if a == local.register =>
+            {
+                constant_name(c).into_iter().collect()
+            }
+            _ => vec![],
+        };
+        let hint = hints.into_iter().find(|hint| {
+            !reserved.contains(hint)
+                && !locals.iter().enumerate().any(|(other, local_var)| {
+                    other != index && local_var.name == *hint && local_var.overlaps(&locals[index])
+                })
+        });
+        if let Some(hint) = hint {
+            locals[index].name = hint;
+        }
+    }
+}
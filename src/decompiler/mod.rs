@@ -0,0 +1,74 @@
+use std::fmt::Display;
+
+use crate::{binary_chunks::proto_path::ProtoPath, lua_file::LuaFile};
+
+use self::{
+    ast::{Block, FunctionBody},
+    lifter::Lifter,
+};
+
+pub mod ast;
+mod lifter;
+mod locals;
+pub mod printer;
+pub mod verify;
+
+/// Reasons a function block could not be decompiled
+#[derive(Debug, PartialEq, Clone)]
+pub enum DecompileError {
+    /// The function block does not exist
+    MissingFunction { path: ProtoPath },
+    /// The instruction could not be decoded
+    InvalidInstruction { path: ProtoPath, pc: usize },
+    /// The instruction is valid but cannot be translated yet
+    UnsupportedInstruction {
+        path: ProtoPath,
+        pc: usize,
+        instruction: String,
+    },
+}
+
+impl Display for DecompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecompileError::MissingFunction { path } => {
+                write!(f, "{}: no such function block", path)
+            }
+            DecompileError::InvalidInstruction { path, pc } => {
+                write!(f, "{} pc {}: invalid instruction", path, pc)
+            }
+            DecompileError::UnsupportedInstruction {
+                path,
+                pc,
+                instruction,
+            } => write!(
+                f,
+                "{} pc {}: unsupported instruction {}",
+                path, pc, instruction
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecompileError {}
+
+/// Translates compiled Lua back to Lua source
+pub struct Decompiler<'a> {
+    file: &'a LuaFile,
+}
+
+impl<'a> Decompiler<'a> {
+    pub fn new(file: &'a LuaFile) -> Self {
+        Decompiler { file }
+    }
+
+    /// Decompiles the main function block, which is the whole chunk
+    pub fn decompile(&self) -> Result<Block, DecompileError> {
+        Ok(self.decompile_function(&ProtoPath::main())?.body)
+    }
+
+    /// Decompiles the function block at `path` with its nested functions
+    pub fn decompile_function(&self, path: &ProtoPath) -> Result<FunctionBody, DecompileError> {
+        Lifter::new(self, path, vec![])?.lift()
+    }
+}
@@ -0,0 +1,888 @@
+use std::fmt::Display;
+
+use super::ast::{
+    Block, Expression, FunctionBody, FunctionCall, FunctionName, LocalAttribute, LocalName,
+    Position, Statement, TableField, UnaryOperator,
+};
+
+/// Precedence of expressions that never need parentheses
+const ATOM_PRECEDENCE: u8 = 13;
+
+/// Maximum width of a table constructor printed on a single line
+const MAX_INLINE_TABLE_WIDTH: usize = 80;
+
+/// Delimits the source line written before a statement until the lines are placed
+const LINE_MARKER: char = '\u{1}';
+
+/// Writes a string as a double quoted Lua string literal, with the bytes
+/// that are not UTF-8 escaped
+pub fn quote_string(string: &[u8]) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for chunk in string.utf8_chunks() {
+        for char in chunk.valid().chars() {
+            quote_char(&mut quoted, char);
+        }
+        for byte in chunk.invalid() {
+            quoted.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn quote_char(quoted: &mut String, char: char) {
+    match char {
+        '\\' => quoted.push_str("\\\\"),
+        '"' => quoted.push_str("\\\""),
+        '\n' => quoted.push_str("\\n"),
+        '\r' => quoted.push_str("\\r"),
+        '\t' => quoted.push_str("\\t"),
+        '\x07' => quoted.push_str("\\a"),
+        '\x08' => quoted.push_str("\\b"),
+        '\x0b' => quoted.push_str("\\v"),
+        '\x0c' => quoted.push_str("\\f"),
+        char if char.is_ascii_control() => quoted.push_str(&format!("\\{:03}", char as u32)),
+        char => quoted.push(char),
+    }
+}
+
+/// Whether a printed line ends with a comment, so nothing can follow it
+fn ends_with_comment(line: &str) -> bool {
+    line.trim_start().starts_with("--")
+        || line
+            .rsplit_once(" -- pc ")
+            .is_some_and(|(_, pc)| !pc.is_empty() && pc.chars().all(|char| char.is_ascii_digit()))
+}
+
+/// Moves the lines marked with a source line down to it with blank lines.
+/// When the output is already past that line, the line is joined to the
+/// previous one, as are unmarked lines that would push the next marked
+/// line past its own.
+fn place_lines(output: &str) -> String {
+    let lines: Vec<(Option<u64>, &str)> = output
+        .lines()
+        .map(|line| {
+            line.strip_prefix(LINE_MARKER)
+                .and_then(|line| line.split_once(LINE_MARKER))
+                .and_then(|(number, line)| Some((Some(number.parse().ok()?), line)))
+                .unwrap_or((None, line))
+        })
+        .collect();
+    let mut placed: Vec<String> = vec![];
+    for (index, (target, line)) in lines.iter().enumerate() {
+        let current = placed.len() as u64;
+        let join = match target {
+            Some(target) => *target <= current,
+            None => lines[index + 1..]
+                .iter()
+                .find_map(|(target, _)| *target)
+                .is_some_and(|next| next <= current + 1),
+        };
+        match placed.last_mut() {
+            Some(previous) if join && !ends_with_comment(previous) => {
+                previous.push(' ');
+                previous.push_str(line.trim_start());
+            }
+            _ => {
+                while placed.len() as u64 + 1 < target.unwrap_or(0) {
+                    placed.push(String::new());
+                }
+                placed.push(line.to_string());
+            }
+        }
+    }
+    if placed.is_empty() {
+        return String::new();
+    }
+    let mut output = placed.join("\n");
+    output.push('\n');
+    output
+}
+
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Binary(operator, _, _) => operator.precedence(),
+        Expression::Unary(_, _) => UnaryOperator::PRECEDENCE,
+        // Negative literals are written with a unary minus
+        Expression::Integer(integer) if *integer < 0 && *integer != i64::MIN => {
+            UnaryOperator::PRECEDENCE
+        }
+        Expression::Number(number) if number.is_finite() && number.is_sign_negative() => {
+            UnaryOperator::PRECEDENCE
+        }
+        _ => ATOM_PRECEDENCE,
+    }
+}
+
+/// Only prefix expressions can be indexed or called without parentheses
+fn is_prefix_expression(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::Name(_) | Expression::Index(_, _) | Expression::Call(_) | Expression::Paren(_)
+    )
+}
+
+/// Writes the Lua source of an AST
+#[derive(Debug, Clone)]
+pub struct Printer {
+    indentation: String,
+    /// Whether statements are placed on the source lines they were compiled from
+    original_lines: bool,
+    /// Whether statements end with a comment giving their pc
+    pc_comments: bool,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Printer {
+            indentation: "    ".to_string(),
+            original_lines: false,
+            pc_comments: false,
+        }
+    }
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `indentation` for each level of nesting instead of four spaces
+    pub fn with_indentation(indentation: &str) -> Self {
+        Printer {
+            indentation: indentation.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Places each statement on the source line it was compiled from, padding
+    /// with blank lines, so the lines of error messages match the output.
+    /// Statements that shared a source line are joined on it.
+    pub fn preserving_lines(mut self) -> Self {
+        self.original_lines = true;
+        self
+    }
+
+    /// Ends the first line of each statement with a `-- pc N` comment
+    pub fn with_pc_comments(mut self) -> Self {
+        self.pc_comments = true;
+        self
+    }
+
+    /// Writes a block as a chunk of Lua source
+    pub fn print_block(&self, block: &Block) -> String {
+        let mut output = String::new();
+        self.block(&mut output, block, 0);
+        if self.original_lines {
+            output = place_lines(&output);
+        }
+        output
+    }
+
+    pub fn print_statement(&self, statement: &Statement) -> String {
+        let mut output = String::new();
+        self.statement(&mut output, statement, 0, true);
+        output
+    }
+
+    pub fn print_expression(&self, expression: &Expression) -> String {
+        self.expression(expression, 0)
+    }
+
+    fn indent(&self, output: &mut String, depth: usize) {
+        for _ in 0..depth {
+            output.push_str(&self.indentation);
+        }
+    }
+
+    fn block(&self, output: &mut String, block: &Block, depth: usize) {
+        for (index, statement) in block.statements.iter().enumerate() {
+            let is_last = index + 1 == block.statements.len();
+            match block.position(index) {
+                Some(position) if !matches!(statement, Statement::Comment(_)) => {
+                    self.positioned_statement(output, statement, position, depth, is_last)
+                }
+                _ => self.statement(output, statement, depth, is_last),
+            }
+        }
+    }
+
+    /// Writes a statement with the marker of its line and its pc comment
+    fn positioned_statement(
+        &self,
+        output: &mut String,
+        statement: &Statement,
+        position: Position,
+        depth: usize,
+        is_last: bool,
+    ) {
+        let start = output.len();
+        if let (true, Some(line)) = (self.original_lines, position.line) {
+            output.push_str(&format!("{}{}{}", LINE_MARKER, line, LINE_MARKER));
+        }
+        self.statement(output, statement, depth, is_last);
+        if self.pc_comments {
+            if let Some(end) = output[start..].find('\n') {
+                output.insert_str(start + end, &format!(" -- pc {}", position.pc));
+            }
+        }
+    }
+
+    /// Writes the contents of a block one level deeper, and the closing `end`
+    fn nested_block(&self, output: &mut String, block: &Block, depth: usize) {
+        self.block(output, block, depth + 1);
+        self.indent(output, depth);
+        output.push_str("end\n");
+    }
+
+    fn statement(&self, output: &mut String, statement: &Statement, depth: usize, is_last: bool) {
+        self.indent(output, depth);
+        let line = match statement {
+            Statement::Local { names, values } => {
+                let mut line = format!("local {}", self.local_names(names));
+                if !values.is_empty() {
+                    line.push_str(" = ");
+                    line.push_str(&self.expression_list(values, depth));
+                }
+                line
+            }
+            Statement::Assign { targets, values } => format!(
+                "{} = {}",
+                self.expression_list(targets, depth),
+                self.expression_list(values, depth)
+            ),
+            Statement::Call(call) => self.function_call(call, depth),
+            Statement::Do(block) => {
+                output.push_str("do\n");
+                return self.nested_block(output, block, depth);
+            }
+            Statement::While { condition, body } => {
+                output.push_str(&format!("while {} do\n", self.expression(condition, depth)));
+                return self.nested_block(output, body, depth);
+            }
+            Statement::Repeat { body, condition } => {
+                output.push_str("repeat\n");
+                self.block(output, body, depth + 1);
+                self.indent(output, depth);
+                format!("until {}", self.expression(condition, depth))
+            }
+            Statement::If {
+                branches,
+                else_block,
+            } => {
+                for (index, (condition, block)) in branches.iter().enumerate() {
+                    if index > 0 {
+                        self.indent(output, depth);
+                        output.push_str("else");
+                    }
+                    output.push_str(&format!("if {} then\n", self.expression(condition, depth)));
+                    self.block(output, block, depth + 1);
+                }
+                if let Some(block) = else_block {
+                    self.indent(output, depth);
+                    output.push_str("else\n");
+                    self.block(output, block, depth + 1);
+                }
+                self.indent(output, depth);
+                "end".to_string()
+            }
+            Statement::NumericFor {
+                variable,
+                start,
+                limit,
+                step,
+                body,
+            } => {
+                let mut header = format!(
+                    "for {} = {}, {}",
+                    variable,
+                    self.expression(start, depth),
+                    self.expression(limit, depth)
+                );
+                if let Some(step) = step {
+                    header.push_str(", ");
+                    header.push_str(&self.expression(step, depth));
+                }
+                output.push_str(&header);
+                output.push_str(" do\n");
+                return self.nested_block(output, body, depth);
+            }
+            Statement::GenericFor {
+                names,
+                expressions,
+                body,
+            } => {
+                output.push_str(&format!(
+                    "for {} in {} do\n",
+                    names.join(", "),
+                    self.expression_list(expressions, depth)
+                ));
+                return self.nested_block(output, body, depth);
+            }
+            Statement::Function { name, body } => {
+                output.push_str(&format!("function {}", self.function_name(name)));
+                return self.function_body(output, body, depth);
+            }
+            Statement::LocalFunction { name, body } => {
+                output.push_str(&format!("local function {}", name));
+                return self.function_body(output, body, depth);
+            }
+            Statement::Return(values) => {
+                let mut line = "return".to_string();
+                if !values.is_empty() {
+                    line.push(' ');
+                    line.push_str(&self.expression_list(values, depth));
+                }
+                // A return statement must end its block
+                if !is_last {
+                    line = format!("do {} end", line);
+                }
+                line
+            }
+            Statement::Break => "break".to_string(),
+            Statement::Goto(label) => format!("goto {}", label),
+            Statement::Label(label) => format!("::{}::", label),
+            Statement::Comment(comment) => {
+                for (index, comment_line) in comment.lines().enumerate() {
+                    if index > 0 {
+                        self.indent(output, depth);
+                    }
+                    if comment_line.is_empty() {
+                        output.push_str("--\n");
+                    } else {
+                        output.push_str(&format!("-- {}\n", comment_line));
+                    }
+                }
+                return;
+            }
+        };
+        // A statement starting with a parenthesis would continue the previous call
+        if line.starts_with('(') {
+            output.push(';');
+        }
+        output.push_str(&line);
+        output.push('\n');
+    }
+
+    fn local_names(&self, names: &[LocalName]) -> String {
+        names
+            .iter()
+            .map(|local_name| match local_name.attribute {
+                Some(LocalAttribute::Const) => format!("{} <const>", local_name.name),
+                Some(LocalAttribute::Close) => format!("{} <close>", local_name.name),
+                None => local_name.name.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn function_name(&self, name: &FunctionName) -> String {
+        let mut function_name = name.base.clone();
+        for field in &name.fields {
+            function_name.push('.');
+            function_name.push_str(field);
+        }
+        if let Some(method) = &name.method {
+            function_name.push(':');
+            function_name.push_str(method);
+        }
+        function_name
+    }
+
+    /// Writes the parameters, body and `end` of a function
+    fn function_body(&self, output: &mut String, body: &FunctionBody, depth: usize) {
+        let mut parameters = body.parameters.clone();
+        if body.is_vararg {
+            parameters.push("...".to_string());
+        }
+        output.push_str(&format!("({})\n", parameters.join(", ")));
+        self.nested_block(output, &body.body, depth);
+    }
+
+    fn expression_list(&self, expressions: &[Expression], depth: usize) -> String {
+        expressions
+            .iter()
+            .map(|expression| self.expression(expression, depth))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Writes an operand, in parentheses if needed
+    fn operand(&self, expression: &Expression, depth: usize, parenthesize: bool) -> String {
+        let operand = self.expression(expression, depth);
+        if parenthesize {
+            format!("({})", operand)
+        } else {
+            operand
+        }
+    }
+
+    /// Writes an expression that is indexed or called
+    fn prefix_expression(&self, expression: &Expression, depth: usize) -> String {
+        self.operand(expression, depth, !is_prefix_expression(expression))
+    }
+
+    fn function_call(&self, call: &FunctionCall, depth: usize) -> String {
+        let mut output = self.prefix_expression(&call.function, depth);
+        if let Some(method) = &call.method {
+            output.push(':');
+            output.push_str(method);
+        }
+        output.push('(');
+        output.push_str(&self.expression_list(&call.arguments, depth));
+        output.push(')');
+        output
+    }
+
+    fn expression(&self, expression: &Expression, depth: usize) -> String {
+        match expression {
+            Expression::Nil => "nil".to_string(),
+            Expression::Boolean(boolean) => boolean.to_string(),
+            // Hexadecimal integers wrap around, unlike decimal ones
+            Expression::Integer(i64::MIN) => "0x8000000000000000".to_string(),
+            Expression::Integer(integer) => integer.to_string(),
+            Expression::Number(number) if number.is_nan() => "(0/0)".to_string(),
+            Expression::Number(number) if number.is_infinite() => {
+                if number.is_sign_positive() {
+                    "(1/0)".to_string()
+                } else {
+                    "(-1/0)".to_string()
+                }
+            }
+            Expression::Number(number) => format!("{:?}", number),
+            Expression::String(string) => quote_string(string),
+            Expression::Vararg => "...".to_string(),
+            Expression::Name(name) => name.clone(),
+            Expression::Index(table, key) => {
+                let table = self.prefix_expression(table, depth);
+                match key.as_name() {
+                    Some(name) => format!("{}.{}", table, name),
+                    None => format!("{}[{}]", table, self.expression(key, depth)),
+                }
+            }
+            Expression::Call(call) => self.function_call(call, depth),
+            Expression::Function(body) => {
+                let mut output = "function".to_string();
+                self.function_body(&mut output, body, depth);
+                output.pop();
+                output
+            }
+            Expression::Table(fields) => self.table(fields, depth),
+            Expression::Paren(expression) => format!("({})", self.expression(expression, depth)),
+            Expression::Binary(operator, left, right) => {
+                let precedence = operator.precedence();
+                let left_precedence = self::precedence(left);
+                let right_precedence = self::precedence(right);
+                let right_associative = operator.is_right_associative();
+                format!(
+                    "{} {} {}",
+                    self.operand(
+                        left,
+                        depth,
+                        left_precedence < precedence
+                            || (left_precedence == precedence && right_associative)
+                    ),
+                    operator.symbol(),
+                    self.operand(
+                        right,
+                        depth,
+                        right_precedence < precedence
+                            || (right_precedence == precedence && !right_associative)
+                    )
+                )
+            }
+            Expression::Unary(operator, operand) => {
+                let operand = self.operand(
+                    operand,
+                    depth,
+                    self::precedence(operand) < UnaryOperator::PRECEDENCE,
+                );
+                // `--` would start a comment
+                if *operator == UnaryOperator::Neg && operand.starts_with('-') {
+                    format!("- {}", operand)
+                } else {
+                    format!("{}{}", operator.symbol(), operand)
+                }
+            }
+        }
+    }
+
+    fn table(&self, fields: &[TableField], depth: usize) -> String {
+        if fields.is_empty() {
+            return "{}".to_string();
+        }
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| match field {
+                TableField::Positional(value) => self.expression(value, depth + 1),
+                TableField::Keyed(key, value) if key.as_name().is_some() => format!(
+                    "{} = {}",
+                    key.as_name().unwrap_or_default(),
+                    self.expression(value, depth + 1)
+                ),
+                TableField::Keyed(key, value) => format!(
+                    "[{}] = {}",
+                    self.expression(key, depth + 1),
+                    self.expression(value, depth + 1)
+                ),
+            })
+            .collect();
+        let inline = format!("{{{}}}", fields.join(", "));
+        if inline.len() <= MAX_INLINE_TABLE_WIDTH && !inline.contains('\n') {
+            return inline;
+        }
+        let mut output = "{\n".to_string();
+        for field in fields {
+            self.indent(&mut output, depth + 1);
+            output.push_str(&field);
+            output.push_str(",\n");
+        }
+        self.indent(&mut output, depth);
+        output.push('}');
+        output
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Printer::default().print_block(self))
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Printer::default().print_statement(self))
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Printer::default().print_expression(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decompiler::ast::{
+        BinaryOperator::*, Block, Expression, FunctionBody, FunctionCall, FunctionName,
+        LocalAttribute, LocalName, Position, Statement, TableField, UnaryOperator::*,
+    };
+
+    use super::{quote_string, Printer};
+
+    fn name(name: &str) -> Expression {
+        Expression::name(name)
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let cases = [
+            (
+                Expression::binary(
+                    Mul,
+                    Expression::binary(Add, name("a"), name("b")),
+                    name("c"),
+                ),
+                "(a + b) * c",
+            ),
+            (
+                Expression::binary(
+                    Add,
+                    name("a"),
+                    Expression::binary(Mul, name("b"), name("c")),
+                ),
+                "a + b * c",
+            ),
+            (
+                Expression::binary(
+                    Sub,
+                    name("a"),
+                    Expression::binary(Sub, name("b"), name("c")),
+                ),
+                "a - (b - c)",
+            ),
+            (
+                Expression::binary(
+                    Sub,
+                    Expression::binary(Sub, name("a"), name("b")),
+                    name("c"),
+                ),
+                "a - b - c",
+            ),
+            (
+                Expression::binary(
+                    Pow,
+                    Expression::binary(Pow, name("a"), name("b")),
+                    name("c"),
+                ),
+                "(a ^ b) ^ c",
+            ),
+            (
+                Expression::binary(
+                    Concat,
+                    name("a"),
+                    Expression::binary(Concat, name("b"), name("c")),
+                ),
+                "a .. b .. c",
+            ),
+            (
+                Expression::unary(Neg, Expression::binary(Pow, name("a"), name("b"))),
+                "-a ^ b",
+            ),
+            (
+                Expression::binary(Pow, Expression::unary(Neg, name("a")), name("b")),
+                "(-a) ^ b",
+            ),
+            (
+                Expression::binary(Pow, Expression::Integer(-2), Expression::Integer(2)),
+                "(-2) ^ 2",
+            ),
+            (
+                Expression::unary(Neg, Expression::unary(Neg, name("a"))),
+                "- -a",
+            ),
+            (
+                Expression::unary(Not, Expression::binary(Eq, name("a"), name("b"))),
+                "not (a == b)",
+            ),
+            (
+                Expression::binary(
+                    Or,
+                    Expression::binary(And, name("a"), name("b")),
+                    Expression::binary(And, name("c"), name("d")),
+                ),
+                "a and b or c and d",
+            ),
+            (
+                Expression::binary(And, Expression::binary(Or, name("a"), name("b")), name("c")),
+                "(a or b) and c",
+            ),
+            (
+                Expression::binary(
+                    BAnd,
+                    Expression::binary(Shl, name("a"), Expression::Integer(1)),
+                    Expression::binary(BOr, name("b"), name("c")),
+                ),
+                "a << 1 & (b | c)",
+            ),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(expression.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_literals_and_prefix_expressions() {
+        assert_eq!(
+            quote_string(b"a\"b\\c\n\0\x01z"),
+            "\"a\\\"b\\\\c\\n\\000\\001z\""
+        );
+        assert_eq!(quote_string(b"caf\xe9 \xff"), "\"caf\\xe9 \\xff\"");
+        assert_eq!(Expression::Number(1.0).to_string(), "1.0");
+        assert_eq!(Expression::Number(f64::INFINITY).to_string(), "(1/0)");
+        assert_eq!(
+            Expression::Integer(i64::MIN).to_string(),
+            "0x8000000000000000"
+        );
+        assert_eq!(
+            Expression::field(Expression::field(name("a"), "b"), "end").to_string(),
+            "a.b[\"end\"]"
+        );
+        assert_eq!(
+            Expression::Call(Box::new(FunctionCall::method(
+                Expression::String(b"x".to_vec()),
+                "rep",
+                vec![Expression::Integer(3)]
+            )))
+            .to_string(),
+            "(\"x\"):rep(3)"
+        );
+        assert_eq!(
+            Expression::Table(vec![
+                TableField::Positional(Expression::Integer(1)),
+                TableField::Keyed(Expression::String(b"x".to_vec()), Expression::Vararg),
+                TableField::Keyed(Expression::Integer(5), Expression::Boolean(true)),
+            ])
+            .to_string(),
+            "{1, x = ..., [5] = true}"
+        );
+    }
+
+    #[test]
+    fn test_statements() {
+        let block = Block::new(vec![
+            Statement::Local {
+                names: vec![
+                    LocalName::new("a"),
+                    LocalName {
+                        name: "b".to_string(),
+                        attribute: Some(LocalAttribute::Const),
+                    },
+                ],
+                values: vec![Expression::Integer(1), Expression::Nil],
+            },
+            Statement::Function {
+                name: FunctionName {
+                    base: "t".to_string(),
+                    fields: vec!["u".to_string()],
+                    method: Some("m".to_string()),
+                },
+                body: FunctionBody {
+                    parameters: vec!["x".to_string()],
+                    is_vararg: true,
+                    body: Block::new(vec![Statement::If {
+                        branches: vec![
+                            (name("x"), Block::new(vec![Statement::Return(vec![])])),
+                            (
+                                name("y"),
+                                Block::new(vec![
+                                    Statement::Return(vec![name("x")]),
+                                    Statement::Break,
+                                ]),
+                            ),
+                        ],
+                        else_block: Some(Block::new(vec![Statement::Goto("done".to_string())])),
+                    }]),
+                },
+            },
+            Statement::NumericFor {
+                variable: "i".to_string(),
+                start: Expression::Integer(1),
+                limit: Expression::Integer(10),
+                step: Some(Expression::Integer(2)),
+                body: Block::new(vec![Statement::Call(FunctionCall::new(
+                    Expression::Function(Box::default()),
+                    vec![],
+                ))]),
+            },
+            Statement::Label("done".to_string()),
+            Statement::Comment("two\nlines".to_string()),
+        ]);
+        assert_eq!(
+            Printer::with_indentation("  ").print_block(&block),
+            indoc!(
+                "
+                local a, b <const> = 1, nil
+                function t.u:m(x, ...)
+                  if x then
+                    return
+                  elseif y then
+                    do return x end
+                    break
+                  else
+                    goto done
+                  end
+                end
+                for i = 1, 10, 2 do
+                  ;(function()
+                  end)()
+                end
+                ::done::
+                -- two
+                -- lines
+                "
+            )
+            .trim_start()
+        );
+    }
+
+    #[test]
+    fn test_multiline_table_indentation() {
+        let statement = Statement::Assign {
+            targets: vec![name("t")],
+            values: vec![Expression::Table(vec![
+                TableField::Positional(Expression::String(b"a".repeat(40))),
+                TableField::Positional(Expression::String(b"b".repeat(40))),
+                TableField::Keyed(
+                    Expression::String(b"f".to_vec()),
+                    Expression::Function(Box::new(FunctionBody {
+                        parameters: vec![],
+                        is_vararg: false,
+                        body: Block::new(vec![Statement::Return(vec![Expression::Integer(1)])]),
+                    })),
+                ),
+            ])],
+        };
+        assert_eq!(
+            statement.to_string(),
+            format!(
+                "t = {{\n    \"{}\",\n    \"{}\",\n    f = function()\n        return 1\n    end,\n}}\n",
+                "a".repeat(40),
+                "b".repeat(40)
+            )
+        );
+    }
+
+    fn positioned_block() -> Block {
+        let local = |variable: &str, value| Statement::Local {
+            names: vec![LocalName::new(variable)],
+            values: vec![Expression::Integer(value)],
+        };
+        let at = |pc, line| Position {
+            pc,
+            line: Some(line),
+        };
+        Block::with_positions(
+            vec![
+                local("x", 1),
+                local("y", 2),
+                Statement::If {
+                    branches: vec![(
+                        name("x"),
+                        Block::with_positions(
+                            vec![Statement::Call(FunctionCall::new(
+                                name("print"),
+                                vec![name("x")],
+                            ))],
+                            vec![at(5, 6)],
+                        ),
+                    )],
+                    else_block: None,
+                },
+                Statement::Return(vec![name("y")]),
+            ],
+            vec![at(1, 3), at(2, 3), at(3, 5), at(7, 7)],
+        )
+    }
+
+    #[test]
+    fn test_original_lines() {
+        assert_eq!(
+            Printer::new()
+                .preserving_lines()
+                .print_block(&positioned_block()),
+            indoc!(
+                "
+
+
+                local x = 1 local y = 2
+
+                if x then
+                    print(x) end
+                return y
+                "
+            )
+        );
+    }
+
+    #[test]
+    fn test_pc_comments() {
+        assert_eq!(
+            Printer::new()
+                .with_pc_comments()
+                .print_block(&positioned_block()),
+            indoc!(
+                "
+                local x = 1 -- pc 1
+                local y = 2 -- pc 2
+                if x then -- pc 3
+                    print(x) -- pc 5
+                end
+                return y -- pc 7
+                "
+            )
+            .trim_start()
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    analysis::decode,
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    common_structs::constant::LuaConstant,
+    disassembler::display_constant,
+    instruction_parsing::instruction::Instruction,
+    lua_file::LuaFile,
+};
+
+use super::{printer::Printer, DecompileError, Decompiler};
+
+/// Compiles Lua 5.4 source to a binary chunk
+pub trait Compiler {
+    fn compile(&self, source: &str) -> Result<Vec<u8>, String>;
+}
+
+impl<F: Fn(&str) -> Result<Vec<u8>, String>> Compiler for F {
+    fn compile(&self, source: &str) -> Result<Vec<u8>, String> {
+        self(source)
+    }
+}
+
+/// Compiles by running a `luac` executable, which reads the source from stdin
+#[derive(Debug, PartialEq, Clone)]
+pub struct Luac {
+    program: PathBuf,
+}
+
+impl Default for Luac {
+    fn default() -> Self {
+        Luac {
+            program: PathBuf::from("luac"),
+        }
+    }
+}
+
+impl Luac {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Luac {
+            program: program.into(),
+        }
+    }
+}
+
+impl Compiler for Luac {
+    fn compile(&self, source: &str) -> Result<Vec<u8>, String> {
+        let program = self.program.display();
+        let mut child = Command::new(&self.program)
+            .args(["-o", "-", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("{}: {}", program, error))?;
+        // luac reads the whole source before writing anything
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(source.as_bytes())
+                .map_err(|error| format!("{}: {}", program, error))?;
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|error| format!("{}: {}", program, error))?;
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+    }
+}
+
+/// Reasons a file could not be checked
+#[derive(Debug, PartialEq, Clone)]
+pub enum VerifyError {
+    Decompile(DecompileError),
+    /// The decompiled source was rejected by the compiler
+    Compile(String),
+    /// The compiler did not produce a Lua 5.4 binary chunk
+    InvalidChunk,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::Decompile(error) => write!(f, "{}", error),
+            VerifyError::Compile(message) => {
+                write!(f, "decompiled source does not compile: {}", message)
+            }
+            VerifyError::InvalidChunk => write!(f, "compiler output is not a binary chunk"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl From<DecompileError> for VerifyError {
+    fn from(error: DecompileError) -> Self {
+        VerifyError::Decompile(error)
+    }
+}
+
+/// A difference between a function block and its recompiled decompilation
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mismatch {
+    pub path: ProtoPath,
+    /// Pc of the first instruction that differs, if the code differs
+    pub pc: Option<usize>,
+    pub message: String,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.pc {
+            Some(pc) => write!(f, "{} pc {}: {}", self.path, pc, self.message),
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+/// Decompiles `file`, compiles the source back with `compiler` and compares
+/// the result to `file`, returning the functions that differ
+pub fn verify(file: &LuaFile, compiler: &impl Compiler) -> Result<Vec<Mismatch>, VerifyError> {
+    let block = Decompiler::new(file).decompile()?;
+    let source = Printer::new().print_block(&block);
+    let chunk = compiler.compile(&source).map_err(VerifyError::Compile)?;
+    let (_, recompiled) = LuaFile::parse(&chunk).map_err(|_| VerifyError::InvalidChunk)?;
+    Ok(compare(file, &recompiled))
+}
+
+/// Compares the function blocks of two files with the same structure. Register
+/// allocation and the order of the constants may differ, and debug
+/// information is ignored. Only the first difference of each function block
+/// is reported, as the following ones usually derive from it.
+pub fn compare(original: &LuaFile, recompiled: &LuaFile) -> Vec<Mismatch> {
+    let mut mismatches = vec![];
+    compare_function(
+        &ProtoPath::main(),
+        &original.main_function_block,
+        &recompiled.main_function_block,
+        &mut mismatches,
+    );
+    mismatches
+}
+
+fn compare_function(
+    path: &ProtoPath,
+    original: &FunctionBlockChunk,
+    recompiled: &FunctionBlockChunk,
+    mismatches: &mut Vec<Mismatch>,
+) {
+    let mismatch = |pc, message: String| Mismatch {
+        path: path.clone(),
+        pc,
+        message,
+    };
+    let signature = |function: &FunctionBlockChunk| {
+        (
+            function.number_of_parameters,
+            function.is_vararg.has_arg() != 0,
+        )
+    };
+    let upvalues = |function: &FunctionBlockChunk| {
+        function
+            .upvalues
+            .iter()
+            .map(|upvalue| (upvalue.in_stack, upvalue.index))
+            .collect::<Vec<_>>()
+    };
+    if signature(original) != signature(recompiled) {
+        mismatches.push(mismatch(
+            None,
+            format!(
+                "expected {} parameters and vararg {}, found {} and {}",
+                original.number_of_parameters,
+                signature(original).1,
+                recompiled.number_of_parameters,
+                signature(recompiled).1
+            ),
+        ));
+    } else if upvalues(original) != upvalues(recompiled) {
+        mismatches.push(mismatch(
+            None,
+            format!(
+                "expected upvalues {:?}, found {:?}",
+                upvalues(original),
+                upvalues(recompiled)
+            ),
+        ));
+    } else if let Some((pc, message)) = compare_code(original, recompiled) {
+        mismatches.push(mismatch(Some(pc), message));
+    }
+    if original.protos.len() != recompiled.protos.len() {
+        mismatches.push(mismatch(
+            None,
+            format!(
+                "expected {} nested functions, found {}",
+                original.protos.len(),
+                recompiled.protos.len()
+            ),
+        ));
+    }
+    for (index, (original, recompiled)) in
+        original.protos.iter().zip(&recompiled.protos).enumerate()
+    {
+        compare_function(&path.child(index), original, recompiled, mismatches);
+    }
+}
+
+/// The first pc where the normalized instructions differ, with a description
+fn compare_code(
+    original: &FunctionBlockChunk,
+    recompiled: &FunctionBlockChunk,
+) -> Option<(usize, String)> {
+    let original_code = normalize(original);
+    let recompiled_code = normalize(recompiled);
+    let describe = |function: &FunctionBlockChunk, pc: usize| {
+        let instruction = decode(function)
+            .nth(pc)
+            .and_then(|(_, instruction)| instruction);
+        match instruction {
+            Some(instruction) => format!("{:?}", instruction),
+            None => format!("{:#010x}", function.instructions[pc]),
+        }
+    };
+    for pc in 0..original_code.len().min(recompiled_code.len()) {
+        let message = match (&original_code[pc], &recompiled_code[pc]) {
+            (None, None) => continue,
+            // The same instruction reading other constants
+            (Some((instruction, constants)), Some((other, other_constants)))
+                if instruction == other =>
+            {
+                let Some((expected, found)) = constants
+                    .iter()
+                    .zip(other_constants)
+                    .find(|(expected, found)| !same_constant(**expected, **found))
+                else {
+                    continue;
+                };
+                let display = |constant: Option<&LuaConstant>| {
+                    constant.map_or_else(|| "?".to_string(), display_constant)
+                };
+                format!(
+                    "expected {} to read {}, found {}",
+                    describe(original, pc),
+                    display(*expected),
+                    display(*found)
+                )
+            }
+            _ => format!(
+                "expected {}, found {}",
+                describe(original, pc),
+                describe(recompiled, pc)
+            ),
+        };
+        return Some((pc, message));
+    }
+    if original_code.len() != recompiled_code.len() {
+        let pc = original_code.len().min(recompiled_code.len());
+        return Some((
+            pc,
+            format!(
+                "expected {} instructions, found {}",
+                original_code.len(),
+                recompiled_code.len()
+            ),
+        ));
+    }
+    None
+}
+
+fn same_constant(expected: Option<&LuaConstant>, found: Option<&LuaConstant>) -> bool {
+    match (expected, found) {
+        (Some(expected), Some(found)) => expected.is_same(found),
+        (expected, found) => expected.is_none() && found.is_none(),
+    }
+}
+
+/// An instruction with its registers numbered by first use and its constant
+/// indices cleared, along with the constants it reads
+type Normalized<'a> = Option<(Instruction, Vec<Option<&'a LuaConstant>>)>;
+
+fn normalize(function: &FunctionBlockChunk) -> Vec<Normalized<'_>> {
+    let mut registers: HashMap<u8, u8> = HashMap::new();
+    let mut previous = None;
+    decode(function)
+        .map(|(_, instruction)| {
+            let instruction = instruction?;
+            let mut indices = instruction.constant_indices();
+            let mut instruction = instruction.rewrite_operands(
+                |register| {
+                    let next = registers.len() as u8;
+                    *registers.entry(register).or_insert(next)
+                },
+                |_| 0,
+            );
+            // The constant of `LoadKx`
+            if let (Some(Instruction::LoadKx(_)), Instruction::Extraarg(ax)) =
+                (previous, instruction)
+            {
+                indices.push(ax);
+                instruction = Instruction::Extraarg(0);
+            }
+            previous = Some(instruction);
+            let constants = indices
+                .iter()
+                .map(|index| function.constants.get(*index as usize))
+                .collect();
+            Some((instruction, constants))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{binary_chunks::proto_path::ProtoPath, lua_file::LuaFile};
+
+    use super::{compare, verify, Mismatch};
+    use crate::decompiler::Decompiler;
+
+    /// Compiles with the Lua 5.4 interpreter linked into the tests
+    fn compile(source: &str) -> Result<Vec<u8>, String> {
+        let lua = mlua::Lua::new();
+        let function = lua
+            .load(source)
+            .into_function()
+            .map_err(|error| error.to_string())?;
+        Ok(function.dump(false))
+    }
+
+    fn parse(chunk: &[u8]) -> LuaFile {
+        LuaFile::parse(chunk).unwrap().1
+    }
+
+    #[test]
+    fn test_verify_fixtures() {
+        let fixtures: [(&str, &[u8]); 11] = [
+            (
+                "all_opcodes",
+                include_bytes!("../../tests/all_opcodes.luac"),
+            ),
+            ("audit", include_bytes!("../../tests/audit.luac")),
+            (
+                "binary_strings",
+                include_bytes!("../../tests/binary_strings.luac"),
+            ),
+            ("closures", include_bytes!("../../tests/closures.luac")),
+            (
+                "control_flow",
+                include_bytes!("../../tests/control_flow.luac"),
+            ),
+            (
+                "expressions",
+                include_bytes!("../../tests/expressions.luac"),
+            ),
+            ("functions", include_bytes!("../../tests/functions.luac")),
+            ("globals", include_bytes!("../../tests/globals.luac")),
+            ("stripped", include_bytes!("../../tests/stripped.luac")),
+            ("tables", include_bytes!("../../tests/tables.luac")),
+            ("values", include_bytes!("../../tests/values.luac")),
+        ];
+        for (name, chunk) in fixtures {
+            assert_eq!(verify(&parse(chunk), &compile), Ok(vec![]), "{}", name);
+        }
+        // `if i == 2 then goto continue end` comes back as `if i ~= 2 then`,
+        // without the jump over the empty then block
+        let mismatches = verify(&parse(include_bytes!("../../tests/goto.luac")), &compile).unwrap();
+        assert_eq!(
+            mismatches
+                .iter()
+                .map(|mismatch| (mismatch.pc, mismatch.message.as_str()))
+                .collect::<Vec<_>>(),
+            [(Some(12), "expected ForPrep(2, 6), found ForPrep(2, 5)")]
+        );
+    }
+
+    #[test]
+    fn test_verify_comparisons() {
+        let sources = [
+            "print(a > b, a >= b, a < b, a <= b)",
+            "if f() > g() then print(1) end",
+            "local x = ... print(x > y, y >= x)",
+        ];
+        for source in sources {
+            let file = parse(&compile(source).unwrap());
+            let decompiled = Decompiler::new(&file).decompile().unwrap().to_string();
+            assert_eq!(verify(&file, &compile), Ok(vec![]), "{}", decompiled);
+        }
+        let file = parse(&compile(sources[0]).unwrap());
+        let decompiled = Decompiler::new(&file).decompile().unwrap().to_string();
+        assert_eq!(decompiled, "print(a > b, a >= b, a < b, a <= b)\n");
+    }
+
+    #[test]
+    fn test_compare() {
+        let original = parse(&compile("local a = 1 return function(x) return a + x end").unwrap());
+        let changed =
+            parse(&compile("local a = 2 return function(x, y) return a + x end").unwrap());
+        assert_eq!(
+            compare(&original, &changed),
+            vec![
+                Mismatch {
+                    path: ProtoPath::main(),
+                    pc: Some(1),
+                    message: "expected LoadI(0, 1), found LoadI(0, 2)".to_string(),
+                },
+                Mismatch {
+                    path: ProtoPath::main().child(0),
+                    pc: None,
+                    message: "expected 1 parameters and vararg false, found 2 and false"
+                        .to_string(),
+                },
+            ]
+        );
+        assert_eq!(compare(&original, &original), vec![]);
+    }
+
+    #[test]
+    fn test_compare_constants() {
+        let original = parse(&compile("print(\"\\xff\\xfe\")").unwrap());
+        let changed = parse(&compile("print(\"\\xfe\\xff\")").unwrap());
+        assert_eq!(
+            compare(&original, &changed),
+            vec![Mismatch {
+                path: ProtoPath::main(),
+                pc: Some(2),
+                message: "expected LoadK(1, 1) to read \"\\xff\\xfe\", found \"\\xfe\\xff\""
+                    .to_string(),
+            }]
+        );
+    }
+}
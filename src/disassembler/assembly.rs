@@ -0,0 +1,290 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use super::{display_constant, quote_bytes};
+use crate::{
+    assembler::line_info,
+    binary_chunks::{function_block::FunctionBlockChunk, header::HeaderChunk},
+    common_structs::variable_kind::VariableKind,
+    instruction_parsing::{
+        instruction::Instruction, instruction_encodings::InstructionEncoding, opcodes::Opcode,
+    },
+    lua_file::LuaFile,
+};
+
+/// Line deltas written per `.lineinfo` directive
+const LINE_DELTAS_PER_DIRECTIVE: usize = 16;
+
+/// The source of `file` for the assembler, which assembles it back to the
+/// same bytes
+pub(super) fn assembly(file: &LuaFile) -> String {
+    let mut text = String::new();
+    let main = &file.main_function_block;
+    if file.header != HeaderChunk::default()
+        || file.number_of_upvalues as usize != main.upvalues.len()
+    {
+        let header = &file.header;
+        text.push_str(&format!(
+            ".header {} {} {} {} {} {}\n",
+            header.version_number,
+            header.format_version,
+            header.size_of_int,
+            header.size_of_size_t,
+            header.size_of_lua_number,
+            file.number_of_upvalues
+        ));
+    }
+    text.push_str(
+        &FunctionAssembly {
+            name: "main".to_string(),
+            function: main,
+            depth: 0,
+        }
+        .to_string(),
+    );
+    text
+}
+
+/// A function block and its nested ones, indented by their depth
+struct FunctionAssembly<'a> {
+    name: String,
+    function: &'a FunctionBlockChunk,
+    depth: usize,
+}
+
+impl Display for FunctionAssembly<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let FunctionAssembly {
+            name,
+            function,
+            depth,
+        } = self;
+        let indent = "    ".repeat(*depth);
+        writeln!(f, "{}.function {}", indent, name)?;
+        let indent = "    ".repeat(depth + 1);
+        if let Some(source_name) = &function.source_name {
+            writeln!(f, "{}.source {}", indent, quote_bytes(source_name))?;
+        }
+        writeln!(
+            f,
+            "{}.lines {} {}",
+            indent, function.source_line_start, function.source_line_end
+        )?;
+        writeln!(f, "{}.params {}", indent, function.number_of_parameters)?;
+        match function.is_vararg.clone().into_bytes()[0] {
+            0 => {}
+            1 => writeln!(f, "{}.vararg", indent)?,
+            flag => writeln!(f, "{}.vararg {}", indent, flag)?,
+        }
+        writeln!(f, "{}.stack {}", indent, function.maximum_stack_size)?;
+        let debug_info = &function.debug_info;
+        for (index, upvalue) in function.upvalues.iter().enumerate() {
+            let variable = debug_info.upvalue_names.get(index).cloned().flatten();
+            write!(
+                f,
+                "{}.upvalue {} {} {}",
+                indent,
+                display_name(variable.as_deref()),
+                upvalue.in_stack as u8,
+                upvalue.index
+            )?;
+            if upvalue.kind != VariableKind::Regular {
+                write!(f, " {:?}", upvalue.kind)?;
+            }
+            writeln!(f)?;
+        }
+        for constant in &function.constants {
+            writeln!(f, "{}.const {}", indent, display_constant(constant))?;
+        }
+        for local in &debug_info.local_vars {
+            writeln!(
+                f,
+                "{}.local {} {} {}",
+                indent,
+                display_name(local.name.as_deref()),
+                local.start_pc,
+                local.end_pc
+            )?;
+        }
+        let lines: Vec<_> = (0..function.instructions.len())
+            .map(|pc| function.line_at(pc))
+            .collect();
+        // `.line` only when it gives back the line information as stored
+        let (computed_line_info, computed_abs_line_info) =
+            line_info(function.source_line_start, &lines);
+        let lines_computed = debug_info.line_info.len() == function.instructions.len()
+            && computed_line_info == debug_info.line_info
+            && computed_abs_line_info == debug_info.abs_line_info;
+        if !lines_computed {
+            for deltas in debug_info.line_info.chunks(LINE_DELTAS_PER_DIRECTIVE) {
+                let deltas: Vec<_> = deltas.iter().map(i8::to_string).collect();
+                writeln!(f, "{}.lineinfo {}", indent, deltas.join(" "))?;
+            }
+            for abs_line_info in &debug_info.abs_line_info {
+                writeln!(
+                    f,
+                    "{}.abslineinfo {} {}",
+                    indent, abs_line_info.pc, abs_line_info.line
+                )?;
+            }
+        }
+        let targets: BTreeSet<_> = function
+            .instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(pc, instruction)| jump_target(function, pc, *instruction))
+            .collect();
+        let mut line = None;
+        for (pc, instruction) in function.instructions.iter().enumerate() {
+            if lines_computed && lines[pc] != line {
+                line = lines[pc];
+                writeln!(f, "{}.line {}", indent, line.unwrap_or_default())?;
+            }
+            if targets.contains(&pc) {
+                writeln!(f, "{}L{}:", "    ".repeat(*depth), pc)?;
+            }
+            writeln!(
+                f,
+                "{}{}",
+                indent,
+                display_instruction(function, pc, *instruction)
+            )?;
+        }
+        if targets.contains(&function.instructions.len()) {
+            writeln!(
+                f,
+                "{}L{}:",
+                "    ".repeat(*depth),
+                function.instructions.len()
+            )?;
+        }
+        for (index, proto) in function.protos.iter().enumerate() {
+            let nested = FunctionAssembly {
+                name: format!("f{}", index),
+                function: proto,
+                depth: depth + 1,
+            };
+            write!(f, "{}", nested)?;
+        }
+        writeln!(f, "{}.end", "    ".repeat(*depth))
+    }
+}
+
+/// The pc `instruction` jumps to, when a label can stand for it
+fn jump_target(function: &FunctionBlockChunk, pc: usize, instruction: u32) -> Option<usize> {
+    Instruction::parse_u32(instruction)?
+        .jump_target(pc)
+        .filter(|target| *target <= function.instructions.len())
+}
+
+fn display_instruction(function: &FunctionBlockChunk, pc: usize, instruction: u32) -> String {
+    let Some((opcode, encoding)) = InstructionEncoding::decode(instruction) else {
+        return format!(".word {:#010x}", instruction);
+    };
+    // The assembler would add the `Extraarg` that is missing
+    let followed_by_extraarg = function
+        .instructions
+        .get(pc + 1)
+        .and_then(|next| InstructionEncoding::decode(*next))
+        .is_some_and(|(next, _)| next == Opcode::Extraarg);
+    if opcode == Opcode::NewTable && !followed_by_extraarg {
+        return format!(".word {:#010x}", instruction);
+    }
+    let mut operands: Vec<_> = encoding
+        .operands(opcode)
+        .iter()
+        .map(i64::to_string)
+        .collect();
+    if let Some(target) = jump_target(function, pc, instruction) {
+        // The jump is the last operand, sJ or Bx
+        *operands.last_mut().unwrap() = format!("L{}", target);
+    }
+    format!("{} {}", opcode.mnemonic(), operands.join(", "))
+}
+
+/// `name` bare when it reads as an identifier, quoted otherwise, or `-`
+fn display_name(name: Option<&[u8]>) -> String {
+    match name {
+        None => "-".to_string(),
+        Some(name)
+            if name
+                .first()
+                .is_some_and(|byte| byte.is_ascii_alphabetic() || *byte == b'_')
+                && name
+                    .iter()
+                    .all(|byte| byte.is_ascii_alphanumeric() || *byte == b'_') =>
+        {
+            String::from_utf8_lossy(name).into_owned()
+        }
+        Some(name) => quote_bytes(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembler::assemble, binary_chunks::function_block::IsVarargFlag,
+        disassembler::Disassembler, lua_file::LuaFile,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let chunks: [&[u8]; 12] = [
+            include_bytes!("../../tests/all_opcodes.luac"),
+            include_bytes!("../../tests/audit.luac"),
+            include_bytes!("../../tests/binary_strings.luac"),
+            include_bytes!("../../tests/closures.luac"),
+            include_bytes!("../../tests/control_flow.luac"),
+            include_bytes!("../../tests/expressions.luac"),
+            include_bytes!("../../tests/functions.luac"),
+            include_bytes!("../../tests/globals.luac"),
+            include_bytes!("../../tests/goto.luac"),
+            include_bytes!("../../tests/stripped.luac"),
+            include_bytes!("../../tests/tables.luac"),
+            include_bytes!("../../tests/values.luac"),
+        ];
+        for chunk in chunks {
+            let file = LuaFile::parse(chunk).unwrap().1;
+            let assembly = Disassembler::new(file).assembly();
+            let assembled = assemble(&assembly).unwrap_or_else(|error| panic!("{}", error));
+            assert_eq!(assembled.to_bytes(), chunk, "{}", assembly);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_non_utf8_strings() {
+        let chunk = include_bytes!("../../tests/binary_strings.luac");
+        let assembly = Disassembler::new(LuaFile::parse(chunk).unwrap().1).assembly();
+        assert!(assembly.contains("\n    .const \"\\xff\\xfeaded\"\n    .const \"caf\\xe9\"\n"));
+        assert_eq!(assemble(&assembly).unwrap().to_bytes(), chunk);
+    }
+
+    #[test]
+    fn test_round_trip_irregular_chunk() {
+        let mut file = LuaFile::parse(include_bytes!("../../tests/closures.luac"))
+            .unwrap()
+            .1;
+        file.header.format_version = 1;
+        file.number_of_upvalues = 0;
+        let main = &mut file.main_function_block;
+        // An unknown opcode, a `NewTable` alone and lines luac would not save
+        main.instructions.extend([0x7f, 0x13]);
+        main.debug_info.line_info.extend([5, -3]);
+        main.debug_info.line_info[0] = -0x80;
+        main.is_vararg = IsVarargFlag::from_bytes([3]).unwrap();
+        main.debug_info.local_vars[0].name = Some(b"(for state)".to_vec());
+        main.debug_info.local_vars[1].name = None;
+        main.debug_info.local_vars[2].name = Some(b"\xc0\"x\"".to_vec());
+        let chunk = file.to_bytes();
+        let assembly = Disassembler::new(file).assembly();
+        assert!(
+            assembly.starts_with(".header 5.4 1 4 8 8 0\n"),
+            "{}",
+            assembly
+        );
+        assert!(assembly.contains("\n    .word 0x0000007f\n    .word 0x00000013\n"));
+        assert!(assembly.contains("\n    .lineinfo -128 1 "));
+        assert!(assembly.contains("\n    .local \"(for state)\" 2 15\n    .local - 3 15\n"));
+        assert!(assembly.contains("\n    .local \"\\xc0\\\"x\\\"\" "));
+        assert_eq!(assemble(&assembly).unwrap().to_bytes(), chunk);
+    }
+}
@@ -1,9 +1,18 @@
-use crate::lua_file::LuaFile;
+use std::fmt::Display;
+
+use crate::{
+    analysis::decode,
+    binary_chunks::{function_block::FunctionBlockChunk, proto_path::ProtoPath},
+    common_structs::constant::LuaConstant,
+    lua_file::LuaFile,
+};
+
+mod assembly;
 
 pub struct Disassembler {
     lua_file: LuaFile,
 }
-const UNKNOWN_FILE_NAME: &'static str = "Unknown";
+const UNKNOWN_FILE_NAME: &str = "Unknown";
 
 impl Disassembler {
     pub fn new(file: LuaFile) -> Self {
@@ -11,14 +20,12 @@ impl Disassembler {
     }
 
     pub fn display_header(&self) -> String {
-        let unknown_file_name = UNKNOWN_FILE_NAME.to_string();
-
         let file_name = self
             .lua_file
             .main_function_block
             .source_name
-            .as_ref()
-            .unwrap_or(&unknown_file_name);
+            .as_deref()
+            .map_or(UNKNOWN_FILE_NAME.into(), String::from_utf8_lossy);
         format!(
             indoc!(
                 "Lua Compiled File
@@ -37,4 +44,194 @@ impl Disassembler {
             self.lua_file.header.size_of_size_t
         )
     }
+
+    /// Lists every function block, in the manner of `luac -l -l`
+    pub fn display_functions(&self) -> String {
+        self.lua_file
+            .functions()
+            .into_iter()
+            .map(|(path, function)| FunctionListing { path, function }.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The header followed by the listing of every function block
+    pub fn disassemble(&self) -> String {
+        format!("{}\n\n{}", self.display_header(), self.display_functions())
+    }
+
+    /// The chunk as source for `assembler::assemble`, which gives back the
+    /// same bytes
+    pub fn assembly(&self) -> String {
+        assembly::assembly(&self.lua_file)
+    }
+}
+
+pub(crate) fn display_constant(constant: &LuaConstant) -> String {
+    match constant {
+        LuaConstant::Nil => "nil".to_string(),
+        LuaConstant::Boolean(value) => value.to_string(),
+        LuaConstant::Number(value) => format!("{:?}", value),
+        LuaConstant::Integer(value) => value.to_string(),
+        LuaConstant::String(value) => quote_bytes(value),
+    }
+}
+
+/// `bytes` quoted as `Debug` quotes strings, with the bytes that are not
+/// UTF-8 escaped as `\xNN`
+pub(crate) fn quote_bytes(bytes: &[u8]) -> String {
+    let mut quoted = "\"".to_string();
+    for chunk in bytes.utf8_chunks() {
+        let valid = format!("{:?}", chunk.valid());
+        quoted.push_str(&valid[1..valid.len() - 1]);
+        for byte in chunk.invalid() {
+            quoted.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The listing of a function block, without its nested function blocks
+struct FunctionListing<'a> {
+    path: ProtoPath,
+    function: &'a FunctionBlockChunk,
+}
+
+impl Display for FunctionListing<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let FunctionListing { path, function } = self;
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        writeln!(
+            f,
+            "function {} <{}:{},{}> ({} instruction{})",
+            path,
+            function
+                .source_name
+                .as_deref()
+                .map_or("?".into(), String::from_utf8_lossy),
+            function.source_line_start,
+            function.source_line_end,
+            function.instructions.len(),
+            plural(function.instructions.len())
+        )?;
+        writeln!(
+            f,
+            "{} param{}, {}{} slot{}, {} upvalue{}, {} constant{}, {} function{}",
+            function.number_of_parameters,
+            plural(function.number_of_parameters as usize),
+            if function.is_vararg.has_arg() != 0 {
+                "vararg, "
+            } else {
+                ""
+            },
+            function.maximum_stack_size,
+            plural(function.maximum_stack_size as usize),
+            function.upvalues.len(),
+            plural(function.upvalues.len()),
+            function.constants.len(),
+            plural(function.constants.len()),
+            function.protos.len(),
+            plural(function.protos.len())
+        )?;
+        for (pc, instruction) in decode(function) {
+            let line = function
+                .line_at(pc)
+                .map_or_else(|| "-".to_string(), |line| line.to_string());
+            write!(f, "\t{}\t[{}]\t", pc, line)?;
+            let Some(instruction) = instruction else {
+                writeln!(f, "{:#010x}", function.instructions[pc])?;
+                continue;
+            };
+            write!(f, "{:?}", instruction)?;
+            // The constants read and the pc jumped to
+            let mut comments: Vec<_> = instruction
+                .constant_indices()
+                .iter()
+                .map(|index| {
+                    function
+                        .constants
+                        .get(*index as usize)
+                        .map_or_else(|| "?".to_string(), display_constant)
+                })
+                .collect();
+            if let Some(target) = instruction.jump_target(pc) {
+                comments.push(format!("to {}", target));
+            }
+            if !comments.is_empty() {
+                write!(f, "\t; {}", comments.join(" "))?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "constants ({}) for {}:", function.constants.len(), path)?;
+        for (index, constant) in function.constants.iter().enumerate() {
+            writeln!(f, "\t{}\t{}", index, display_constant(constant))?;
+        }
+        let locals = &function.debug_info.local_vars;
+        writeln!(f, "locals ({}) for {}:", locals.len(), path)?;
+        for (index, local) in locals.iter().enumerate() {
+            writeln!(
+                f,
+                "\t{}\t{}\t{}\t{}",
+                index,
+                local
+                    .name
+                    .as_deref()
+                    .map_or("?".into(), String::from_utf8_lossy),
+                local.start_pc,
+                local.end_pc
+            )?;
+        }
+        writeln!(f, "upvalues ({}) for {}:", function.upvalues.len(), path)?;
+        for (index, upvalue) in function.upvalues.iter().enumerate() {
+            let name = function
+                .debug_info
+                .upvalue_names
+                .get(index)
+                .and_then(|name| name.as_deref())
+                .map_or("-".into(), String::from_utf8_lossy);
+            writeln!(
+                f,
+                "\t{}\t{}\t{}\t{}",
+                index, name, upvalue.in_stack as u8, upvalue.index
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lua_file::LuaFile;
+
+    use super::Disassembler;
+
+    #[test]
+    fn test_display_functions() {
+        let file = LuaFile::parse(include_bytes!("../../tests/closures.luac"))
+            .unwrap()
+            .1;
+        let listing = Disassembler::new(file).display_functions();
+        let increment = indoc!(
+            "
+            function main/0 <?:4,7> (7 instructions)
+            1 param, 2 slots, 1 upvalue, 0 constants, 0 functions
+            \t0\t[5]\tGetUpval(1, 0)
+            \t1\t[5]\tAdd(1, 1, 0)
+            \t2\t[5]\tMmBin(1, 0, 6)
+            \t3\t[5]\tSetUpval(1, 0)
+            \t4\t[6]\tGetUpval(1, 0)
+            \t5\t[6]\tReturn1(1)
+            \t6\t[7]\tReturn0
+            constants (0) for main/0:
+            locals (1) for main/0:
+            \t0\tstep\t0\t7
+            upvalues (1) for main/0:
+            \t0\tcounter\t1\t0
+            "
+        );
+        assert!(listing.starts_with("function main <@closures.lua:0,0> (15 instructions)\n"));
+        assert!(listing.contains("\t5\t[14]\tSetTabup(0, 0, 3, 0)\t; \"report\"\n"));
+        assert!(listing.contains(increment), "{}", listing);
+    }
 }
@@ -3,7 +3,7 @@ use nom::{error::ErrorKind, IResult, Needed};
 use super::{instruction_encodings::InstructionEncoding, opcodes::Opcode};
 
 // All VM instructions
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Instruction {
     Move(u8, u8),             /* A B     R[A] := R[B]                                    */
     LoadI(u8, i32),           /* A sBx   R[A] := sBx                                     */
@@ -20,12 +20,12 @@ pub enum Instruction {
     GetTable(u8, u8, u8),     /* A B C   R[A] := R[B][R[C]]                              */
     GetI(u8, u8, u8),         /* A B C   R[A] := R[B][C]                                 */
     GetField(u8, u8, u8),     /* A B C   R[A] := R[B][K[C]:string]                       */
-    SetTabup(u8, u8, u8),     /* A B C   UpValue[A][K[B]:string] := RK(C)                */
-    SetTable(u8, u8, u8),     /* A B C   R[A][R[B]] := RK(C)                             */
-    SetI(u8, u8, u8),         /* A B C   R[A][B] := RK(C)                                */
-    SetField(u8, u8, u8),     /* A B C   R[A][K[B]:string] := RK(C)                      */
+    SetTabup(u8, u8, u8, u8), /* A B C k UpValue[A][K[B]:string] := RK(C)                */
+    SetTable(u8, u8, u8, u8), /* A B C k R[A][R[B]] := RK(C)                             */
+    SetI(u8, u8, u8, u8),     /* A B C k R[A][B] := RK(C)                                */
+    SetField(u8, u8, u8, u8), /* A B C k R[A][K[B]:string] := RK(C)                      */
     NewTable(u8, u8, u8, u8), /* A B C k R[A] := {}                                      */
-    Self_(u8, u8, u8),        /* A B C   R[A+1] := R[B]; R[A] := R[B][RK(C):string]      */
+    Self_(u8, u8, u8, u8),    /* A B C k R[A+1] := R[B]; R[A] := R[B][RK(C):string]      */
     AddI(u8, u8, i8),         /* A B sC  R[A] := R[B] + sC                               */
     AddK(u8, u8, u8),         /* A B C   R[A] := R[B] + K[C]:number                      */
     SubK(u8, u8, u8),         /* A B C   R[A] := R[B] - K[C]:number                      */
@@ -241,23 +241,23 @@ impl Instruction {
             Some(Opcode::GetField) => handle_iabc(input, |next_input, c, b, _, a| {
                 Ok((next_input, Self::GetField(a, b, c)))
             }),
-            Some(Opcode::SetTabup) => handle_iabc(input, |next_input, c, b, _, a| {
-                Ok((next_input, Self::SetTabup(a, b, c)))
+            Some(Opcode::SetTabup) => handle_iabc(input, |next_input, c, b, k, a| {
+                Ok((next_input, Self::SetTabup(a, b, c, k)))
             }),
-            Some(Opcode::SetTable) => handle_iabc(input, |next_input, c, b, _, a| {
-                Ok((next_input, Self::SetTable(a, b, c)))
+            Some(Opcode::SetTable) => handle_iabc(input, |next_input, c, b, k, a| {
+                Ok((next_input, Self::SetTable(a, b, c, k)))
             }),
-            Some(Opcode::SetI) => handle_iabc(input, |next_input, c, b, _, a| {
-                Ok((next_input, Self::SetI(a, b, c)))
+            Some(Opcode::SetI) => handle_iabc(input, |next_input, c, b, k, a| {
+                Ok((next_input, Self::SetI(a, b, c, k)))
             }),
-            Some(Opcode::SetField) => handle_iabc(input, |next_input, c, b, _, a| {
-                Ok((next_input, Self::SetField(a, b, c)))
+            Some(Opcode::SetField) => handle_iabc(input, |next_input, c, b, k, a| {
+                Ok((next_input, Self::SetField(a, b, c, k)))
             }),
             Some(Opcode::NewTable) => handle_iabc(input, |next_input, c, b, k, a| {
                 Ok((next_input, Self::NewTable(a, b, c, k)))
             }),
-            Some(Opcode::Self_) => handle_iabc(input, |next_input, c, b, _, a| {
-                Ok((next_input, Self::Self_(a, b, c)))
+            Some(Opcode::Self_) => handle_iabc(input, |next_input, c, b, k, a| {
+                Ok((next_input, Self::Self_(a, b, c, k)))
             }),
             Some(Opcode::AddI) => handle_iabc(input, |next_input, c, b, _, a| {
                 Ok((next_input, Self::AddI(a, b, make_signed(c))))
@@ -451,6 +451,334 @@ impl Instruction {
             })),
         }
     }
+
+    /// Registers written by the instruction, as the first register and the
+    /// number of registers written. A count of `None` means every register up
+    /// to the top of the stack.
+    pub fn written_registers(&self) -> Option<(u8, Option<u8>)> {
+        match *self {
+            Self::Move(a, _)
+            | Self::LoadI(a, _)
+            | Self::LoadF(a, _)
+            | Self::LoadK(a, _)
+            | Self::LoadKx(a)
+            | Self::LoadFalse(a)
+            | Self::LFalseSkip(a)
+            | Self::LoadTrue(a)
+            | Self::GetUpval(a, _)
+            | Self::GetTabup(a, _, _)
+            | Self::GetTable(a, _, _)
+            | Self::GetI(a, _, _)
+            | Self::GetField(a, _, _)
+            | Self::NewTable(a, _, _, _)
+            | Self::AddI(a, _, _)
+            | Self::AddK(a, _, _)
+            | Self::SubK(a, _, _)
+            | Self::MulK(a, _, _)
+            | Self::ModK(a, _, _)
+            | Self::PowK(a, _, _)
+            | Self::DivK(a, _, _)
+            | Self::IDivK(a, _, _)
+            | Self::BAndK(a, _, _)
+            | Self::BOrK(a, _, _)
+            | Self::BXorK(a, _, _)
+            | Self::ShrI(a, _, _)
+            | Self::ShlI(a, _, _)
+            | Self::Add(a, _, _)
+            | Self::Sub(a, _, _)
+            | Self::Mul(a, _, _)
+            | Self::Mod(a, _, _)
+            | Self::Pow(a, _, _)
+            | Self::Div(a, _, _)
+            | Self::IDiv(a, _, _)
+            | Self::BAnd(a, _, _)
+            | Self::BOr(a, _, _)
+            | Self::BXor(a, _, _)
+            | Self::Shl(a, _, _)
+            | Self::Shr(a, _, _)
+            | Self::Unm(a, _)
+            | Self::BNot(a, _)
+            | Self::Not(a, _)
+            | Self::Len(a, _)
+            | Self::Concat(a, _)
+            | Self::TestSet(a, _, _)
+            | Self::Closure(a, _) => Some((a, Some(1))),
+            Self::LoadNil(a, b) => Some((a, Some(b + 1))),
+            Self::Self_(a, _, _, _) => Some((a, Some(2))),
+            Self::Call(_, _, 1) | Self::Vararg(_, 1) => None,
+            Self::Call(a, _, 0) | Self::Vararg(a, 0) => Some((a, None)),
+            Self::Call(a, _, c) | Self::Vararg(a, c) => Some((a, Some(c - 1))),
+            Self::ForPrep(a, _) | Self::ForLoop(a, _) => Some((a, Some(4))),
+            Self::TForCall(a, c) => Some((a + 4, Some(c))),
+            Self::TForLoop(a, _) => Some((a + 2, Some(1))),
+            _ => None,
+        }
+    }
+
+    /// Registers read by the instruction, as ranges of a first register and
+    /// the number of registers read, with `None` meaning up to the top of the
+    /// stack. Metamethod fallbacks read the operands of the instruction before
+    /// them and are left out.
+    pub fn read_registers(&self) -> Vec<(u8, Option<u8>)> {
+        let one = |register: u8| (register, Some(1));
+        let rk = |register: u8, k: u8| if k == 0 { vec![one(register)] } else { vec![] };
+        // `b - 1` registers, or up to the top when `b` is 0
+        let list = |first: u8, b: u8| {
+            if b == 0 {
+                (first, None)
+            } else {
+                (first, Some(b - 1))
+            }
+        };
+        match *self {
+            Self::Move(_, b)
+            | Self::GetI(_, b, _)
+            | Self::GetField(_, b, _)
+            | Self::AddI(_, b, _)
+            | Self::AddK(_, b, _)
+            | Self::SubK(_, b, _)
+            | Self::MulK(_, b, _)
+            | Self::ModK(_, b, _)
+            | Self::PowK(_, b, _)
+            | Self::DivK(_, b, _)
+            | Self::IDivK(_, b, _)
+            | Self::BAndK(_, b, _)
+            | Self::BOrK(_, b, _)
+            | Self::BXorK(_, b, _)
+            | Self::ShrI(_, b, _)
+            | Self::ShlI(_, b, _)
+            | Self::Unm(_, b)
+            | Self::BNot(_, b)
+            | Self::Not(_, b)
+            | Self::Len(_, b)
+            | Self::TestSet(_, b, _) => vec![one(b)],
+            Self::GetTable(_, b, c)
+            | Self::Add(_, b, c)
+            | Self::Sub(_, b, c)
+            | Self::Mul(_, b, c)
+            | Self::Mod(_, b, c)
+            | Self::Pow(_, b, c)
+            | Self::Div(_, b, c)
+            | Self::IDiv(_, b, c)
+            | Self::BAnd(_, b, c)
+            | Self::BOr(_, b, c)
+            | Self::BXor(_, b, c)
+            | Self::Shl(_, b, c)
+            | Self::Shr(_, b, c) => vec![one(b), one(c)],
+            Self::Eq(a, b, _) | Self::Lt(a, b, _) | Self::Le(a, b, _) => vec![one(a), one(b)],
+            Self::SetUpval(a, _)
+            | Self::EqK(a, _, _)
+            | Self::EqI(a, _, _)
+            | Self::LtI(a, _, _)
+            | Self::LeI(a, _, _)
+            | Self::GtI(a, _, _)
+            | Self::GeI(a, _, _)
+            | Self::Test(a, _)
+            | Self::Return1(a)
+            | Self::Tbc(a) => vec![one(a)],
+            Self::SetTabup(_, _, c, k) => rk(c, k),
+            Self::SetTable(a, b, c, k) => [vec![one(a), one(b)], rk(c, k)].concat(),
+            Self::SetI(a, _, c, k) | Self::SetField(a, _, c, k) => {
+                [vec![one(a)], rk(c, k)].concat()
+            }
+            Self::Self_(_, b, c, k) => [vec![one(b)], rk(c, k)].concat(),
+            Self::Concat(a, b) => vec![(a, Some(b))],
+            Self::Call(a, b, _) | Self::TailCall(a, b, _, _) => vec![one(a), list(a + 1, b)],
+            Self::Return(a, b, _, _) => vec![list(a, b)],
+            Self::SetList(a, 0, _, _) => vec![one(a), (a + 1, None)],
+            Self::SetList(a, b, _, _) => vec![one(a), (a + 1, Some(b))],
+            Self::ForPrep(a, _) | Self::ForLoop(a, _) => vec![(a, Some(3))],
+            Self::TForCall(a, _) => vec![(a, Some(3))],
+            Self::TForLoop(a, _) => vec![one(a + 4)],
+            _ => vec![],
+        }
+    }
+
+    /// Target of the jump performed by the instruction at `pc`, if it jumps
+    pub fn jump_target(&self, pc: usize) -> Option<usize> {
+        match *self {
+            Self::Jmp(sj) => usize::try_from(pc as i64 + 1 + sj as i64).ok(),
+            Self::ForLoop(_, bx) | Self::TForLoop(_, bx) => (pc + 1).checked_sub(bx as usize),
+            Self::ForPrep(_, bx) => Some(pc + bx as usize + 2),
+            Self::TForPrep(_, bx) => Some(pc + bx as usize + 1),
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction may skip the instruction following it
+    pub fn is_test(&self) -> bool {
+        matches!(
+            self,
+            Self::Eq(..)
+                | Self::Lt(..)
+                | Self::Le(..)
+                | Self::EqK(..)
+                | Self::EqI(..)
+                | Self::LtI(..)
+                | Self::LeI(..)
+                | Self::GtI(..)
+                | Self::GeI(..)
+                | Self::Test(..)
+                | Self::TestSet(..)
+        )
+    }
+
+    /// The instruction with each register operand passed through `register`
+    /// and each constant index through `constant`. Counts, immediates, jump
+    /// offsets and upvalue and prototype indices are kept. The constant of
+    /// `LoadKx` is the operand of the following `Extraarg`, which is kept.
+    pub fn rewrite_operands(
+        &self,
+        mut register: impl FnMut(u8) -> u8,
+        mut constant: impl FnMut(u32) -> u32,
+    ) -> Instruction {
+        // Constants in 8 bit operands, and operands that are a constant if `k` is set
+        macro_rules! k {
+            ($index:expr) => {
+                constant($index as u32) as u8
+            };
+        }
+        macro_rules! rk {
+            ($operand:expr, $k:expr) => {
+                if $k == 0 {
+                    register($operand)
+                } else {
+                    k!($operand)
+                }
+            };
+        }
+        match *self {
+            Self::Move(a, b) => Self::Move(register(a), register(b)),
+            Self::LoadI(a, sbx) => Self::LoadI(register(a), sbx),
+            Self::LoadF(a, sbx) => Self::LoadF(register(a), sbx),
+            Self::LoadK(a, bx) => Self::LoadK(register(a), constant(bx)),
+            Self::LoadKx(a) => Self::LoadKx(register(a)),
+            Self::LoadFalse(a) => Self::LoadFalse(register(a)),
+            Self::LFalseSkip(a) => Self::LFalseSkip(register(a)),
+            Self::LoadTrue(a) => Self::LoadTrue(register(a)),
+            Self::LoadNil(a, b) => Self::LoadNil(register(a), b),
+            Self::GetUpval(a, b) => Self::GetUpval(register(a), b),
+            Self::SetUpval(a, b) => Self::SetUpval(register(a), b),
+            Self::GetTabup(a, b, c) => Self::GetTabup(register(a), b, k!(c)),
+            Self::GetTable(a, b, c) => Self::GetTable(register(a), register(b), register(c)),
+            Self::GetI(a, b, c) => Self::GetI(register(a), register(b), c),
+            Self::GetField(a, b, c) => Self::GetField(register(a), register(b), k!(c)),
+            Self::SetTabup(a, b, c, k) => {
+                let b = k!(b);
+                Self::SetTabup(a, b, rk!(c, k), k)
+            }
+            Self::SetTable(a, b, c, k) => {
+                let (a, b) = (register(a), register(b));
+                Self::SetTable(a, b, rk!(c, k), k)
+            }
+            Self::SetI(a, b, c, k) => {
+                let a = register(a);
+                Self::SetI(a, b, rk!(c, k), k)
+            }
+            Self::SetField(a, b, c, k) => {
+                let (a, b) = (register(a), k!(b));
+                Self::SetField(a, b, rk!(c, k), k)
+            }
+            Self::NewTable(a, b, c, k) => Self::NewTable(register(a), b, c, k),
+            Self::Self_(a, b, c, k) => {
+                let (a, b) = (register(a), register(b));
+                Self::Self_(a, b, rk!(c, k), k)
+            }
+            Self::AddI(a, b, sc) => Self::AddI(register(a), register(b), sc),
+            Self::AddK(a, b, c) => Self::AddK(register(a), register(b), k!(c)),
+            Self::SubK(a, b, c) => Self::SubK(register(a), register(b), k!(c)),
+            Self::MulK(a, b, c) => Self::MulK(register(a), register(b), k!(c)),
+            Self::ModK(a, b, c) => Self::ModK(register(a), register(b), k!(c)),
+            Self::PowK(a, b, c) => Self::PowK(register(a), register(b), k!(c)),
+            Self::DivK(a, b, c) => Self::DivK(register(a), register(b), k!(c)),
+            Self::IDivK(a, b, c) => Self::IDivK(register(a), register(b), k!(c)),
+            Self::BAndK(a, b, c) => Self::BAndK(register(a), register(b), k!(c)),
+            Self::BOrK(a, b, c) => Self::BOrK(register(a), register(b), k!(c)),
+            Self::BXorK(a, b, c) => Self::BXorK(register(a), register(b), k!(c)),
+            Self::ShrI(a, b, sc) => Self::ShrI(register(a), register(b), sc),
+            Self::ShlI(a, b, sc) => Self::ShlI(register(a), register(b), sc),
+            Self::Add(a, b, c) => Self::Add(register(a), register(b), register(c)),
+            Self::Sub(a, b, c) => Self::Sub(register(a), register(b), register(c)),
+            Self::Mul(a, b, c) => Self::Mul(register(a), register(b), register(c)),
+            Self::Mod(a, b, c) => Self::Mod(register(a), register(b), register(c)),
+            Self::Pow(a, b, c) => Self::Pow(register(a), register(b), register(c)),
+            Self::Div(a, b, c) => Self::Div(register(a), register(b), register(c)),
+            Self::IDiv(a, b, c) => Self::IDiv(register(a), register(b), register(c)),
+            Self::BAnd(a, b, c) => Self::BAnd(register(a), register(b), register(c)),
+            Self::BOr(a, b, c) => Self::BOr(register(a), register(b), register(c)),
+            Self::BXor(a, b, c) => Self::BXor(register(a), register(b), register(c)),
+            Self::Shl(a, b, c) => Self::Shl(register(a), register(b), register(c)),
+            Self::Shr(a, b, c) => Self::Shr(register(a), register(b), register(c)),
+            Self::MmBin(a, b, c) => Self::MmBin(register(a), register(b), c),
+            Self::MmBinI(a, sb, c, k) => Self::MmBinI(register(a), sb, c, k),
+            Self::MmBinK(a, b, c, k) => Self::MmBinK(register(a), k!(b), c, k),
+            Self::Unm(a, b) => Self::Unm(register(a), register(b)),
+            Self::BNot(a, b) => Self::BNot(register(a), register(b)),
+            Self::Not(a, b) => Self::Not(register(a), register(b)),
+            Self::Len(a, b) => Self::Len(register(a), register(b)),
+            Self::Concat(a, b) => Self::Concat(register(a), b),
+            Self::Close(a) => Self::Close(register(a)),
+            Self::Tbc(a) => Self::Tbc(register(a)),
+            Self::Jmp(sj) => Self::Jmp(sj),
+            Self::Eq(a, b, k) => Self::Eq(register(a), register(b), k),
+            Self::Lt(a, b, k) => Self::Lt(register(a), register(b), k),
+            Self::Le(a, b, k) => Self::Le(register(a), register(b), k),
+            Self::EqK(a, b, k) => Self::EqK(register(a), k!(b), k),
+            Self::EqI(a, sb, k) => Self::EqI(register(a), sb, k),
+            Self::LtI(a, sb, k) => Self::LtI(register(a), sb, k),
+            Self::LeI(a, sb, k) => Self::LeI(register(a), sb, k),
+            Self::GtI(a, sb, k) => Self::GtI(register(a), sb, k),
+            Self::GeI(a, sb, k) => Self::GeI(register(a), sb, k),
+            Self::Test(a, k) => Self::Test(register(a), k),
+            Self::TestSet(a, b, k) => Self::TestSet(register(a), register(b), k),
+            Self::Call(a, b, c) => Self::Call(register(a), b, c),
+            Self::TailCall(a, b, c, k) => Self::TailCall(register(a), b, c, k),
+            Self::Return(a, b, c, k) => Self::Return(register(a), b, c, k),
+            Self::Return0() => Self::Return0(),
+            Self::Return1(a) => Self::Return1(register(a)),
+            Self::ForLoop(a, bx) => Self::ForLoop(register(a), bx),
+            Self::ForPrep(a, bx) => Self::ForPrep(register(a), bx),
+            Self::TForPrep(a, bx) => Self::TForPrep(register(a), bx),
+            Self::TForCall(a, c) => Self::TForCall(register(a), c),
+            Self::TForLoop(a, bx) => Self::TForLoop(register(a), bx),
+            Self::SetList(a, b, c, k) => Self::SetList(register(a), b, c, k),
+            Self::Closure(a, bx) => Self::Closure(register(a), bx),
+            Self::Vararg(a, c) => Self::Vararg(register(a), c),
+            Self::VarargPrep(a) => Self::VarargPrep(a),
+            Self::Extraarg(ax) => Self::Extraarg(ax),
+        }
+    }
+
+    /// Indices of the constants the instruction reads. The constant of
+    /// `LoadKx` is given by the following `Extraarg` instead.
+    pub fn constant_indices(&self) -> Vec<u32> {
+        match *self {
+            Self::LoadK(_, bx) => vec![bx],
+            Self::GetTabup(_, _, c) | Self::GetField(_, _, c) => vec![c as u32],
+            Self::SetTabup(_, b, c, k) | Self::SetField(_, b, c, k) => {
+                if k != 0 {
+                    vec![b as u32, c as u32]
+                } else {
+                    vec![b as u32]
+                }
+            }
+            Self::SetTable(_, _, c, 1) | Self::SetI(_, _, c, 1) | Self::Self_(_, _, c, 1) => {
+                vec![c as u32]
+            }
+            Self::AddK(_, _, c)
+            | Self::SubK(_, _, c)
+            | Self::MulK(_, _, c)
+            | Self::ModK(_, _, c)
+            | Self::PowK(_, _, c)
+            | Self::DivK(_, _, c)
+            | Self::IDivK(_, _, c)
+            | Self::BAndK(_, _, c)
+            | Self::BOrK(_, _, c)
+            | Self::BXorK(_, _, c) => vec![c as u32],
+            Self::MmBinK(_, b, _, _) | Self::EqK(_, b, _) => vec![b as u32],
+            _ => vec![],
+        }
+    }
 }
 
 #[cfg(test)]
@@ -496,7 +824,7 @@ mod tests {
             Instruction::LoadK(13, 2),
             Instruction::Call(4, 10, 1),
             Instruction::Closure(4, 2),
-            Instruction::SetTabup(0, 3, 4),
+            Instruction::SetTabup(0, 3, 4, 0),
             Instruction::Return(4, 1, 1, 0),
         ];
 
@@ -506,4 +834,21 @@ mod tests {
         }
         assert_eq!(parsed_instructions, instructions);
     }
+
+    #[test]
+    fn test_rewrite_operands() {
+        let register = |register: u8| register + 10;
+        let constant = |index: u32| index + 100;
+        let cases = [
+            (Instruction::SetField(1, 2, 3, 0), Instruction::SetField(11, 102, 13, 0)),
+            (Instruction::SetField(1, 2, 3, 1), Instruction::SetField(11, 102, 103, 1)),
+            (Instruction::SetTabup(0, 2, 3, 0), Instruction::SetTabup(0, 102, 13, 0)),
+            (Instruction::LoadK(1, 300), Instruction::LoadK(11, 400)),
+            (Instruction::Call(1, 3, 2), Instruction::Call(11, 3, 2)),
+            (Instruction::Jmp(-4), Instruction::Jmp(-4)),
+        ];
+        for (instruction, rewritten) in cases {
+            assert_eq!(instruction.rewrite_operands(register, constant), rewritten);
+        }
+    }
 }
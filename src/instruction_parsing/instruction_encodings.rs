@@ -18,7 +18,17 @@ isJ                           sJ(25)                     |   Op(7)     |
 
 use nom::{combinator::map, number::complete::le_u32, IResult};
 
+use super::opcodes::{OpMode, Opcode};
+
+/// Excess of the signed sB and sC operands
+const OFFSET_SC: i64 = 127;
+/// Excess of the signed sBx operand
+const OFFSET_SBX: i64 = 0xffff;
+/// Excess of the signed sJ operand
+const OFFSET_SJ: i64 = 0xffffff;
+
 // Instruction encoding
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum InstructionEncoding {
     IABC {
         c: u8,
@@ -92,6 +102,125 @@ impl InstructionEncoding {
             opcode: (instruction_encoded & 0x7f) as u8,
         })(input)
     }
+
+    /// Decodes `instruction` with the format of its opcode, keeping every bit.
+    /// Returns `None` for unknown opcodes.
+    pub fn decode(instruction: u32) -> Option<(Opcode, Self)> {
+        let opcode: Opcode = num::FromPrimitive::from_u8((instruction & 0x7f) as u8)?;
+        let bytes = instruction.to_le_bytes();
+        let parser = match opcode.mode() {
+            OpMode::IABC => Self::parse_iabc,
+            OpMode::IABx => Self::parse_iabx,
+            OpMode::IAsBx => Self::parse_iasbx,
+            OpMode::IAx => Self::parse_iax,
+            OpMode::IsJ => Self::parse_isj,
+        };
+        let (_, encoding) = parser(&bytes).ok()?;
+        Some((opcode, encoding))
+    }
+
+    /// The 32-bit instruction
+    pub fn encode(&self) -> u32 {
+        match *self {
+            InstructionEncoding::IABC { c, b, k, a, opcode } => {
+                (c as u32) << 24
+                    | (b as u32) << 16
+                    | (k as u32 & 0x1) << 15
+                    | (a as u32) << 7
+                    | (opcode as u32 & 0x7f)
+            }
+            InstructionEncoding::IABx { bx, a, opcode } => {
+                bx << 15 | (a as u32) << 7 | (opcode as u32 & 0x7f)
+            }
+            InstructionEncoding::IAsBx { sbx, a, opcode } => {
+                ((sbx as i64 + OFFSET_SBX) as u32) << 15 | (a as u32) << 7 | (opcode as u32 & 0x7f)
+            }
+            InstructionEncoding::IAx { ax, opcode } => ax << 7 | (opcode as u32 & 0x7f),
+            InstructionEncoding::IsJ { sj, opcode } => {
+                ((sj as i64 + OFFSET_SJ) as u32) << 7 | (opcode as u32 & 0x7f)
+            }
+        }
+    }
+
+    /// The operands of an instruction of `opcode`, in the order of
+    /// `Opcode::operand_names`, with sB and sC as signed values
+    pub fn operands(&self, opcode: Opcode) -> Vec<i64> {
+        match *self {
+            InstructionEncoding::IABC { c, b, k, a, .. } => {
+                let signed = |operand: u8, is_signed: bool| {
+                    operand as i64 - if is_signed { OFFSET_SC } else { 0 }
+                };
+                vec![
+                    a as i64,
+                    signed(b, opcode.has_signed_b()),
+                    signed(c, opcode.has_signed_c()),
+                    k as i64,
+                ]
+            }
+            InstructionEncoding::IABx { bx, a, .. } => vec![a as i64, bx as i64],
+            InstructionEncoding::IAsBx { sbx, a, .. } => vec![a as i64, sbx as i64],
+            InstructionEncoding::IAx { ax, .. } => vec![ax as i64],
+            InstructionEncoding::IsJ { sj, .. } => vec![sj as i64],
+        }
+    }
+
+    /// The inverse of `operands`, failing when an operand does not fit
+    pub fn from_operands(opcode: Opcode, operands: &[i64]) -> Result<Self, String> {
+        let names = opcode.operand_names();
+        if operands.len() != names.len() {
+            return Err(format!(
+                "{} takes {} operands, found {}",
+                opcode.mnemonic(),
+                names.len(),
+                operands.len()
+            ));
+        }
+        let operand = |index: usize, min: i64, max: i64| {
+            let value = operands[index];
+            if (min..=max).contains(&value) {
+                Ok(value)
+            } else {
+                Err(format!(
+                    "{} of {} is out of range: {}",
+                    names[index],
+                    opcode.mnemonic(),
+                    value
+                ))
+            }
+        };
+        let byte = |index: usize, is_signed: bool| {
+            let offset = if is_signed { OFFSET_SC } else { 0 };
+            operand(index, -offset, 0xff - offset).map(|value| (value + offset) as u8)
+        };
+        let opcode_byte = opcode as u8;
+        Ok(match opcode.mode() {
+            OpMode::IABC => InstructionEncoding::IABC {
+                a: byte(0, false)?,
+                b: byte(1, opcode.has_signed_b())?,
+                c: byte(2, opcode.has_signed_c())?,
+                k: operand(3, 0, 1)? as u8,
+                opcode: opcode_byte,
+            },
+            OpMode::IABx => InstructionEncoding::IABx {
+                a: byte(0, false)?,
+                bx: operand(1, 0, 0x1ffff)? as u32,
+                opcode: opcode_byte,
+            },
+            OpMode::IAsBx => InstructionEncoding::IAsBx {
+                a: byte(0, false)?,
+                sbx: operand(1, -OFFSET_SBX, 0x1ffff - OFFSET_SBX)? as i32,
+                opcode: opcode_byte,
+            },
+            OpMode::IAx => InstructionEncoding::IAx {
+                ax: operand(0, 0, 0x1ffffff)? as u32,
+                opcode: opcode_byte,
+            },
+            OpMode::IsJ => InstructionEncoding::IsJ {
+                sj: operand(0, -OFFSET_SJ, 0x1ffffff - OFFSET_SJ)? as i32,
+                opcode: opcode_byte,
+            },
+        })
+    }
 }
 
 #[cfg(test)]
@@ -112,10 +241,7 @@ mod tests {
             assert_eq!(c, 2);
             assert_eq!(k, 1);
         } else {
-            assert!(
-                false,
-                "parse_iabc should not return an Ok value with another encoding"
-            )
+            panic!("parse_iabc should not return an Ok value with another encoding")
         }
     }
 
@@ -129,10 +255,7 @@ mod tests {
             assert_eq!(a, 0xe);
             assert_eq!(bx, 0);
         } else {
-            assert!(
-                false,
-                "parse_iabx should not return an Ok value with another encoding"
-            )
+            panic!("parse_iabx should not return an Ok value with another encoding")
         }
     }
 
@@ -146,10 +269,7 @@ mod tests {
             assert_eq!(a, 0);
             assert_eq!(sbx, 0);
         } else {
-            assert!(
-                false,
-                "parse_iasbx should not return an Ok value with another encoding"
-            )
+            panic!("parse_iasbx should not return an Ok value with another encoding")
         }
     }
 
@@ -162,11 +282,31 @@ mod tests {
             assert_eq!(opcode, Opcode::Extraarg as u8);
             assert_eq!(ax, 0);
         } else {
-            assert!(
-                false,
-                "parse_iax should not return an Ok value with another encoding"
-            )
+            panic!("parse_iax should not return an Ok value with another encoding")
+        }
+    }
+
+    #[test]
+    fn test_decode_encode() {
+        // EqI with a negative sB and the isfloat flag in C
+        let instruction = 0x0101_00bd;
+        let (opcode, encoding) = InstructionEncoding::decode(instruction).unwrap();
+        assert_eq!(opcode, Opcode::EqI);
+        assert_eq!(encoding.operands(opcode), vec![1, -126, 1, 0]);
+        assert_eq!(
+            InstructionEncoding::from_operands(opcode, &[1, -126, 1, 0]),
+            Ok(encoding)
+        );
+        assert_eq!(encoding.encode(), instruction);
+        for instruction in [0x8000_0038, 0x7fff_8001, 0x0000_0052, 0x0000_074a] {
+            let (_, encoding) = InstructionEncoding::decode(instruction).unwrap();
+            assert_eq!(encoding.encode(), instruction);
         }
+        assert_eq!(
+            InstructionEncoding::from_operands(Opcode::LoadI, &[0, 70000]),
+            Err("sbx of LoadI is out of range: 70000".to_string())
+        );
+        assert_eq!(InstructionEncoding::decode(0x7f), None);
     }
 
     #[test]
@@ -178,10 +318,7 @@ mod tests {
             assert_eq!(opcode, Opcode::Jmp as u8);
             assert_eq!(sj, 1);
         } else {
-            assert!(
-                false,
-                "parse_isj should not return an Ok value with another encoding"
-            )
+            panic!("parse_isj should not return an Ok value with another encoding")
         }
     }
 }
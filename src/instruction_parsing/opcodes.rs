@@ -1,4 +1,4 @@
-#[derive(FromPrimitive, PartialEq, Debug)]
+#[derive(FromPrimitive, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy, Debug)]
 // Opcodes in Lua VM
 pub enum Opcode {
     Move,       /* A B     R[A] := R[B]                                    */
@@ -86,3 +86,71 @@ pub enum Opcode {
     VarargPrep, /* A       (adjust vararg parameters)                      */
     Extraarg,   /* Ax      extra (larger) argument for previous opcode     */
 }
+
+/// How the operands of an opcode are laid out, see `InstructionEncoding`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum OpMode {
+    IABC,
+    IABx,
+    IAsBx,
+    IAx,
+    IsJ,
+}
+
+impl Opcode {
+    pub fn mode(&self) -> OpMode {
+        match self {
+            Opcode::LoadK
+            | Opcode::LoadKx
+            | Opcode::ForLoop
+            | Opcode::ForPrep
+            | Opcode::TForPrep
+            | Opcode::TForLoop
+            | Opcode::Closure => OpMode::IABx,
+            Opcode::LoadI | Opcode::LoadF => OpMode::IAsBx,
+            Opcode::Extraarg => OpMode::IAx,
+            Opcode::Jmp => OpMode::IsJ,
+            _ => OpMode::IABC,
+        }
+    }
+
+    /// Whether the B operand is signed (sB)
+    pub fn has_signed_b(&self) -> bool {
+        matches!(
+            self,
+            Opcode::EqI | Opcode::LtI | Opcode::LeI | Opcode::GtI | Opcode::GeI | Opcode::MmBinI
+        )
+    }
+
+    /// Whether the C operand is signed (sC)
+    pub fn has_signed_c(&self) -> bool {
+        matches!(self, Opcode::AddI | Opcode::ShrI | Opcode::ShlI)
+    }
+
+    /// The name of the opcode, as in the `Debug` output of `Instruction`
+    pub fn mnemonic(&self) -> String {
+        format!("{:?}", self).trim_end_matches('_').to_string()
+    }
+
+    /// The opcode named `mnemonic`, ignoring case so that `LOADI` is accepted
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        (0..=Opcode::Extraarg as u8)
+            .filter_map(num::FromPrimitive::from_u8)
+            .find(|opcode: &Opcode| opcode.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+
+    /// The names of the operands encoded by the opcode, in encoding order
+    pub fn operand_names(&self) -> &'static [&'static str] {
+        match self.mode() {
+            OpMode::IABC => match (self.has_signed_b(), self.has_signed_c()) {
+                (true, _) => &["a", "sb", "c", "k"],
+                (_, true) => &["a", "b", "sc", "k"],
+                _ => &["a", "b", "c", "k"],
+            },
+            OpMode::IABx => &["a", "bx"],
+            OpMode::IAsBx => &["a", "sbx"],
+            OpMode::IAx => &["ax"],
+            OpMode::IsJ => &["sj"],
+        }
+    }
+}
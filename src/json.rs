@@ -0,0 +1,450 @@
+use std::fmt::Display;
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    binary_chunks::{
+        function_block::{FunctionBlockChunk, IsVarargFlag},
+        header::{HeaderChunk, HeaderVersion},
+    },
+    common_structs::{
+        constant::LuaConstant,
+        debug_info::{AbsLineInfo, DebugInfo, LocalVar},
+        upvalue::Upvalue,
+        variable_kind::VariableKind,
+    },
+    instruction_parsing::{instruction_encodings::InstructionEncoding, opcodes::Opcode},
+    lua_file::LuaFile,
+};
+
+/// Why a JSON document could not be imported, with the path of the value at
+/// fault, as in `main.protos[0].instructions[3].sbx`
+#[derive(Debug, PartialEq, Clone)]
+pub struct JsonError {
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// Exports `file` with every field the binary chunk holds. Instructions are
+/// decoded to their mnemonic and named operands, and instructions with an
+/// unknown opcode are kept as `{"raw": <u32>}`. Strings that are not UTF-8
+/// are kept as arrays of their bytes.
+pub fn export(file: &LuaFile) -> Value {
+    let header = &file.header;
+    json!({
+        "header": {
+            "version": header.version_number.to_string(),
+            "format_version": header.format_version,
+            "size_of_int": header.size_of_int,
+            "size_of_size_t": header.size_of_size_t,
+            "size_of_lua_number": header.size_of_lua_number,
+        },
+        "number_of_upvalues": file.number_of_upvalues,
+        "main": export_function(&file.main_function_block),
+    })
+}
+
+/// Pretty-printed `export`
+pub fn export_string(file: &LuaFile) -> String {
+    format!("{:#}\n", export(file))
+}
+
+fn export_function(function: &FunctionBlockChunk) -> Value {
+    let debug_info = &function.debug_info;
+    json!({
+        "source_name": export_string_bytes(function.source_name.as_deref()),
+        "source_line_start": function.source_line_start,
+        "source_line_end": function.source_line_end,
+        "number_of_parameters": function.number_of_parameters,
+        "is_vararg": function.is_vararg.clone().into_bytes()[0],
+        "maximum_stack_size": function.maximum_stack_size,
+        "instructions": function
+            .instructions
+            .iter()
+            .map(|instruction| export_instruction(*instruction))
+            .collect::<Vec<_>>(),
+        "constants": function.constants.iter().map(export_constant).collect::<Vec<_>>(),
+        "upvalues": function
+            .upvalues
+            .iter()
+            .map(|upvalue| json!({
+                "in_stack": upvalue.in_stack,
+                "index": upvalue.index,
+                "kind": format!("{:?}", upvalue.kind),
+            }))
+            .collect::<Vec<_>>(),
+        "protos": function.protos.iter().map(export_function).collect::<Vec<_>>(),
+        "debug_info": {
+            "line_info": debug_info.line_info,
+            "abs_line_info": debug_info
+                .abs_line_info
+                .iter()
+                .map(|abs_line_info| json!({"pc": abs_line_info.pc, "line": abs_line_info.line}))
+                .collect::<Vec<_>>(),
+            "local_vars": debug_info
+                .local_vars
+                .iter()
+                .map(|local_var| json!({
+                    "name": export_string_bytes(local_var.name.as_deref()),
+                    "start_pc": local_var.start_pc,
+                    "end_pc": local_var.end_pc,
+                }))
+                .collect::<Vec<_>>(),
+            "upvalue_names": debug_info
+                .upvalue_names
+                .iter()
+                .map(|name| export_string_bytes(name.as_deref()))
+                .collect::<Vec<_>>(),
+        },
+    })
+}
+
+fn export_instruction(instruction: u32) -> Value {
+    match InstructionEncoding::decode(instruction) {
+        Some((opcode, encoding)) => {
+            let mut object = Map::new();
+            object.insert("op".to_string(), json!(opcode.mnemonic()));
+            for (name, operand) in opcode.operand_names().iter().zip(encoding.operands(opcode)) {
+                object.insert(name.to_string(), json!(operand));
+            }
+            Value::Object(object)
+        }
+        None => json!({ "raw": instruction }),
+    }
+}
+
+fn export_constant(constant: &LuaConstant) -> Value {
+    match constant {
+        LuaConstant::Nil => json!({"type": "nil"}),
+        LuaConstant::Boolean(value) => json!({"type": "boolean", "value": value}),
+        LuaConstant::Integer(value) => json!({"type": "integer", "value": value}),
+        // JSON has no infinities or NaN
+        LuaConstant::Number(value) if value.is_nan() => json!({"type": "number", "value": "nan"}),
+        LuaConstant::Number(value) if value.is_infinite() => json!({
+            "type": "number",
+            "value": if *value > 0.0 { "inf" } else { "-inf" },
+        }),
+        LuaConstant::Number(value) => json!({"type": "number", "value": value}),
+        LuaConstant::String(value) => {
+            json!({"type": "string", "value": export_string_bytes(Some(value))})
+        }
+    }
+}
+
+/// A string as JSON text when it is UTF-8, as its bytes otherwise
+fn export_string_bytes(string: Option<&[u8]>) -> Value {
+    match string.map(std::str::from_utf8) {
+        None => Value::Null,
+        Some(Ok(text)) => json!(text),
+        Some(Err(_)) => json!(string),
+    }
+}
+
+/// Rebuilds the file exported by `export`
+pub fn import(value: &Value) -> Result<LuaFile, JsonError> {
+    let root = Node::root(value);
+    let header = root.field("header")?;
+    let version = header.field("version")?;
+    let version_number = version
+        .str()?
+        .split_once('.')
+        .and_then(|(major, minor)| Some((major.parse::<u8>().ok()?, minor.parse::<u8>().ok()?)))
+        .filter(|(major, minor)| *major < 16 && *minor < 16)
+        .map(|(major, minor)| HeaderVersion::from(major << 4 | minor))
+        .ok_or_else(|| version.error("expected a version such as \"5.4\""))?;
+    Ok(LuaFile {
+        header: HeaderChunk {
+            version_number,
+            format_version: header.field("format_version")?.u8()?,
+            size_of_int: header.field("size_of_int")?.u8()?,
+            size_of_size_t: header.field("size_of_size_t")?.u8()?,
+            size_of_lua_number: header.field("size_of_lua_number")?.u8()?,
+        },
+        number_of_upvalues: root.field("number_of_upvalues")?.u8()?,
+        main_function_block: import_function(&root.field("main")?)?,
+    })
+}
+
+/// Parses and imports a document written by `export_string`
+pub fn import_str(text: &str) -> Result<LuaFile, JsonError> {
+    let value = serde_json::from_str(text).map_err(|error| JsonError {
+        path: String::new(),
+        message: error.to_string(),
+    })?;
+    import(&value)
+}
+
+fn import_function(node: &Node) -> Result<FunctionBlockChunk, JsonError> {
+    let is_vararg = node.field("is_vararg")?;
+    let debug_info = node.field("debug_info")?;
+    Ok(FunctionBlockChunk {
+        source_name: node.field("source_name")?.optional_bytes()?,
+        source_line_start: node.field("source_line_start")?.u64()?,
+        source_line_end: node.field("source_line_end")?.u64()?,
+        number_of_parameters: node.field("number_of_parameters")?.u8()?,
+        is_vararg: IsVarargFlag::from_bytes([is_vararg.u8()?])
+            .map_err(|_| is_vararg.error("expected a vararg flag"))?,
+        maximum_stack_size: node.field("maximum_stack_size")?.u8()?,
+        instructions: node.field("instructions")?.list(import_instruction)?,
+        constants: node.field("constants")?.list(import_constant)?,
+        upvalues: node.field("upvalues")?.list(|upvalue| {
+            let kind = upvalue.field("kind")?;
+            Ok(Upvalue {
+                in_stack: upvalue.field("in_stack")?.bool()?,
+                index: upvalue.field("index")?.u8()?,
+                kind: VariableKind::from_name(kind.str()?)
+                    .ok_or_else(|| kind.error("expected a variable kind"))?,
+            })
+        })?,
+        protos: node.field("protos")?.list(import_function)?,
+        debug_info: DebugInfo {
+            line_info: debug_info.field("line_info")?.list(|delta| {
+                i8::try_from(delta.i64()?).map_err(|_| delta.error("expected a line delta"))
+            })?,
+            abs_line_info: debug_info.field("abs_line_info")?.list(|abs_line_info| {
+                Ok(AbsLineInfo {
+                    pc: abs_line_info.field("pc")?.u64()?,
+                    line: abs_line_info.field("line")?.u64()?,
+                })
+            })?,
+            local_vars: debug_info.field("local_vars")?.list(|local_var| {
+                Ok(LocalVar {
+                    name: local_var.field("name")?.optional_bytes()?,
+                    start_pc: local_var.field("start_pc")?.u64()?,
+                    end_pc: local_var.field("end_pc")?.u64()?,
+                })
+            })?,
+            upvalue_names: debug_info
+                .field("upvalue_names")?
+                .list(|name| name.optional_bytes())?,
+        },
+    })
+}
+
+fn import_instruction(node: &Node) -> Result<u32, JsonError> {
+    if let Ok(raw) = node.field("raw") {
+        return u32::try_from(raw.u64()?).map_err(|_| raw.error("expected a 32-bit instruction"));
+    }
+    let op = node.field("op")?;
+    let opcode = Opcode::from_mnemonic(op.str()?)
+        .ok_or_else(|| op.error(format!("unknown opcode {}", op.value)))?;
+    let operands = opcode
+        .operand_names()
+        .iter()
+        .map(|name| node.field(name)?.i64())
+        .collect::<Result<Vec<_>, _>>()?;
+    InstructionEncoding::from_operands(opcode, &operands)
+        .map(|encoding| encoding.encode())
+        .map_err(|message| node.error(message))
+}
+
+fn import_constant(node: &Node) -> Result<LuaConstant, JsonError> {
+    let type_ = node.field("type")?;
+    let value = || node.field("value");
+    match type_.str()? {
+        "nil" => Ok(LuaConstant::Nil),
+        "boolean" => Ok(LuaConstant::Boolean(value()?.bool()?)),
+        "integer" => Ok(LuaConstant::Integer(value()?.i64()?)),
+        "number" => {
+            let value = value()?;
+            match value.value.as_str() {
+                Some("nan") => Ok(LuaConstant::Number(f64::NAN)),
+                Some("inf") => Ok(LuaConstant::Number(f64::INFINITY)),
+                Some("-inf") => Ok(LuaConstant::Number(f64::NEG_INFINITY)),
+                _ => value
+                    .value
+                    .as_f64()
+                    .map(LuaConstant::Number)
+                    .ok_or_else(|| value.error("expected a number")),
+            }
+        }
+        "string" => Ok(LuaConstant::String(value()?.bytes()?)),
+        _ => Err(type_.error("expected nil, boolean, integer, number or string")),
+    }
+}
+
+/// A value of the imported document with its path
+struct Node<'a> {
+    value: &'a Value,
+    path: String,
+}
+
+impl<'a> Node<'a> {
+    fn root(value: &'a Value) -> Self {
+        Node {
+            value,
+            path: String::new(),
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> JsonError {
+        JsonError {
+            path: self.path.clone(),
+            message: message.into(),
+        }
+    }
+
+    fn field(&self, name: &str) -> Result<Node<'a>, JsonError> {
+        let path = if self.path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.path, name)
+        };
+        match self.value.get(name) {
+            Some(value) => Ok(Node { value, path }),
+            None => Err(JsonError {
+                path,
+                message: "missing".to_string(),
+            }),
+        }
+    }
+
+    fn list<T>(
+        &self,
+        item: impl Fn(&Node<'a>) -> Result<T, JsonError>,
+    ) -> Result<Vec<T>, JsonError> {
+        let values = self
+            .value
+            .as_array()
+            .ok_or_else(|| self.error("expected an array"))?;
+        values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                item(&Node {
+                    value,
+                    path: format!("{}[{}]", self.path, index),
+                })
+            })
+            .collect()
+    }
+
+    fn bool(&self) -> Result<bool, JsonError> {
+        self.value
+            .as_bool()
+            .ok_or_else(|| self.error("expected a boolean"))
+    }
+
+    fn i64(&self) -> Result<i64, JsonError> {
+        self.value
+            .as_i64()
+            .ok_or_else(|| self.error("expected an integer"))
+    }
+
+    fn u64(&self) -> Result<u64, JsonError> {
+        self.value
+            .as_u64()
+            .ok_or_else(|| self.error("expected an unsigned integer"))
+    }
+
+    fn u8(&self) -> Result<u8, JsonError> {
+        u8::try_from(self.u64()?).map_err(|_| self.error("expected a byte"))
+    }
+
+    fn str(&self) -> Result<&'a str, JsonError> {
+        self.value
+            .as_str()
+            .ok_or_else(|| self.error("expected a string"))
+    }
+
+    /// A string given as text or as an array of its bytes
+    fn bytes(&self) -> Result<Vec<u8>, JsonError> {
+        match self.value {
+            Value::String(string) => Ok(string.as_bytes().to_vec()),
+            Value::Array(_) => self.list(|byte| byte.u8()),
+            _ => Err(self.error("expected a string")),
+        }
+    }
+
+    fn optional_bytes(&self) -> Result<Option<Vec<u8>>, JsonError> {
+        if self.value.is_null() {
+            Ok(None)
+        } else {
+            self.bytes().map(Some)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::lua_file::LuaFile;
+
+    use super::{export, import, import_str, JsonError};
+
+    fn parse(chunk: &[u8]) -> LuaFile {
+        LuaFile::parse(chunk).unwrap().1
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunks: [&[u8]; 5] = [
+            include_bytes!("../tests/all_opcodes.luac"),
+            include_bytes!("../tests/binary_strings.luac"),
+            include_bytes!("../tests/closures.luac"),
+            include_bytes!("../tests/stripped.luac"),
+            include_bytes!("../tests/values.luac"),
+        ];
+        for chunk in chunks {
+            let file = parse(chunk);
+            let text = super::export_string(&file);
+            assert_eq!(import_str(&text), Ok(file));
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let file = parse(include_bytes!("../tests/closures.luac"));
+        let value = export(&file);
+        assert_eq!(value["header"]["version"], json!("5.4"));
+        assert_eq!(
+            value["main"]["instructions"][0],
+            json!({"op": "VarargPrep", "a": 0, "b": 0, "c": 0, "k": 0})
+        );
+        assert_eq!(
+            value["main"]["instructions"][1],
+            json!({"op": "LoadI", "a": 0, "sbx": 0})
+        );
+        assert_eq!(
+            value["main"]["upvalues"][0],
+            json!({"in_stack": true, "index": 0, "kind": "Regular"})
+        );
+        let file = parse(include_bytes!("../tests/binary_strings.luac"));
+        let constants = &export(&file)["main"]["constants"];
+        assert_eq!(
+            constants[0],
+            json!({"type": "string", "value": [0xff, 0xfe, 97, 100, 101, 100]})
+        );
+        assert_eq!(constants[3], json!({"type": "string", "value": "print"}));
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let file = parse(include_bytes!("../tests/closures.luac"));
+        let mut value = export(&file);
+        value["main"]["protos"][0]["instructions"][1] = json!({"op": "LoadI", "a": 0});
+        assert_eq!(
+            import(&value),
+            Err(JsonError {
+                path: "main.protos[0].instructions[1].sbx".to_string(),
+                message: "missing".to_string(),
+            })
+        );
+        value["main"]["protos"][0]["instructions"][1] = json!({"op": "Nop"});
+        assert_eq!(
+            import(&value).map_err(|error| error.to_string()),
+            Err("main.protos[0].instructions[1].op: unknown opcode \"Nop\"".to_string())
+        );
+        value["main"]["protos"][0]["instructions"][1] = json!({"op": "LOADI", "a": 0, "sbx": 1});
+        assert!(import(&value).is_ok());
+    }
+}
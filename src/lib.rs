@@ -9,3 +9,9 @@ pub mod lua_file;
 pub mod instruction_parsing;
 pub mod common_structs;
 pub mod disassembler;
+pub mod assembler;
+pub mod analysis;
+pub mod decompiler;
+pub mod batch;
+pub mod json;
+pub mod coverage;
\ No newline at end of file
@@ -1,6 +1,6 @@
-use nom::{IResult, sequence::tuple, combinator::map, number::complete::be_u8};
+use nom::{IResult, sequence::tuple, combinator::map, number::complete::be_u8, error::{Error, ErrorKind}, Err};
 
-use crate::binary_chunks::{header::HeaderChunk, function_block::FunctionBlockChunk};
+use crate::binary_chunks::{header::HeaderChunk, function_block::{FunctionBlockChunk, StripMode}, proto_path::ProtoPath};
 
 /// Compiled Lua File
 #[derive(Debug, PartialEq)]
@@ -26,4 +26,149 @@ impl LuaFile {
             }
         })(input)
     }
-}
\ No newline at end of file
+
+    /// The binary chunk of the file, as `luac` writes it
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = vec![];
+        self.header.write(&mut output);
+        output.push(self.number_of_upvalues);
+        self.main_function_block.write(&mut output);
+        output
+    }
+
+    /// Removes all debug information, like `luac -s`
+    pub fn strip(&mut self) {
+        self.strip_with(StripMode::All);
+    }
+
+    /// Removes the debug information of `mode` from every function block
+    pub fn strip_with(&mut self, mode: StripMode) {
+        self.main_function_block.strip(mode);
+    }
+
+    /// Lists every function block in the file, in pre-order
+    pub fn functions(&self) -> Vec<(ProtoPath, &FunctionBlockChunk)> {
+        self.main_function_block.functions(ProtoPath::main())
+    }
+
+    /// Finds a function block by its path
+    pub fn function_at(&self, path: &ProtoPath) -> Option<&FunctionBlockChunk> {
+        self.main_function_block.function_at(path)
+    }
+}
+
+/// Describes an error of `LuaFile::parse` on `input` with the offset it was found at
+pub fn describe_parse_error(input: &[u8], error: &Err<Error<&[u8]>>) -> String {
+    match error {
+        Err::Error(error) | Err::Failure(error) => {
+            let offset = input.len() - error.input.len();
+            if error.code == ErrorKind::Eof {
+                format!("truncated Lua 5.4 binary chunk: data missing at byte {}", offset)
+            } else {
+                format!(
+                    "not a Lua 5.4 binary chunk: {:?} at byte {}",
+                    error.code, offset
+                )
+            }
+        }
+        Err::Incomplete(_) => "truncated Lua 5.4 binary chunk".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{binary_chunks::function_block::StripMode, common_structs::constant::LuaConstant};
+
+    use super::LuaFile;
+
+    #[test]
+    fn test_to_bytes() {
+        let chunks: [&[u8]; 12] = [
+            include_bytes!("../tests/all_opcodes.luac"),
+            include_bytes!("../tests/audit.luac"),
+            include_bytes!("../tests/binary_strings.luac"),
+            include_bytes!("../tests/closures.luac"),
+            include_bytes!("../tests/control_flow.luac"),
+            include_bytes!("../tests/expressions.luac"),
+            include_bytes!("../tests/functions.luac"),
+            include_bytes!("../tests/globals.luac"),
+            include_bytes!("../tests/goto.luac"),
+            include_bytes!("../tests/stripped.luac"),
+            include_bytes!("../tests/tables.luac"),
+            include_bytes!("../tests/values.luac"),
+        ];
+        for chunk in chunks {
+            assert_eq!(LuaFile::parse(chunk).unwrap().1.to_bytes(), chunk);
+        }
+    }
+
+    #[test]
+    fn test_non_utf8_strings() {
+        let file = LuaFile::parse(include_bytes!("../tests/binary_strings.luac")).unwrap().1;
+        let constants = &file.main_function_block.constants;
+        assert_eq!(constants[0], LuaConstant::String(b"\xff\xfeaded".to_vec()));
+        assert_eq!(constants[1], LuaConstant::String(b"caf\xe9".to_vec()));
+        assert_eq!(constants[0].as_str(), None);
+        // A name patched to bytes that are not UTF-8 is written back as is
+        let mut file = LuaFile::parse(include_bytes!("../tests/globals.luac")).unwrap().1;
+        file.main_function_block.debug_info.local_vars[0].name = Some(b"\xc0nv".to_vec());
+        let chunk = file.to_bytes();
+        assert_eq!(LuaFile::parse(&chunk).unwrap().1, file);
+        assert!(chunk.windows(3).any(|window| window == b"\xc0nv"));
+    }
+
+    #[test]
+    fn test_strip() {
+        let sources = [
+            include_str!("../tests/all_opcodes.lua"),
+            include_str!("../tests/binary_strings.lua"),
+            include_str!("../tests/closures.lua"),
+            include_str!("../tests/goto.lua"),
+        ];
+        let lua = mlua::Lua::new();
+        for source in sources {
+            let function = lua.load(source).into_function().unwrap();
+            let mut file = LuaFile::parse(&function.dump(false)).unwrap().1;
+            file.strip();
+            assert_eq!(file.to_bytes(), function.dump(true));
+        }
+    }
+
+    #[test]
+    fn test_strip_keeps_constants() {
+        let constants = |file: &LuaFile| {
+            let mut output = vec![];
+            for constant in &file.main_function_block.constants {
+                constant.write(&mut output);
+            }
+            output
+        };
+        let chunk = include_bytes!("../tests/binary_strings.luac");
+        let mut file = LuaFile::parse(chunk).unwrap().1;
+        let original = constants(&file);
+        for mode in [StripMode::SourceNames, StripMode::Names, StripMode::All] {
+            file.strip_with(mode);
+            assert_eq!(constants(&file), original);
+            assert_eq!(constants(&LuaFile::parse(&file.to_bytes()).unwrap().1), original);
+        }
+    }
+
+    #[test]
+    fn test_strip_with() {
+        let mut file = LuaFile::parse(include_bytes!("../tests/closures.luac")).unwrap().1;
+        file.strip_with(StripMode::SourceNames);
+        assert_eq!(file.main_function_block.source_name, None);
+        assert!(!file.main_function_block.debug_info.local_vars.is_empty());
+        file.strip_with(StripMode::Names);
+        for (_, function) in file.functions() {
+            let debug_info = &function.debug_info;
+            assert!(debug_info.local_vars.is_empty() && debug_info.upvalue_names.is_empty());
+            assert_eq!(debug_info.line_info.len(), function.instructions.len());
+        }
+        // The lines still tell where errors are raised
+        let lua = unsafe { mlua::Lua::unsafe_new() };
+        lua.globals().set("print", mlua::Nil).unwrap();
+        let error = lua.load(file.to_bytes()).exec().unwrap_err();
+        assert!(error.to_string().contains("?:15:"), "{}", error);
+    }
+}
@@ -1,9 +1,521 @@
-use rusty_lua_dec::{lua_file::LuaFile, disassembler::Disassembler};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-fn main() {
-    let test = include_bytes!(r"..\tests\all_opcodes.luac");
-    let file = LuaFile::parse(test).unwrap().1;
-    let disasm = Disassembler::new(file);
-    println!("{}", disasm.display_header());
+use indoc::indoc;
+use rusty_lua_dec::{
+    analysis::{
+        audit::{AuditReport, Severity},
+        diff::FileDiff,
+        stats::FileStats,
+        strings::StringsReport,
+    },
+    assembler::assemble,
+    batch::{process_tree, BatchError, BatchMode},
+    binary_chunks::function_block::StripMode,
+    coverage::{instrument, DEFAULT_HOOK},
+    decompiler::{
+        printer::Printer,
+        verify::{verify, Luac},
+        Decompiler,
+    },
+    disassembler::Disassembler,
+    json,
+    lua_file::{describe_parse_error, LuaFile},
+};
 
+const USAGE: &str = indoc! {"
+    Usage: rusty_lua_dec <command> [options] [input] [second input]
+
+    Reads a Lua 5.4 binary chunk from `input`, or from stdin when it is
+    missing or `-`. When `input` is a directory, disasm and decompile process
+    every chunk under it in parallel, writing the results to the same paths
+    under the `--output` directory.
+
+    Commands:
+        info         Print the header of the chunk
+        disasm       List the instructions, constants, locals and upvalues
+        decompile    Print Lua source for the chunk
+        strings      List the strings of the chunk and where they are used
+        audit        List risky library calls, `_ENV` tampering and suspicious
+                     code
+        verify       Recompile the decompilation and compare it to the chunk
+        stats        Print the size and contents of every function block
+        assemble     Assemble the text of `input` into a binary chunk
+        json         Print the header and every function block as JSON
+        diff         Compare the functions of `input` to those of `second input`
+        strip        Write the chunk without its debug information, like luac -s
+        instrument   Write the chunk calling a hook at every basic block, for
+                     coverage, and the table of the probes to `--map`
+
+    Options:
+        -o, --output <file>    Write to `file` instead of stdout
+        --lines                decompile: keep statements on their original lines
+        --pcs                  decompile: end statements with their pc
+        --asm                  disasm: write source for the assemble command
+        --keep-lines           strip: keep the line information
+        --only-sources         strip: only remove the source file names
+        --map <file>           instrument: where to write the table of probes
+        --hook <name>          instrument: the global function probes call
+                               (default: __cov)
+        --luac <program>       verify: compile with `program` (default: luac)
+        --severity <level>     audit: lowest severity failing the audit, one of
+                               info, low, medium, high and critical
+                               (default: medium)
+        -h, --help             Print this help
+
+    Exit codes: 0 on success, 1 when verify or diff find differences or audit
+    finds something at least as severe as `--severity`, 2 on bad
+    usage, 3 when reading or writing fails, 4 when the input is not a Lua 5.4
+    chunk or valid assembly, 5 when the chunk cannot be decompiled or
+    recompiled. A directory exits with the code of its first failure.
+"};
+
+const EXIT_MISMATCH: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_IO: u8 = 3;
+const EXIT_PARSE: u8 = 4;
+const EXIT_DECOMPILE: u8 = 5;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Command {
+    Info,
+    Disasm,
+    Decompile,
+    Strings,
+    Audit,
+    Verify,
+    Stats,
+    Json,
+    Assemble,
+    Diff,
+    Strip,
+    Instrument,
+}
+
+impl Command {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "info" => Some(Command::Info),
+            "disasm" => Some(Command::Disasm),
+            "decompile" => Some(Command::Decompile),
+            "strings" => Some(Command::Strings),
+            "audit" => Some(Command::Audit),
+            "verify" => Some(Command::Verify),
+            "stats" => Some(Command::Stats),
+            "json" => Some(Command::Json),
+            "assemble" => Some(Command::Assemble),
+            "diff" => Some(Command::Diff),
+            "strip" => Some(Command::Strip),
+            "instrument" => Some(Command::Instrument),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    command: Command,
+    /// The chunk to read, stdin when missing
+    input: Option<PathBuf>,
+    /// The chunk `diff` compares to the first one
+    second_input: Option<PathBuf>,
+    /// Where to write, stdout when missing
+    output: Option<PathBuf>,
+    preserve_lines: bool,
+    pc_comments: bool,
+    assembly: bool,
+    strip_mode: StripMode,
+    /// Where `instrument` writes the table of probes
+    map: Option<PathBuf>,
+    hook: Option<String>,
+    luac: Option<PathBuf>,
+    /// The lowest severity of the findings failing `audit`
+    severity: Severity,
+}
+
+fn parse_severity(name: &str) -> Option<Severity> {
+    match name {
+        "info" => Some(Severity::Info),
+        "low" => Some(Severity::Low),
+        "medium" => Some(Severity::Medium),
+        "high" => Some(Severity::High),
+        "critical" => Some(Severity::Critical),
+        _ => None,
+    }
+}
+
+/// Why the tool stopped, with the exit code reporting it
+#[derive(Debug, PartialEq)]
+struct Failure {
+    code: u8,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: u8, message: impl Into<String>) -> Self {
+        Failure {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Parses the arguments after the program name. `None` asks for the help.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, Failure> {
+    let mut args = args.into_iter();
+    let mut command = None;
+    let mut input = None;
+    let mut second_input = None;
+    let mut output = None;
+    let mut preserve_lines = false;
+    let mut pc_comments = false;
+    let mut assembly = false;
+    let mut strip_mode = StripMode::All;
+    let mut map = None;
+    let mut hook = None;
+    let mut luac = None;
+    let mut severity = Severity::Medium;
+    let usage = |message: String| Failure::new(EXIT_USAGE, message);
+    while let Some(arg) = args.next() {
+        let mut value = |option: &str| {
+            args.next()
+                .map(PathBuf::from)
+                .ok_or_else(|| usage(format!("{} needs a value", option)))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(value(&arg)?),
+            "--lines" => preserve_lines = true,
+            "--pcs" => pc_comments = true,
+            "--asm" => assembly = true,
+            "--keep-lines" => strip_mode = StripMode::Names,
+            "--only-sources" => strip_mode = StripMode::SourceNames,
+            "--map" => map = Some(value(&arg)?),
+            "--hook" => hook = Some(value(&arg)?.to_string_lossy().into_owned()),
+            "--luac" => luac = Some(value(&arg)?),
+            "--severity" => {
+                let level = value(&arg)?.to_string_lossy().into_owned();
+                severity = parse_severity(&level)
+                    .ok_or_else(|| usage(format!("unknown severity {}", level)))?
+            }
+            "-" if command.is_some() && input.is_none() => input = Some(None),
+            option if option.starts_with('-') => {
+                return Err(usage(format!("unknown option {}", option)))
+            }
+            name if command.is_none() => {
+                command = Some(
+                    Command::parse(name)
+                        .ok_or_else(|| usage(format!("unknown command {}", name)))?,
+                )
+            }
+            path if input.is_none() => input = Some(Some(PathBuf::from(path))),
+            path if command == Some(Command::Diff) && second_input.is_none() => {
+                second_input = Some(PathBuf::from(path))
+            }
+            extra => return Err(usage(format!("unexpected argument {}", extra))),
+        }
+    }
+    let command = command.ok_or_else(|| usage("missing command".to_string()))?;
+    if command == Command::Diff && second_input.is_none() {
+        return Err(usage("diff needs two chunks".to_string()));
+    }
+    if command == Command::Instrument && map.is_none() {
+        return Err(usage("instrument needs --map".to_string()));
+    }
+    Ok(Some(Options {
+        command,
+        input: input.flatten(),
+        second_input,
+        output,
+        preserve_lines,
+        pc_comments,
+        assembly,
+        strip_mode,
+        map,
+        hook,
+        luac,
+        severity,
+    }))
+}
+
+fn read_input(input: &Option<PathBuf>) -> Result<Vec<u8>, Failure> {
+    match input {
+        Some(path) => fs::read(path)
+            .map_err(|error| Failure::new(EXIT_IO, format!("{}: {}", path.display(), error))),
+        None => {
+            let mut data = vec![];
+            io::stdin()
+                .read_to_end(&mut data)
+                .map_err(|error| Failure::new(EXIT_IO, format!("stdin: {}", error)))?;
+            Ok(data)
+        }
+    }
+}
+
+fn write_output(output: &Option<PathBuf>, data: impl AsRef<[u8]>) -> Result<(), Failure> {
+    match output {
+        Some(path) => fs::write(path, data)
+            .map_err(|error| Failure::new(EXIT_IO, format!("{}: {}", path.display(), error))),
+        None => io::stdout()
+            .write_all(data.as_ref())
+            .map_err(|error| Failure::new(EXIT_IO, format!("stdout: {}", error))),
+    }
+}
+
+/// Processes the chunks of the directory `input` into `--output`
+fn run_batch(options: &Options, input: &Path) -> Result<(), Failure> {
+    let mode = match options.command {
+        Command::Disasm => BatchMode::Disassemble,
+        Command::Decompile => BatchMode::Decompile,
+        _ => {
+            return Err(Failure::new(
+                EXIT_USAGE,
+                "only disasm and decompile take a directory",
+            ))
+        }
+    };
+    let output = options
+        .output
+        .as_ref()
+        .ok_or_else(|| Failure::new(EXIT_USAGE, "a directory needs an --output directory"))?;
+    let report = process_tree(input, output, mode)
+        .map_err(|error| Failure::new(EXIT_IO, format!("{}: {}", input.display(), error)))?;
+    print!("{}", report);
+    match report.failures.first() {
+        None => Ok(()),
+        Some((_, error)) => {
+            let code = match error {
+                BatchError::Io(_) => EXIT_IO,
+                BatchError::Parse(_) => EXIT_PARSE,
+                BatchError::Decompile(_) => EXIT_DECOMPILE,
+            };
+            Err(Failure::new(
+                code,
+                format!(
+                    "{} of {} chunks failed",
+                    report.failures.len(),
+                    report.failures.len() + report.written.len()
+                ),
+            ))
+        }
+    }
+}
+
+fn parse_chunk(data: &[u8]) -> Result<LuaFile, Failure> {
+    LuaFile::parse(data)
+        .map(|(_, file)| file)
+        .map_err(|error| Failure::new(EXIT_PARSE, describe_parse_error(data, &error)))
+}
+
+fn run(options: &Options) -> Result<(), Failure> {
+    if let Some(input) = options.input.as_ref().filter(|input| input.is_dir()) {
+        return run_batch(options, input);
+    }
+    let data = read_input(&options.input)?;
+    if options.command == Command::Assemble {
+        let source = String::from_utf8(data)
+            .map_err(|_| Failure::new(EXIT_PARSE, "the assembly is not UTF-8"))?;
+        let file =
+            assemble(&source).map_err(|error| Failure::new(EXIT_PARSE, error.to_string()))?;
+        return write_output(&options.output, file.to_bytes());
+    }
+    let mut file = parse_chunk(&data)?;
+    let decompile_failure =
+        |error: &dyn std::fmt::Display| Failure::new(EXIT_DECOMPILE, error.to_string());
+    match options.command {
+        Command::Info => write_output(
+            &options.output,
+            format!("{}\n", Disassembler::new(file).display_header()),
+        ),
+        Command::Disasm if options.assembly => {
+            write_output(&options.output, Disassembler::new(file).assembly())
+        }
+        Command::Disasm => write_output(&options.output, Disassembler::new(file).disassemble()),
+        Command::Decompile => {
+            let block = Decompiler::new(&file)
+                .decompile()
+                .map_err(|error| decompile_failure(&error))?;
+            let mut printer = Printer::new();
+            if options.preserve_lines {
+                printer = printer.preserving_lines();
+            }
+            if options.pc_comments {
+                printer = printer.with_pc_comments();
+            }
+            write_output(&options.output, printer.print_block(&block))
+        }
+        Command::Strings => write_output(&options.output, StringsReport::build(&file).to_string()),
+        Command::Audit => {
+            let report = AuditReport::build(&file);
+            write_output(&options.output, report.to_string())?;
+            match report.max_severity() {
+                Some(severity) if severity >= options.severity => Err(Failure::new(
+                    EXIT_MISMATCH,
+                    format!("the audit found a {} finding", severity),
+                )),
+                _ => Ok(()),
+            }
+        }
+        Command::Stats => write_output(&options.output, FileStats::build(&file).to_string()),
+        Command::Json => write_output(&options.output, json::export_string(&file)),
+        Command::Assemble => unreachable!("assembled before parsing"),
+        Command::Strip => {
+            file.strip_with(options.strip_mode);
+            write_output(&options.output, file.to_bytes())
+        }
+        Command::Instrument => {
+            let map = instrument(&mut file, options.hook.as_deref().unwrap_or(DEFAULT_HOOK));
+            write_output(&options.map, map.to_string())?;
+            write_output(&options.output, file.to_bytes())
+        }
+        Command::Diff => {
+            let second_file = parse_chunk(&read_input(&options.second_input)?)?;
+            let diff = FileDiff::build(&file, &second_file);
+            write_output(&options.output, diff.to_string())?;
+            if diff.is_empty() {
+                Ok(())
+            } else {
+                Err(Failure::new(EXIT_MISMATCH, "the chunks differ"))
+            }
+        }
+        Command::Verify => {
+            let compiler = options.luac.as_ref().map_or_else(Luac::default, Luac::new);
+            let mismatches = verify(&file, &compiler).map_err(|error| decompile_failure(&error))?;
+            let report: String = mismatches
+                .iter()
+                .map(|mismatch| format!("{}\n", mismatch))
+                .collect();
+            write_output(&options.output, &report)?;
+            if mismatches.is_empty() {
+                Ok(())
+            } else {
+                Err(Failure::new(
+                    EXIT_MISMATCH,
+                    "the recompiled decompilation differs",
+                ))
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(failure) => {
+            eprintln!("error: {}\n\n{}", failure.message, USAGE);
+            return ExitCode::from(failure.code);
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("error: {}", failure.message);
+            ExitCode::from(failure.code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rusty_lua_dec::{analysis::audit::Severity, binary_chunks::function_block::StripMode};
+
+    use super::{parse_args, run, Command, Failure, Options, EXIT_MISMATCH, EXIT_USAGE};
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, Failure> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&["decompile", "--lines", "a.luac", "-o", "a.lua"]),
+            Ok(Some(Options {
+                command: Command::Decompile,
+                input: Some(PathBuf::from("a.luac")),
+                second_input: None,
+                output: Some(PathBuf::from("a.lua")),
+                preserve_lines: true,
+                pc_comments: false,
+                assembly: false,
+                strip_mode: StripMode::All,
+                map: None,
+                hook: None,
+                luac: None,
+                severity: Severity::Medium,
+            }))
+        );
+        assert_eq!(
+            parse(&["verify", "-", "--luac", "luac5.4"]).map(|options| options.unwrap().luac),
+            Ok(Some(PathBuf::from("luac5.4")))
+        );
+        assert_eq!(parse(&["stats", "--help"]), Ok(None));
+        assert_eq!(
+            parse(&["strip", "--keep-lines"]).map(|options| options.unwrap().strip_mode),
+            Ok(StripMode::Names)
+        );
+        assert_eq!(
+            parse(&["dump"]).map_err(|failure| failure.code),
+            Err(EXIT_USAGE)
+        );
+        assert_eq!(
+            parse(&["info", "a.luac", "b.luac"]).map_err(|failure| failure.message),
+            Err("unexpected argument b.luac".to_string())
+        );
+        assert_eq!(
+            parse(&["diff", "-", "b.luac"]).map(|options| options.unwrap().second_input),
+            Ok(Some(PathBuf::from("b.luac")))
+        );
+        assert_eq!(
+            parse(&["diff", "a.luac"]).map_err(|failure| failure.message),
+            Err("diff needs two chunks".to_string())
+        );
+        assert_eq!(
+            parse(&["instrument", "a.luac", "--hook", "hit"]).map_err(|failure| failure.message),
+            Err("instrument needs --map".to_string())
+        );
+        assert_eq!(
+            parse(&["disasm", "-o"]).map_err(|failure| failure.message),
+            Err("-o needs a value".to_string())
+        );
+        assert_eq!(
+            parse(&["audit", "a.luac", "--severity", "high"])
+                .map(|options| options.unwrap().severity),
+            Ok(Severity::High)
+        );
+        assert_eq!(
+            parse(&["audit", "--severity", "severe"]).map_err(|failure| failure.message),
+            Err("unknown severity severe".to_string())
+        );
+    }
+
+    #[test]
+    fn test_audit_exit_code() {
+        let output =
+            std::env::temp_dir().join(format!("rusty_lua_dec_audit_{}", std::process::id()));
+        let audit = |input: &str, severity: &str| {
+            let mut options = parse(&["audit", input, "--severity", severity])
+                .unwrap()
+                .unwrap();
+            options.output = Some(output.clone());
+            run(&options).map_err(|failure| failure.code)
+        };
+        // One finding, of medium severity
+        assert_eq!(audit("tests/functions.luac", "medium"), Err(EXIT_MISMATCH));
+        let report = std::fs::read_to_string(&output).unwrap();
+        assert!(report.starts_with("[MEDIUM] "), "{}", report);
+        assert_eq!(audit("tests/functions.luac", "high"), Ok(()));
+        assert_eq!(audit("tests/values.luac", "info"), Ok(()));
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "");
+        std::fs::remove_file(&output).unwrap();
+    }
 }
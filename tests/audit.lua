@@ -0,0 +1,12 @@
+---- Risky patterns for the security audit ----
+local run = os.execute
+local dump = string.dump
+setmetatable(_G, { __index = rawget })
+local info = debug.getinfo
+collectgarbage("collect")
+run("ls")
+function plugin()
+	local pipe = io.popen("ls")
+	local chunk = load(pipe:read("a"))
+	_ENV = { chunk = chunk }
+end
@@ -0,0 +1,5 @@
+---- Strings that are not UTF-8 ----
+local magic = "\xff\xfeaded"
+local latin1 = "caf\xe9"
+local long = "\x80\x81 a string too long to be a short string \xc0\xff"
+print(#magic, latin1:byte(4), long:sub(-2) == "\xc0\xff")
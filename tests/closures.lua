@@ -0,0 +1,24 @@
+---- Closures, upvalues and calls between functions ----
+local counter = 0
+
+local function increment(step)
+	counter = counter + step
+	return counter
+end
+
+local function twice(step)
+	increment(step)
+	return increment(step)
+end
+
+function report()
+	print(twice(2))
+end
+
+local helpers = {}
+function helpers.reset()
+	counter = 0
+end
+
+report()
+helpers.reset()
@@ -0,0 +1,51 @@
+local n = tonumber(...)
+local total = 0
+for i = 1, n do
+    if i % 2 == 0 then
+        total = total + i
+    elseif i % 3 == 0 then
+        total = total - 1
+    else
+        print(i)
+    end
+end
+for i = n, 1, -1 do
+    print(i)
+end
+local t = {}
+for k, v in pairs(t) do
+    print(k, v)
+    if v == nil then
+        break
+    end
+end
+local j = 0
+while j < n and total > 0 do
+    j = j + 1
+    if j > 5 then
+        break
+    end
+end
+repeat
+    local done = check(j, n)
+    j = j + 1
+until done or j > 100
+while true do
+    if f(j) then
+        break
+    end
+    j = j - 1
+end
+if n > 1 then
+    print("big")
+end
+if n == 1 or n == 2 then
+    print("small")
+else
+    print("other")
+end
+do
+    local n = 5
+    print(n)
+end
+print(n)
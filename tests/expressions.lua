@@ -0,0 +1,19 @@
+local x = 10
+print(("a"):rep(3) .. x)
+local s = string.format("%d-%d", x * 2, -x)
+local t = {}
+t.name = s
+t[1] = x // 3
+local a, b = x, s
+a, b = b, a
+print(select("#", ...))
+local biggest = math.max(x, 1, 2.5)
+local y = x - 1
+local z = 1 + x
+local w = 2 ^ x << 1
+obj = {}
+obj.count = #t .. "items"
+g1, g2 = math.huge, 0
+local m, n = string.find(s, "-")
+print(m, n, ...)
+return x, s
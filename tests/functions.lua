@@ -0,0 +1,34 @@
+local count = 0
+local function fact(n)
+    if n <= 1 then
+        return 1
+    end
+    return n * fact(n - 1)
+end
+local function counter()
+    count = count + 1
+    return function()
+        return count
+    end
+end
+local function add(a, b)
+    return a + b
+end
+local tostring = function(value)
+    return "<" .. tostring(value) .. ">"
+end
+function greet(name, ...)
+    print("hello", name, ...)
+end
+local t = {a = {b = {}}}
+function t.a.b.run(x)
+    return x
+end
+function t.a.b:method(y)
+    return self, y
+end
+do
+    local file <close> = io.open("f")
+    print(file, fact(5), counter()(), add(1, 2), tostring(t))
+end
+return t
@@ -0,0 +1,11 @@
+---- Global accesses, directly and through registers holding _ENV ----
+local env = _ENV
+print(os.time())
+env.answer = 42
+local key = "loaded"
+local flag = env[key]
+debug = nil
+local function sandbox()
+	local _ENV = { print = print }
+	print(load)
+end
@@ -0,0 +1,45 @@
+---- Gotos and labels, with the closes they need ----
+local n = 0
+::retry::
+local step = 1
+n = n + step
+if n < 3 then
+	goto retry
+end
+for i = 1, 3 do
+	if i == 2 then
+		goto continue
+	end
+	print(i)
+	::continue::
+end
+for i = 1, 3 do
+	for j = 1, 3 do
+		if i * j == 4 then
+			goto done
+		end
+	end
+end
+::done::
+for i = 1, 3 do
+	for j = 1, 3 do
+		if j > i then
+			goto continue
+		end
+	end
+	print(i)
+	::continue::
+end
+do
+	local k = 0
+	::again::
+	local v = k
+	callbacks[k] = function()
+		return v
+	end
+	k = k + 1
+	if k < 2 then
+		goto again
+	end
+end
+print(n)
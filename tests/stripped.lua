@@ -0,0 +1,34 @@
+local l_0_0 = 0
+local function l_0_1(arg1)
+    if arg1 <= 1 then
+        return 1
+    end
+    return arg1 * l_0_1(arg1 - 1)
+end
+local function l_0_2()
+    l_0_0 = l_0_0 + 1
+    return function()
+        return l_0_0
+    end
+end
+local function l_0_3(arg1, arg2)
+    return arg1 + arg2
+end
+local function l_0_4(arg1)
+    return "<" .. tostring(arg1) .. ">"
+end
+function greet(arg1, ...)
+    print("hello", arg1, ...)
+end
+local l_0_5 = {a = {b = {}}}
+function l_0_5.a.b.run(arg1)
+    return arg1
+end
+function l_0_5.a.b.method(arg1, arg2)
+    return arg1, arg2
+end
+do
+    local l_0_6 <close> = io.open("f")
+    print(l_0_6, l_0_1(5), l_0_2()(), l_0_3(1, 2), l_0_4(l_0_5))
+end
+return l_0_5
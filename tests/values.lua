@@ -0,0 +1,15 @@
+local a, b, c = ...
+local k = 1.2
+local f = not k or true
+local x1 = a and b
+local x2 = a or b
+local x3 = tonumber(c) or 10
+local x4 = a < b
+local x5 = (a or b) and c
+local x6 = a and b or c
+local x7 = a <= b or c
+local x8 = a == 1 and b or c
+print(a and b, x1 ~= x2)
+config = a.settings or {}
+x1 = x1 or x2
+return x3 > 0 and x4